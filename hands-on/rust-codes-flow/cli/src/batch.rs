@@ -0,0 +1,509 @@
+//! Batch mode: many repositories from a manifest, one shared dataset
+//!
+//! Repositories are processed one after another through a single
+//! `DatasetWriter`, so deduplication spans the whole batch. Every item goes
+//! to the combined dataset and to the dataset of its repository. A
+//! repository that cannot be checked out is recorded in the report and the
+//! batch moves on.
+
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// One repository of a batch manifest
+///
+/// Options left out fall back to the values given on the command line.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ManifestEntry {
+    /// GitHub URL or local path
+    pub source: String,
+    /// Name of the repository's dataset (default: the repository name)
+    pub name: Option<String>,
+    /// Commit, tag or branch to check out
+    pub rev: Option<String>,
+    /// SPDX expression of the repository, replacing the one detected from
+    /// its license files
+    pub license: Option<String>,
+    /// Only emit items changed since this commit
+    pub diff_base: Option<String>,
+    pub keep_docs: Option<bool>,
+    pub full_context: Option<bool>,
+    /// Replaces the `--include` globs
+    pub include: Option<Vec<String>>,
+    /// Replaces the `--exclude` globs
+    pub exclude: Option<Vec<String>>,
+    pub no_default_excludes: Option<bool>,
+    pub skip_generated: Option<bool>,
+    pub no_scrub: Option<bool>,
+}
+
+impl ManifestEntry {
+    fn job(&self, defaults: &ExtractArgs) -> RepoJob {
+        let mut job = RepoJob::new(
+            self.source.clone(),
+            self.rev.clone(),
+            self.diff_base.clone(),
+            defaults,
+        );
+        job.license = self.license.clone();
+        if let Some(keep_docs) = self.keep_docs {
+            job.config.keep_docs = keep_docs;
+        }
+        if let Some(full_context) = self.full_context {
+            job.config.include_context = full_context;
+        }
+        if let Some(include) = &self.include {
+            job.discovery.include = include.clone();
+        }
+        if let Some(exclude) = &self.exclude {
+            job.discovery.exclude = exclude.clone();
+        }
+        if let Some(no_default_excludes) = self.no_default_excludes {
+            job.discovery.default_excludes = !no_default_excludes;
+        }
+        if let Some(skip_generated) = self.skip_generated {
+            job.discovery.skip_generated = skip_generated;
+        }
+        if let Some(no_scrub) = self.no_scrub {
+            job.config.scrub = !no_scrub;
+        }
+        job
+    }
+}
+
+/// `[[repo]]` tables of a TOML manifest
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlManifest {
+    #[serde(default)]
+    repo: Vec<ManifestEntry>,
+}
+
+/// Read a manifest: `[[repo]]` tables in `.toml` files, one JSON object per
+/// line otherwise
+pub(crate) fn read_manifest(path: &Path) -> anyhow::Result<Vec<ManifestEntry>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read manifest {}: {}", path.display(), e))?;
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "toml")
+    {
+        let manifest: TomlManifest = toml::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Invalid manifest {}: {}", path.display(), e))?;
+        return Ok(manifest.repo);
+    }
+
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid manifest {} line {}: {}",
+                    path.display(),
+                    index + 1,
+                    e
+                )
+            })
+        })
+        .collect()
+}
+
+/// Outcome of one repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RepoStatus {
+    Ok,
+    Failed,
+}
+
+/// Report entry of one repository
+#[derive(Debug, Serialize)]
+pub(crate) struct RepoReport {
+    pub name: String,
+    pub source: String,
+    pub rev: Option<String>,
+    /// Declared or detected repository license
+    pub license: Option<String>,
+    pub status: RepoStatus,
+    pub error: Option<String>,
+    pub commit_hash: Option<String>,
+    /// Files extracted (or changed files with `diff_base`)
+    pub files: usize,
+    /// Items written after deduplication
+    pub items: usize,
+    pub output: Option<PathBuf>,
+}
+
+/// Summary of a batch, written to `report.json` in the output directory
+#[derive(Debug, Serialize)]
+pub(crate) struct BatchReport {
+    pub succeeded: usize,
+    pub failed: usize,
+    pub total_items: usize,
+    pub duplicates: usize,
+    pub near_duplicates: usize,
+    pub combined_output: PathBuf,
+    pub repos: Vec<RepoReport>,
+}
+
+impl BatchReport {
+    fn print(&self) {
+        println!(
+            "\n📦 Batch: {} succeeded, {} failed",
+            self.succeeded, self.failed
+        );
+        for repo in &self.repos {
+            match repo.status {
+                RepoStatus::Ok => println!(
+                    "  ✅ {}: {} items from {} files",
+                    repo.name, repo.items, repo.files
+                ),
+                RepoStatus::Failed => println!(
+                    "  ❌ {}: {}",
+                    repo.name,
+                    repo.error.as_deref().unwrap_or_default()
+                ),
+            }
+        }
+    }
+}
+
+/// Sink writing every item to the combined dataset and to the dataset of
+/// the current repository
+struct BatchSink {
+    combined: Box<dyn ItemSink>,
+    repo: Option<Box<dyn ItemSink>>,
+    repo_items: usize,
+}
+
+impl BatchSink {
+    fn new(combined: Box<dyn ItemSink>) -> Self {
+        Self {
+            combined,
+            repo: None,
+            repo_items: 0,
+        }
+    }
+
+    /// Send the following items to `sink` as well
+    fn start_repo(&mut self, sink: Box<dyn ItemSink>) -> CoreResult<()> {
+        self.finish_repo()?;
+        self.repo = Some(sink);
+        Ok(())
+    }
+
+    /// Finish the current repository's dataset and return its item count
+    fn finish_repo(&mut self) -> CoreResult<usize> {
+        if let Some(mut sink) = self.repo.take() {
+            sink.finish()?;
+        }
+        Ok(std::mem::take(&mut self.repo_items))
+    }
+}
+
+impl ItemSink for BatchSink {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        self.combined.write_item(item)?;
+        if let Some(sink) = self.repo.as_mut() {
+            self.repo_items += 1;
+            sink.write_item(item)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        self.finish_repo()?;
+        self.combined.finish()
+    }
+}
+
+/// Run a batch and write its report
+pub(crate) fn run_batch(args: &BatchArgs) -> anyhow::Result<BatchReport> {
+    let entries = read_manifest(&args.manifest)?;
+    let options = args.output_args.options();
+    let extension = options.format.extension();
+
+    println!("🚀 RustCodeFlow: Converting Rust repositories to AI datasets");
+    println!(
+        "📋 Batch: {} repositories from {}",
+        entries.len(),
+        args.manifest.display()
+    );
+    println!(
+        "📄 Output: {} ({}, compression={}, dedup={})",
+        args.output_dir.display(),
+        options.format,
+        options.compression,
+        DedupMode::from(args.dedup.dedup)
+    );
+
+    fs::create_dir_all(&args.output_dir)?;
+    let combined_output = args.output_dir.join(format!("combined.{extension}"));
+    let mut output = DatasetWriter::new(
+        BatchSink::new(create_sink(&combined_output, &options)?),
+        &args.dedup,
+        &args.filter,
+        &args.graph,
+    )?;
+
+    let mut names: HashSet<String> = ["combined", "report"].map(String::from).into();
+    let mut repos: Vec<RepoReport> = Vec::new();
+    // Spooled item counts per repository, in spool order
+    let mut spooled: Vec<(usize, usize)> = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        let name = match &entry.name {
+            Some(name) => name.clone(),
+            None => extract_repo_name(&entry.source)?,
+        };
+        let name = unique_name(&name, &mut names);
+        println!(
+            "\n📦 [{}/{}] {} ({})",
+            index + 1,
+            entries.len(),
+            name,
+            entry.source
+        );
+
+        let job = entry.job(&args.extract);
+        let mut report = RepoReport {
+            name,
+            source: entry.source.clone(),
+            rev: entry.rev.clone(),
+            license: entry.license.clone(),
+            status: RepoStatus::Ok,
+            error: None,
+            commit_hash: None,
+            files: 0,
+            items: 0,
+            output: None,
+        };
+
+        match checkout(&job, args.runtime.cache_dir.as_deref()) {
+            Ok(checkout) => {
+                let path = args.output_dir.join(format!("{}.{extension}", report.name));
+                report.commit_hash = Some(checkout.commit_hash.clone());
+                report.license = checkout.license.clone();
+                report.files = checkout.plan.len();
+
+                // Errors past the checkout come from the outputs and end the batch
+                if output.spooling() {
+                    let before = output.spooled();
+                    extract(&job, checkout, &mut output)?;
+                    spooled.push((repos.len(), output.spooled() - before));
+                } else {
+                    output.sink.start_repo(create_sink(&path, &options)?)?;
+                    extract(&job, checkout, &mut output)?;
+                    output.flush_graph()?;
+                    report.items = output.sink.finish_repo()?;
+                }
+                report.output = Some(path);
+            }
+            Err(e) => {
+                println!("❌ Skipping {}: {:#}", report.name, e);
+                report.status = RepoStatus::Failed;
+                report.error = Some(format!("{e:#}"));
+            }
+        }
+
+        if args.runtime.cleanup()
+            && let Err(e) = cleanup(&job)
+        {
+            println!("Warning: {}", e);
+        }
+        repos.push(report);
+    }
+
+    if let Some(spool) = output.take_spool() {
+        println!(
+            "\n🧹 Keeping the shortest path of {} spooled items...",
+            spool.len()
+        );
+        let mut items = spool.into_reader()?;
+        for (index, count) in spooled {
+            let report = &mut repos[index];
+            let path = report
+                .output
+                .as_ref()
+                .expect("spooled repositories have an output");
+            output.sink.start_repo(create_sink(path, &options)?)?;
+            for item in items.by_ref().take(count) {
+                output.write(item?)?;
+            }
+            output.flush_graph()?;
+            report.items = output.sink.finish_repo()?;
+        }
+    }
+    output.finish()?;
+    output.print_summary();
+
+    let failed = repos
+        .iter()
+        .filter(|repo| repo.status == RepoStatus::Failed)
+        .count();
+    let report = BatchReport {
+        succeeded: repos.len() - failed,
+        failed,
+        total_items: output.summary.total_items,
+        duplicates: output.dedup.duplicates(),
+        near_duplicates: output.near_duplicates,
+        combined_output,
+        repos,
+    };
+    let report_path = args.output_dir.join("report.json");
+    serde_json::to_writer_pretty(fs::File::create(&report_path)?, &report)?;
+    report.print();
+
+    println!("\n✨ Batch complete!");
+    println!("📁 Combined output: {}", report.combined_output.display());
+    println!("📋 Report: {}", report_path.display());
+
+    Ok(report)
+}
+
+/// `name` made safe for a file name and distinct from the names in `used`
+fn unique_name(name: &str, used: &mut HashSet<String>) -> String {
+    let base: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut name = base.clone();
+    let mut suffix = 2;
+    while !used.insert(name.clone()) {
+        name = format!("{base}-{suffix}");
+        suffix += 1;
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{commit, git};
+
+    #[test]
+    fn test_toml_manifest() {
+        let temp = tempfile::tempdir().unwrap();
+        let manifest = temp.path().join("repos.toml");
+        fs::write(
+            &manifest,
+            r#"
+[[repo]]
+source = "https://github.com/serde-rs/serde"
+rev = "v1.0.200"
+license = "MIT OR Apache-2.0"
+exclude = ["**/tests/**"]
+
+[[repo]]
+source = "../local"
+skip_generated = true
+"#,
+        )
+        .unwrap();
+
+        let entries = read_manifest(&manifest).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].rev.as_deref(), Some("v1.0.200"));
+        assert_eq!(entries[0].license.as_deref(), Some("MIT OR Apache-2.0"));
+
+        let defaults = ExtractArgs {
+            keep_docs: true,
+            full_context: false,
+            include: vec!["src/**".to_string()],
+            exclude: Vec::new(),
+            no_default_excludes: false,
+            skip_generated: false,
+            no_scrub: false,
+            fim: false,
+            fim_order: FimOrderArg::Psm,
+            fim_seed: 0,
+            fim_spans: Vec::new(),
+            fim_prefix_token: String::new(),
+            fim_suffix_token: String::new(),
+            fim_middle_token: String::new(),
+        };
+        let job = entries[0].job(&defaults);
+        assert!(job.config.keep_docs);
+        assert_eq!(job.discovery.include, ["src/**"]);
+        assert_eq!(job.discovery.exclude, ["**/tests/**"]);
+        assert!(entries[1].job(&defaults).discovery.skip_generated);
+
+        fs::write(&manifest, "[[repo]]\nsource = \"x\"\nbranch = \"main\"\n").unwrap();
+        assert!(read_manifest(&manifest).is_err());
+    }
+
+    #[test]
+    fn test_batch_shares_dedup_and_skips_failed_repos() {
+        let temp = tempfile::tempdir().unwrap();
+        for (name, source) in [
+            ("a", "fn shared(x: u32) -> u32 { x + 1 }\nfn only_a() {}\n"),
+            (
+                "bb",
+                "fn shared(y: u32) -> u32 { y + 1 }\nfn only_b() -> bool { true }\n",
+            ),
+        ] {
+            let repo = temp.path().join(name);
+            fs::create_dir(&repo).unwrap();
+            git(&repo, &["init", "--quiet"]);
+            commit(&repo, source);
+        }
+
+        let manifest = temp.path().join("repos.jsonl");
+        let source = |name: &str| temp.path().join(name).to_string_lossy().to_string();
+        fs::write(
+            &manifest,
+            format!(
+                "{}\n\n{}\n{}\n",
+                serde_json::json!({ "source": source("a"), "license": "MIT" }),
+                serde_json::json!({ "source": source("missing") }),
+                serde_json::json!({ "source": source("bb") }),
+            ),
+        )
+        .unwrap();
+
+        for dedup in ["keep-first", "keep-shortest-path"] {
+            let output_dir = temp.path().join(dedup);
+            let cli = Cli::try_parse_from([
+                "rustcodesflow",
+                "batch",
+                manifest.to_str().unwrap(),
+                "--output-dir",
+                output_dir.to_str().unwrap(),
+                "--dedup",
+                dedup,
+            ])
+            .unwrap();
+            let Some(Command::Batch(args)) = cli.command else {
+                panic!("expected the batch subcommand");
+            };
+
+            let report = run_batch(&args).unwrap();
+            assert_eq!((report.succeeded, report.failed), (2, 1));
+            assert_eq!(report.repos[1].status, RepoStatus::Failed);
+            assert_eq!(report.repos[0].license.as_deref(), Some("MIT"));
+
+            // `shared` of the second repository duplicates the first one's
+            let items: Vec<usize> = report.repos.iter().map(|repo| repo.items).collect();
+            assert_eq!(items, [2, 0, 1], "{dedup}");
+            assert_eq!(report.duplicates, 1);
+            assert_eq!(report.total_items, 3);
+
+            let count = |path: &Path| JsonlReader::open(path).unwrap().count();
+            assert_eq!(count(&output_dir.join("combined.jsonl")), 3);
+            assert_eq!(count(&output_dir.join("a.jsonl")), 2);
+            assert_eq!(count(&output_dir.join("bb.jsonl")), 1);
+            assert!(!output_dir.join("missing.jsonl").exists());
+            assert!(output_dir.join("report.json").exists());
+        }
+    }
+}
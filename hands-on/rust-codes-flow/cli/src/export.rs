@@ -0,0 +1,176 @@
+//! Export mode: fine-tuning samples from an existing JSONL dataset
+//!
+//! The dataset is read twice when tests are paired with implementations,
+//! once to index functions and once to write samples.
+
+use crate::*;
+use std::io::{BufWriter, Write};
+
+/// Arguments of the `export` subcommand
+#[derive(Args, Debug)]
+pub(crate) struct ExportArgs {
+    /// Datasets written by previous runs: JSONL files, compressed or not,
+    /// or shard manifests
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Output JSONL file of samples
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Template to generate (repeatable; default: all)
+    #[arg(long = "template", value_enum, value_name = "TEMPLATE")]
+    templates: Vec<TemplateArg>,
+
+    /// Layout of each sample
+    #[arg(long, value_enum, default_value_t = SftFormatArg::PromptCompletion)]
+    format: SftFormatArg,
+}
+
+impl ExportArgs {
+    fn config(&self) -> SftConfig {
+        let templates = if self.templates.is_empty() {
+            SftTemplate::ALL.to_vec()
+        } else {
+            self.templates.iter().map(|&arg| arg.into()).collect()
+        };
+        SftConfig { templates }
+    }
+}
+
+/// Command line spelling of `SftTemplate`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TemplateArg {
+    /// Docstring to function
+    DocToCode,
+    /// Signature to body
+    SignatureToBody,
+    /// Fill in the middle of items extracted with `--fim`
+    Fim,
+    /// Test to the function it exercises
+    TestToImpl,
+}
+
+impl From<TemplateArg> for SftTemplate {
+    fn from(arg: TemplateArg) -> Self {
+        match arg {
+            TemplateArg::DocToCode => SftTemplate::DocToCode,
+            TemplateArg::SignatureToBody => SftTemplate::SignatureToBody,
+            TemplateArg::Fim => SftTemplate::Fim,
+            TemplateArg::TestToImpl => SftTemplate::TestToImpl,
+        }
+    }
+}
+
+/// Command line spelling of `SftFormat`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SftFormatArg {
+    /// `prompt` and `completion`
+    PromptCompletion,
+    /// `messages` with a user and an assistant turn
+    Chat,
+    /// `instruction`, `input` and `output`
+    Alpaca,
+}
+
+impl From<SftFormatArg> for SftFormat {
+    fn from(arg: SftFormatArg) -> Self {
+        match arg {
+            SftFormatArg::PromptCompletion => SftFormat::PromptCompletion,
+            SftFormatArg::Chat => SftFormat::Chat,
+            SftFormatArg::Alpaca => SftFormat::Alpaca,
+        }
+    }
+}
+
+/// Write the samples of every item in the input dataset, returning the
+/// number written per template
+pub(crate) fn run_export(args: &ExportArgs) -> anyhow::Result<BTreeMap<String, usize>> {
+    let config = args.config();
+    let format: SftFormat = args.format.into();
+
+    println!("🧪 RustCodeFlow: Exporting fine-tuning samples");
+    println!("📁 Input: {}", inspect::describe(&args.inputs));
+    println!("📄 Output: {} ({})", args.output.display(), format);
+
+    let pairs_tests = config.templates.contains(&SftTemplate::TestToImpl);
+    let mut exporter = SftExporter::new(config);
+    if pairs_tests {
+        for item in DatasetReader::open(&args.inputs)? {
+            exporter.observe(&item?);
+        }
+    }
+
+    let mut writer = BufWriter::new(fs::File::create(&args.output)?);
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for item in DatasetReader::open(&args.inputs)? {
+        let samples = exporter.samples(&item?);
+        proc_macro2::extra::invalidate_current_thread_spans();
+        for sample in samples {
+            serde_json::to_writer(&mut writer, &sample.to_json(format))?;
+            writer.write_all(b"\n")?;
+            *counts.entry(sample.template.to_string()).or_insert(0) += 1;
+        }
+    }
+    writer.flush()?;
+
+    println!("\n📊 Samples:");
+    for (template, count) in &counts {
+        println!("  {}: {}", template, count);
+    }
+    println!("✨ Export complete!");
+
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_reads_a_dataset() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = r#"
+/// Add two numbers
+pub fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_add() {
+        assert_eq!(super::add(1, 2), 3);
+    }
+}
+"#;
+        let config = ExtractConfig::default();
+        let pipeline = Pipeline::new(&config, String::new(), String::new());
+        let items = pipeline
+            .process_source(Path::new("lib.rs"), source)
+            .unwrap();
+        let input = dir.path().join("dataset.jsonl.gz");
+        let mut sink = ShardedJsonlWriter::create(&input, Compression::Gzip, None).unwrap();
+        for item in &items {
+            sink.write_item(item).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let args = ExportArgs {
+            inputs: vec![input],
+            output: dir.path().join("sft.jsonl"),
+            templates: Vec::new(),
+            format: SftFormatArg::Alpaca,
+        };
+        let counts = run_export(&args).unwrap();
+        assert_eq!(counts.get("doc-to-code"), Some(&1));
+        assert_eq!(counts.get("test-to-impl"), Some(&1));
+        // add and test_add have bodies
+        assert_eq!(counts.get("signature-to-body"), Some(&2));
+
+        let output = fs::read_to_string(&args.output).unwrap();
+        let first: serde_json::Value =
+            serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(first["input"], "Add two numbers");
+    }
+}
@@ -0,0 +1,392 @@
+//! Inspection of an existing JSONL dataset: statistics, samples, searches
+//! and validation
+//!
+//! Every command reads plain, `.gz` and `.zst` JSONL files and shard
+//! manifests, one or several at a time. `sample` and `grep` write the
+//! selected items as JSONL to standard output (or a file), so they can be
+//! piped; their counts go to standard error.
+
+use crate::*;
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+/// Arguments of the `stats` subcommand
+#[derive(Args, Debug)]
+pub(crate) struct StatsArgs {
+    /// Datasets written by previous runs: JSONL files, compressed or not,
+    /// or shard manifests
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+/// Arguments of the `sample` subcommand
+#[derive(Args, Debug)]
+pub(crate) struct SampleArgs {
+    /// Datasets written by previous runs: JSONL files, compressed or not,
+    /// or shard manifests
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Number of items to draw
+    #[arg(short = 'n', long = "count", default_value_t = 10)]
+    count: usize,
+
+    /// Seed of the draw; the same seed gives the same sample
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Write the items to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Arguments of the `grep` subcommand
+#[derive(Args, Debug)]
+pub(crate) struct GrepArgs {
+    /// Datasets written by previous runs: JSONL files, compressed or not,
+    /// or shard manifests
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Only keep items of this kind (repeatable)
+    #[arg(long = "kind", value_enum, value_name = "KIND")]
+    kinds: Vec<KindArg>,
+
+    /// Only keep items whose name matches this regular expression
+    #[arg(long, value_name = "REGEX")]
+    name_regex: Option<Regex>,
+
+    /// Write the items to this file instead of standard output
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+/// Arguments of the `validate` subcommand
+#[derive(Args, Debug)]
+pub(crate) struct ValidateArgs {
+    /// Datasets written by previous runs: JSONL files, compressed or not,
+    /// or shard manifests
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Directory the `file_path` of items is relative to, usually the
+    /// repository the dataset was generated from
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    root: PathBuf,
+}
+
+/// Command line spelling of `ItemKind`, as written in datasets
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum KindArg {
+    Function,
+    Method,
+    Impl,
+    Trait,
+    Module,
+    Struct,
+    Enum,
+    #[value(name = "type_alias")]
+    TypeAlias,
+    Const,
+    Static,
+    Union,
+    Macro,
+}
+
+impl From<KindArg> for ItemKind {
+    fn from(arg: KindArg) -> Self {
+        match arg {
+            KindArg::Function => ItemKind::Function,
+            KindArg::Method => ItemKind::Method,
+            KindArg::Impl => ItemKind::Impl,
+            KindArg::Trait => ItemKind::Trait,
+            KindArg::Module => ItemKind::Module,
+            KindArg::Struct => ItemKind::Struct,
+            KindArg::Enum => ItemKind::Enum,
+            KindArg::TypeAlias => ItemKind::TypeAlias,
+            KindArg::Const => ItemKind::Const,
+            KindArg::Static => ItemKind::Static,
+            KindArg::Union => ItemKind::Union,
+            KindArg::Macro => ItemKind::Macro,
+        }
+    }
+}
+
+/// Print the summary statistics of a dataset, as printed after a run
+pub(crate) fn run_stats(args: &StatsArgs) -> anyhow::Result<Summary> {
+    println!("📊 RustCodeFlow: Dataset statistics");
+    println!("📁 Input: {}", describe(&args.inputs));
+
+    let mut summary = Summary::default();
+    let mut repos = BTreeSet::new();
+    let mut files = BTreeSet::new();
+    let mut versions: BTreeMap<String, usize> = BTreeMap::new();
+    let mut duplicates = 0;
+    for item in DatasetReader::open(&args.inputs)? {
+        let item = item?;
+        summary.add(&item)?;
+        repos.insert(item.project_context.repo_url.clone());
+        files.insert((
+            item.project_context.repo_url.clone(),
+            item.project_context.file_path.clone(),
+        ));
+        *versions.entry(item.schema_version.clone()).or_insert(0) += 1;
+        if item.item_meta.duplicate_of.is_some() {
+            duplicates += 1;
+        }
+    }
+
+    summary.print();
+    println!("\n📦 Sources:");
+    println!("  Repositories: {}", repos.len());
+    println!("  Files: {}", files.len());
+    println!("  Tagged duplicates: {}", duplicates);
+    for (version, count) in &versions {
+        let version = if version.is_empty() { "none" } else { version };
+        println!("  Schema version {}: {}", version, count);
+    }
+
+    Ok(summary)
+}
+
+/// Write a seeded random sample of the items of a dataset
+pub(crate) fn run_sample(args: &SampleArgs) -> anyhow::Result<usize> {
+    let mut sample = Sample::new(args.count, args.seed);
+    for item in DatasetReader::open(&args.inputs)? {
+        sample.push(item?);
+    }
+    let seen = sample.seen();
+
+    let items = sample.into_items();
+    let mut sink = open_sink(args.output.as_deref())?;
+    for item in &items {
+        if !write(&mut sink, item)? {
+            return Ok(0);
+        }
+    }
+    if finish(&mut sink)? {
+        eprintln!("Sampled {} of {} items", items.len(), seen);
+    }
+    Ok(items.len())
+}
+
+/// Write the items of a dataset matching the kind and name filters
+pub(crate) fn run_grep(args: &GrepArgs) -> anyhow::Result<usize> {
+    let kinds: Vec<ItemKind> = args.kinds.iter().map(|&arg| arg.into()).collect();
+    let mut sink = open_sink(args.output.as_deref())?;
+    let mut seen = 0;
+    let mut written = 0;
+    for item in DatasetReader::open(&args.inputs)? {
+        let item = item?;
+        seen += 1;
+        let kind_matches = kinds.is_empty() || kinds.contains(&item.item_meta.kind);
+        let name_matches = args
+            .name_regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&item.item_meta.name));
+        if kind_matches && name_matches {
+            if !write(&mut sink, &item)? {
+                return Ok(written);
+            }
+            written += 1;
+        }
+    }
+
+    if finish(&mut sink)? {
+        eprintln!("Matched {} of {} items", written, seen);
+    }
+    Ok(written)
+}
+
+/// Check every item of a dataset, failing when any is invalid
+pub(crate) fn run_validate(args: &ValidateArgs) -> anyhow::Result<usize> {
+    println!("🔎 RustCodeFlow: Validating dataset");
+    println!("📁 Input: {}", describe(&args.inputs));
+    println!("📂 Source root: {}", args.root.display());
+
+    let mut validator = Validator::new(&args.root);
+    let mut total = 0;
+    let mut invalid = 0;
+    for (index, item) in DatasetReader::open(&args.inputs)?.enumerate() {
+        total += 1;
+        let (label, problems) = match item {
+            Ok(item) => (
+                format!(
+                    "{} ({})",
+                    item.item_meta.fully_qualified_name, item.item_meta.id
+                ),
+                validator.check(&item)?,
+            ),
+            Err(e) => ("unreadable record".to_string(), vec![e.to_string()]),
+        };
+        if !problems.is_empty() {
+            invalid += 1;
+            for problem in problems {
+                println!("  item {}: {}: {}", index + 1, label, problem);
+            }
+        }
+    }
+
+    if validator.missing_files() > 0 {
+        println!(
+            "⚠️  {} items not checked against their file: not found under {}",
+            validator.missing_files(),
+            args.root.display()
+        );
+    }
+    if invalid > 0 {
+        anyhow::bail!("{} of {} items are invalid", invalid, total);
+    }
+    println!("✅ All {} items are valid", total);
+    Ok(total)
+}
+
+/// JSONL sink writing to `output`, or standard output
+fn open_sink(output: Option<&Path>) -> anyhow::Result<JsonlWriter<Box<dyn Write>>> {
+    let writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    Ok(JsonlWriter::new(writer))
+}
+
+/// Write one item, `false` if the reader of the output went away (e.g. a
+/// `| head`), in which case the command stops quietly
+fn write(sink: &mut JsonlWriter<Box<dyn Write>>, item: &ExtractedItem) -> anyhow::Result<bool> {
+    still_open(sink.write_item(item))
+}
+
+/// Flush the output, `false` if its reader went away
+fn finish(sink: &mut JsonlWriter<Box<dyn Write>>) -> anyhow::Result<bool> {
+    still_open(sink.finish())
+}
+
+fn still_open(result: CoreResult<()>) -> anyhow::Result<bool> {
+    let kind = match &result {
+        Err(CoreError::Io(e)) => Some(e.kind()),
+        Err(CoreError::Json(e)) => e.io_error_kind(),
+        _ => None,
+    };
+    match kind {
+        Some(io::ErrorKind::BrokenPipe) => Ok(false),
+        _ => result.map(|()| true).map_err(Into::into),
+    }
+}
+
+/// Input paths, for the banner
+pub(crate) fn describe(inputs: &[PathBuf]) -> String {
+    inputs
+        .iter()
+        .map(|input| input.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspection_commands_read_a_dataset() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = "pub fn parse() {}\n\npub fn print() {}\n\npub struct Parser;\n";
+        fs::write(dir.path().join("lib.rs"), source).unwrap();
+        let config = ExtractConfig::default();
+        let pipeline = Pipeline::new(&config, String::new(), String::new());
+        let mut items = pipeline
+            .process_source(Path::new("lib.rs"), source)
+            .unwrap();
+        let input = dir.path().join("dataset.jsonl");
+        let write = |items: &[ExtractedItem]| {
+            let mut sink = JsonlWriter::create(&input).unwrap();
+            for item in items {
+                sink.write_item(item).unwrap();
+            }
+            sink.finish().unwrap();
+        };
+        write(&items);
+
+        let stats = run_stats(&StatsArgs {
+            inputs: vec![input.clone()],
+        })
+        .unwrap();
+        assert_eq!(stats.total_items, 3);
+
+        let sampled = run_sample(&SampleArgs {
+            inputs: vec![input.clone()],
+            count: 2,
+            seed: 7,
+            output: Some(dir.path().join("sample.jsonl")),
+        })
+        .unwrap();
+        assert_eq!(sampled, 2);
+
+        let grep = GrepArgs {
+            inputs: vec![input.clone()],
+            kinds: vec![KindArg::Function],
+            name_regex: Some(Regex::new("^pa").unwrap()),
+            output: Some(dir.path().join("grep.jsonl")),
+        };
+        assert_eq!(run_grep(&grep).unwrap(), 1);
+        let found: Vec<String> = JsonlReader::open(grep.output.as_ref().unwrap())
+            .unwrap()
+            .map(|item| item.unwrap().item_meta.name)
+            .collect();
+        assert_eq!(found, ["parse"]);
+
+        let validate = ValidateArgs {
+            inputs: vec![input.clone()],
+            root: dir.path().to_path_buf(),
+        };
+        assert_eq!(run_validate(&validate).unwrap(), 3);
+
+        // Sharded and compressed datasets read the same, alone or together
+        let sharded = dir.path().join("sharded.jsonl");
+        let mut sink = create_sink(
+            &sharded,
+            &OutputOptions {
+                compression: Compression::Zstd,
+                shard_size: Some(ShardSize::Items(2)),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for item in &items {
+            sink.write_item(item).unwrap();
+        }
+        sink.finish().unwrap();
+        let manifest = ShardedJsonlWriter::manifest_path(&sharded);
+        let stats = run_stats(&StatsArgs {
+            inputs: vec![manifest.clone(), input.clone()],
+        })
+        .unwrap();
+        assert_eq!(stats.total_items, 6);
+        let grep = GrepArgs {
+            inputs: vec![manifest],
+            ..grep
+        };
+        assert_eq!(run_grep(&grep).unwrap(), 1);
+
+        items[2].content.semantic_hash = "blake3:0".to_string();
+        write(&items);
+        let error = run_validate(&validate).unwrap_err();
+        assert_eq!(error.to_string(), "1 of 3 items are invalid");
+
+        // Inconsistent line ranges and empty datasets do not break stats
+        items[0].item_meta.start_line = 5;
+        items[0].item_meta.end_line = 1;
+        write(&items[..1]);
+        let stats = run_stats(&StatsArgs {
+            inputs: vec![input.clone()],
+        })
+        .unwrap();
+        assert_eq!(stats.total_lines, 1);
+        write(&[]);
+        let stats = run_stats(&StatsArgs {
+            inputs: vec![input],
+        })
+        .unwrap();
+        assert_eq!(stats.total_items, 0);
+    }
+}
@@ -1,28 +1,165 @@
 //! RustCodeFlow CLI
 //!
-//! CLI tool for converting Rust repositories to JSONL datasets for AI training.
+//! CLI tool for converting Rust repositories to JSONL, Parquet or Arrow
+//! datasets for AI training.
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use std::path::{Path, PathBuf};
 
 use core::*;
 
+mod batch;
+mod export;
+mod inspect;
+
 #[derive(Parser, Debug)]
 #[command(name = "rustcodeflow")]
-#[command(about = "Convert Rust repositories to JSONL datasets for AI training")]
+#[command(about = "Convert Rust repositories to JSONL, Parquet or Arrow datasets for AI training")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Process every repository listed in a TOML or JSONL manifest
+    Batch(Box<BatchArgs>),
+    /// Turn a JSONL dataset into prompt/completion pairs for fine-tuning
+    Export(export::ExportArgs),
+    /// Print the JSON Schema of dataset records
+    Schema(SchemaArgs),
+    /// Print summary statistics of a JSONL dataset
+    Stats(inspect::StatsArgs),
+    /// Write a random sample of the items of a JSONL dataset
+    Sample(inspect::SampleArgs),
+    /// Write the items of a JSONL dataset matching a kind or name
+    Grep(inspect::GrepArgs),
+    /// Check the items of a JSONL dataset against their content and files
+    Validate(inspect::ValidateArgs),
+}
+
+/// Arguments of a single repository run
+#[derive(Args, Debug)]
+struct RunArgs {
     /// Source: GitHub URL or local path to Rust repository
-    source: String,
+    #[arg(required = true)]
+    source: Option<String>,
+
+    /// Commit, tag or branch to check out (default: the remote's default
+    /// branch, or the working tree of a local path)
+    #[arg(long, value_name = "REV")]
+    rev: Option<String>,
+
+    /// Output file path
+    #[arg(short, long, required = true)]
+    output: Option<PathBuf>,
+
+    /// Only emit items added, changed or removed since this commit
+    #[arg(long, value_name = "REV")]
+    diff_base: Option<String>,
+
+    #[command(flatten)]
+    output_args: OutputArgs,
+
+    #[command(flatten)]
+    extract: ExtractArgs,
+
+    #[command(flatten)]
+    dedup: DedupArgs,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    graph: GraphArgs,
+
+    #[command(flatten)]
+    runtime: RuntimeArgs,
+}
+
+/// Arguments of the `batch` subcommand
+#[derive(Args, Debug)]
+struct BatchArgs {
+    /// Manifest listing the repositories (`.toml` or `.jsonl`)
+    manifest: PathBuf,
+
+    /// Directory receiving one dataset per repository, the combined
+    /// dataset and the report
+    #[arg(short, long)]
+    output_dir: PathBuf,
+
+    #[command(flatten)]
+    output_args: OutputArgs,
+
+    /// Defaults for options a manifest entry does not set
+    #[command(flatten)]
+    extract: ExtractArgs,
 
-    /// Output JSONL file path
+    #[command(flatten)]
+    dedup: DedupArgs,
+
+    #[command(flatten)]
+    filter: FilterArgs,
+
+    #[command(flatten)]
+    graph: GraphArgs,
+
+    #[command(flatten)]
+    runtime: RuntimeArgs,
+}
+
+/// Arguments of the `schema` subcommand
+#[derive(Args, Debug)]
+struct SchemaArgs {
+    /// Write the schema to this file instead of standard output
     #[arg(short, long)]
-    output: PathBuf,
+    output: Option<PathBuf>,
+}
+
+/// How the dataset is written
+#[derive(Args, Debug)]
+struct OutputArgs {
+    /// Output file format
+    #[arg(long, value_enum, default_value_t = FormatArg::Jsonl)]
+    format: FormatArg,
+
+    /// Compression of the output (Arrow IPC supports zstd only)
+    #[arg(long, value_enum, default_value_t = CompressArg::None)]
+    compress: CompressArg,
+
+    /// Split JSONL output into shards of this many items, or of this size
+    /// when a unit is given (e.g. 100000 or 256MB); a manifest lists them
+    #[arg(long, value_name = "ITEMS|BYTES")]
+    shard_size: Option<ShardSize>,
+
+    /// Rows per Parquet row group and Arrow record batch
+    #[arg(long, value_name = "ROWS")]
+    row_group_size: Option<usize>,
+}
 
-    /// Preserve documentation comments in output
+impl OutputArgs {
+    fn options(&self) -> OutputOptions {
+        OutputOptions {
+            format: self.format.into(),
+            compression: self.compress.into(),
+            row_group_size: self.row_group_size,
+            shard_size: self.shard_size,
+        }
+    }
+}
+
+/// What is extracted from a repository
+#[derive(Args, Debug)]
+struct ExtractArgs {
+    /// Keep documentation comments in normalized bodies
     #[arg(long, default_value = "false")]
     keep_docs: bool,
 
@@ -30,168 +167,871 @@ struct Cli {
     #[arg(long, default_value = "false")]
     full_context: bool,
 
+    /// Only process files matching this glob (relative to the repository
+    /// root, repeatable)
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files matching this glob (relative to the repository root,
+    /// repeatable)
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Do not skip `target/` and `vendor/` directories by default
+    #[arg(long)]
+    no_default_excludes: bool,
+
+    /// Skip files with an `@generated` header, `include!`d files and build
+    /// script (OUT_DIR) outputs
+    #[arg(long)]
+    skip_generated: bool,
+
+    /// Keep secrets and PII (keys, tokens, emails) instead of replacing
+    /// them with placeholders such as `<SECRET_AWS_KEY>`
+    #[arg(long)]
+    no_scrub: bool,
+
+    /// Attach a fill-in-the-middle sample to every function and method,
+    /// with a statement, expression, block or match arm as the middle
+    #[arg(long)]
+    fim: bool,
+
+    /// Order of the parts of FIM prompts
+    #[arg(long, value_enum, default_value_t = FimOrderArg::Psm)]
+    fim_order: FimOrderArg,
+
+    /// Seed of the FIM span choice; the same seed picks the same spans
+    #[arg(long, default_value_t = 0, value_name = "SEED")]
+    fim_seed: u64,
+
+    /// Kind of node the FIM middle may cover (repeatable; default: all)
+    #[arg(long = "fim-span", value_enum, value_name = "KIND")]
+    fim_spans: Vec<FimSpanArg>,
+
+    /// Sentinel token before the FIM prefix
+    #[arg(long, default_value = "<fim_prefix>", value_name = "TOKEN")]
+    fim_prefix_token: String,
+
+    /// Sentinel token before the FIM suffix
+    #[arg(long, default_value = "<fim_suffix>", value_name = "TOKEN")]
+    fim_suffix_token: String,
+
+    /// Sentinel token ending FIM prompts, before the middle
+    #[arg(long, default_value = "<fim_middle>", value_name = "TOKEN")]
+    fim_middle_token: String,
+}
+
+impl ExtractArgs {
+    fn fim(&self) -> Option<FimConfig> {
+        if !self.fim {
+            return None;
+        }
+        let span_kinds = if self.fim_spans.is_empty() {
+            FimSpanKind::ALL.to_vec()
+        } else {
+            self.fim_spans.iter().map(|&arg| arg.into()).collect()
+        };
+        Some(FimConfig {
+            order: self.fim_order.into(),
+            sentinels: FimSentinels {
+                prefix: self.fim_prefix_token.clone(),
+                suffix: self.fim_suffix_token.clone(),
+                middle: self.fim_middle_token.clone(),
+            },
+            seed: self.fim_seed,
+            span_kinds,
+        })
+    }
+}
+
+/// Deduplication across the whole dataset
+#[derive(Args, Debug)]
+struct DedupArgs {
+    /// Handling of items with identical semantic hashes
+    #[arg(long, value_enum, default_value_t = DedupArg::None)]
+    dedup: DedupArg,
+
+    /// Drop items whose estimated Jaccard similarity to an earlier item of
+    /// the same kind reaches this threshold (MinHash over token shingles)
+    #[arg(long, value_name = "JACCARD", value_parser = parse_threshold)]
+    near_dup: Option<f64>,
+}
+
+/// Items left out of the dataset
+#[derive(Args, Debug)]
+struct FilterArgs {
+    /// Only keep items whose SPDX license expression can be satisfied with
+    /// these licenses (repeatable); items without a license are dropped
+    #[arg(long, value_name = "SPDX-ID")]
+    allow_license: Vec<String>,
+
+    /// Drop items whose SPDX license expression cannot be satisfied without
+    /// these licenses (repeatable)
+    #[arg(long, value_name = "SPDX-ID")]
+    deny_license: Vec<String>,
+
+    /// Drop items in which a secret was found instead of writing them with
+    /// placeholders (PII such as emails is still only replaced)
+    #[arg(long)]
+    drop_on_secret: bool,
+
+    /// Drop items with fewer Rust tokens (doc comments excluded)
+    #[arg(long, value_name = "N")]
+    min_tokens: Option<u32>,
+
+    /// Drop items with more Rust tokens (doc comments excluded)
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<u32>,
+
+    /// Drop items with fewer non-blank, non-comment lines
+    #[arg(long, value_name = "N")]
+    min_lines: Option<u32>,
+
+    /// Drop items with more non-blank, non-comment lines
+    #[arg(long, value_name = "N")]
+    max_lines: Option<u32>,
+
+    /// Drop items with a higher cyclomatic complexity
+    #[arg(long, value_name = "N")]
+    max_complexity: Option<u32>,
+
+    /// Drop items nested deeper than this
+    #[arg(long, value_name = "N")]
+    max_nesting: Option<u32>,
+
+    /// Drop `#[test]` functions, `#[cfg(test)]` items and integration tests
+    #[arg(long)]
+    exclude_tests: bool,
+
+    /// Drop `#[bench]` functions and items of bench targets
+    #[arg(long)]
+    exclude_benches: bool,
+
+    /// Drop items from `@generated` files and `#[automatically_derived]` impls
+    #[arg(long)]
+    exclude_generated: bool,
+}
+
+impl FilterArgs {
+    fn quality(&self) -> QualityFilter {
+        QualityFilter {
+            min_tokens: self.min_tokens,
+            max_tokens: self.max_tokens,
+            min_lines: self.min_lines,
+            max_lines: self.max_lines,
+            max_complexity: self.max_complexity,
+            max_nesting: self.max_nesting,
+            exclude_tests: self.exclude_tests,
+            exclude_benches: self.exclude_benches,
+            exclude_generated: self.exclude_generated,
+        }
+    }
+}
+
+/// References between items
+#[derive(Args, Debug)]
+struct GraphArgs {
+    /// Resolve calls, method calls, type references and trait impls between
+    /// the items of each crate into `references` and `referenced_by`
+    #[arg(long)]
+    graph: bool,
+
+    /// Also write the items and their edges to this file (implies --graph)
+    #[arg(long, value_name = "PATH")]
+    graph_output: Option<PathBuf>,
+
+    /// Format of the graph file
+    #[arg(long, value_enum, default_value_t = GraphFormatArg::Jsonl)]
+    graph_format: GraphFormatArg,
+}
+
+impl GraphArgs {
+    fn enabled(&self) -> bool {
+        self.graph || self.graph_output.is_some()
+    }
+}
+
+/// Resources used while running
+#[derive(Args, Debug)]
+struct RuntimeArgs {
     /// Number of parallel threads (default: logical CPUs)
     #[arg(long, default_value_t = num_cpus::get())]
     threads: usize,
 
-    /// Clean up temporary repository after processing
-    #[arg(long, default_value = "true")]
+    /// Clean up temporary repository after processing (the default)
+    #[arg(long, overrides_with = "no_cleanup")]
     cleanup: bool,
+
+    /// Keep the temporary clone after processing; the next run against the
+    /// same repository fetches and resets it instead of cloning again
+    #[arg(long, overrides_with = "cleanup")]
+    no_cleanup: bool,
+
+    /// Directory of the extraction cache; files whose contents did not
+    /// change since the previous run are not parsed again
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+}
+
+impl RuntimeArgs {
+    /// Whether the temporary clone is removed after processing
+    fn cleanup(&self) -> bool {
+        !self.no_cleanup
+    }
+}
+
+fn parse_threshold(value: &str) -> Result<f64, String> {
+    let threshold: f64 = value.parse().map_err(|e| format!("{e}"))?;
+    if (0.0..=1.0).contains(&threshold) {
+        Ok(threshold)
+    } else {
+        Err("must be between 0 and 1".to_string())
+    }
+}
+
+/// Command line spelling of `OutputFormat`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FormatArg {
+    Jsonl,
+    Parquet,
+    ArrowIpc,
+}
+
+impl From<FormatArg> for OutputFormat {
+    fn from(arg: FormatArg) -> Self {
+        match arg {
+            FormatArg::Jsonl => OutputFormat::Jsonl,
+            FormatArg::Parquet => OutputFormat::Parquet,
+            FormatArg::ArrowIpc => OutputFormat::ArrowIpc,
+        }
+    }
+}
+
+/// Command line spelling of `Compression`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum CompressArg {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl From<CompressArg> for Compression {
+    fn from(arg: CompressArg) -> Self {
+        match arg {
+            CompressArg::None => Compression::None,
+            CompressArg::Gzip => Compression::Gzip,
+            CompressArg::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+/// Command line spelling of `GraphFormat`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum GraphFormatArg {
+    Jsonl,
+    Graphml,
+}
+
+impl From<GraphFormatArg> for GraphFormat {
+    fn from(arg: GraphFormatArg) -> Self {
+        match arg {
+            GraphFormatArg::Jsonl => GraphFormat::Jsonl,
+            GraphFormatArg::Graphml => GraphFormat::Graphml,
+        }
+    }
+}
+
+/// Command line spelling of `FimOrder`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FimOrderArg {
+    /// Prefix, suffix, middle
+    Psm,
+    /// Suffix, prefix, middle
+    Spm,
+}
+
+impl From<FimOrderArg> for FimOrder {
+    fn from(arg: FimOrderArg) -> Self {
+        match arg {
+            FimOrderArg::Psm => FimOrder::Psm,
+            FimOrderArg::Spm => FimOrder::Spm,
+        }
+    }
+}
+
+/// Command line spelling of `FimSpanKind`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FimSpanArg {
+    Statement,
+    Expression,
+    Block,
+    MatchArm,
+}
+
+impl From<FimSpanArg> for FimSpanKind {
+    fn from(arg: FimSpanArg) -> Self {
+        match arg {
+            FimSpanArg::Statement => FimSpanKind::Statement,
+            FimSpanArg::Expression => FimSpanKind::Expression,
+            FimSpanArg::Block => FimSpanKind::Block,
+            FimSpanArg::MatchArm => FimSpanKind::MatchArm,
+        }
+    }
+}
+
+/// Command line spelling of `DedupMode`
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DedupArg {
+    /// Write every item
+    None,
+    /// Keep every item, tagging duplicates with `duplicate_of`
+    Exact,
+    /// Drop duplicates, keeping the first occurrence
+    KeepFirst,
+    /// Drop duplicates, keeping the occurrence with the shortest file path
+    KeepShortestPath,
+}
+
+impl From<DedupArg> for DedupMode {
+    fn from(arg: DedupArg) -> Self {
+        match arg {
+            DedupArg::None => DedupMode::None,
+            DedupArg::Exact => DedupMode::Exact,
+            DedupArg::KeepFirst => DedupMode::KeepFirst,
+            DedupArg::KeepShortestPath => DedupMode::KeepShortestPath,
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
+    // Set up thread pool
+    let runtime = match &cli.command {
+        Some(Command::Batch(args)) => Some(&args.runtime),
+        Some(_) => None,
+        None => Some(&cli.run.runtime),
+    };
+    if let Some(runtime) = runtime {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(runtime.threads)
+            .build_global()?;
+    }
+
+    match &cli.command {
+        Some(Command::Batch(args)) => batch::run_batch(args).map(|_| ()),
+        Some(Command::Export(args)) => export::run_export(args).map(|_| ()),
+        Some(Command::Schema(args)) => schema(args),
+        Some(Command::Stats(args)) => inspect::run_stats(args).map(|_| ()),
+        Some(Command::Sample(args)) => inspect::run_sample(args).map(|_| ()),
+        Some(Command::Grep(args)) => inspect::run_grep(args).map(|_| ()),
+        Some(Command::Validate(args)) => inspect::run_validate(args).map(|_| ()),
+        None => run(&cli.run),
+    }
+}
+
+/// Convert a single repository
+fn run(args: &RunArgs) -> anyhow::Result<()> {
+    let source = args.source.clone().expect("clap requires a source");
+    let output_path = args.output.as_ref().expect("clap requires an output");
+    let output_options = args.output_args.options();
+
     println!("🚀 RustCodeFlow: Converting Rust repositories to AI datasets");
-    println!("📁 Source: {}", cli.source);
-    println!("📄 Output: {}", cli.output.display());
+    println!("📁 Source: {}", source);
     println!(
-        "🔧 Options: docs={}, context={}, threads={}",
-        cli.keep_docs, cli.full_context, cli.threads
+        "📄 Output: {} ({}, compression={})",
+        output_path.display(),
+        output_options.format,
+        output_options.compression
+    );
+    println!(
+        "🔧 Options: docs={}, context={}, threads={}, dedup={}",
+        args.extract.keep_docs,
+        args.extract.full_context,
+        args.runtime.threads,
+        DedupMode::from(args.dedup.dedup)
     );
 
-    // Set up thread pool
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(cli.threads)
-        .build_global()?;
-
-    // Create configuration
-    let config = ExtractConfig {
-        keep_docs: cli.keep_docs,
-        include_context: cli.full_context,
-        context_lines: 50,
-    };
+    let job = RepoJob::new(
+        source,
+        args.rev.clone(),
+        args.diff_base.clone(),
+        &args.extract,
+    );
+    let checkout = checkout(&job, args.runtime.cache_dir.as_deref())?;
 
-    // Clone repository or verify local path
-    let repo_path = clone_or_verify_repo(&cli.source)?;
+    let mut output = DatasetWriter::new(
+        create_sink(output_path, &output_options)?,
+        &args.dedup,
+        &args.filter,
+        &args.graph,
+    )?;
+    extract(&job, checkout, &mut output)?;
 
-    // Get repository metadata
-    let (repo_url, commit_hash) = get_repo_metadata(&repo_path)?;
+    if let Some(spool) = output.take_spool() {
+        println!(
+            "🧹 Keeping the shortest path of {} spooled items...",
+            spool.len()
+        );
+        for item in spool.into_reader()? {
+            output.write(item?)?;
+        }
+    }
+    output.finish()?;
 
-    // Find all Rust files
-    let rust_files = find_rust_files(&repo_path)?;
+    // Print summary statistics
+    output.print_summary();
 
-    if rust_files.is_empty() {
-        anyhow::bail!("No .rs files found in repository");
+    println!("✨ Dataset generation complete!");
+    if output_options.shard_size.is_some() {
+        println!(
+            "📁 Output: shards listed in {}",
+            ShardedJsonlWriter::manifest_path(output_path).display()
+        );
+    } else {
+        println!("📁 Output: {}", output_path.display());
     }
 
-    println!("📚 Found {} Rust files to process", rust_files.len());
+    // Clean up temporary repository if requested
+    if args.runtime.cleanup() {
+        cleanup(&job)?;
+    }
 
-    // Set up progress bar
-    let progress_style = ProgressStyle::default_bar()
-        .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} files ({eta})")?
-        .progress_chars("█░");
+    Ok(())
+}
 
-    let progress_bar = ProgressBar::new(rust_files.len() as u64);
-    progress_bar.set_style(progress_style.clone());
-
-    // Process files in parallel
-    let mut all_items = Vec::new();
-
-    for (index, file_path) in rust_files.iter().enumerate() {
-        progress_bar.set_position(index as u64);
-        match extract_items_from_file(
-            file_path,
-            repo_url.to_string(),
-            commit_hash.to_string(),
-            &config,
-        ) {
-            Ok(items) => all_items.extend(items),
-            Err(e) => {
-                eprintln!("Warning: Failed to process {}: {}", file_path.display(), e);
-            }
+/// Print the JSON Schema of `ExtractedItem` records
+fn schema(args: &SchemaArgs) -> anyhow::Result<()> {
+    let schema = serde_json::to_string_pretty(&json_schema())?;
+    match &args.output {
+        Some(path) => fs::write(path, schema + "\n")?,
+        None => println!("{schema}"),
+    }
+    Ok(())
+}
+
+/// One repository to convert, with its options resolved
+struct RepoJob {
+    source: String,
+    rev: Option<String>,
+    diff_base: Option<String>,
+    /// Declared license, replacing the one detected from license files
+    license: Option<String>,
+    config: ExtractConfig,
+    discovery: DiscoveryOptions,
+}
+
+impl RepoJob {
+    fn new(
+        source: String,
+        rev: Option<String>,
+        diff_base: Option<String>,
+        extract: &ExtractArgs,
+    ) -> Self {
+        Self {
+            source,
+            rev,
+            diff_base,
+            license: None,
+            config: ExtractConfig {
+                keep_docs: extract.keep_docs,
+                include_context: extract.full_context,
+                context_lines: 50,
+                scrub: !extract.no_scrub,
+                fim: extract.fim(),
+            },
+            discovery: DiscoveryOptions {
+                include: extract.include.clone(),
+                exclude: extract.exclude.clone(),
+                default_excludes: !extract.no_default_excludes,
+                skip_generated: extract.skip_generated,
+            },
         }
     }
+}
 
-    progress_bar.finish_with_message("✅ Processing complete!");
+/// A repository checked out and ready to be extracted
+struct Checkout {
+    path: PathBuf,
+    repo_url: String,
+    commit_hash: String,
+    workspace: WorkspaceIndex,
+    /// License of files without a package license or an SPDX header
+    license: Option<String>,
+    plan: Plan,
+    cache: Option<ExtractionCache>,
+}
 
-    println!("📊 Extracted {} code items", all_items.len());
+/// Files to extract from a checkout
+enum Plan {
+    /// Every discovered file
+    Full(Vec<PathBuf>),
+    /// Files changed since `base_hash`, relative to the checkout
+    Diff {
+        base_hash: String,
+        changed: Vec<PathBuf>,
+    },
+}
 
-    // Normalize items (identifier anonymization, docstring extraction)
-    println!("🔧 Normalizing identifiers and extracting metadata...");
-    normalize_items(&mut all_items, &config)?;
+impl Plan {
+    /// Number of files to process
+    fn len(&self) -> usize {
+        match self {
+            Plan::Full(files) => files.len(),
+            Plan::Diff { changed, .. } => changed.len(),
+        }
+    }
+}
 
-    // Generate semantic hashes
-    println!("🔐 Generating semantic hashes for deduplication...");
-    hash_and_update_items(&mut all_items)?;
+/// Clone or verify the repository and find the files to extract
+///
+/// Everything that can fail because of the repository itself happens here,
+/// before any item is written.
+fn checkout(job: &RepoJob, cache_dir: Option<&Path>) -> anyhow::Result<Checkout> {
+    // Clone repository or verify local path
+    let path = clone_or_verify_repo(&job.source, job.rev.as_deref())?;
 
-    // Write to JSONL
-    println!("💾 Writing to JSONL file...");
-    write_jsonl(&all_items, &cli.output)?;
+    // Get repository metadata
+    let (repo_url, commit_hash) = get_repo_metadata(&path)?;
 
-    // Print summary statistics
-    print_summary(&all_items)?;
+    // Crates, targets and module paths of the files
+    let workspace = WorkspaceIndex::discover(&path)?;
+    for (manifest, e) in workspace.skipped_manifests() {
+        println!(
+            "Warning: Skipped manifest {}: {}",
+            manifest.strip_prefix(&path).unwrap_or(manifest).display(),
+            e
+        );
+    }
+    println!(
+        "📦 Found {} Cargo packages with {} targets",
+        workspace.packages().len(),
+        workspace
+            .packages()
+            .iter()
+            .map(|package| package.targets.len())
+            .sum::<usize>()
+    );
 
-    println!("✨ Dataset generation complete!");
-    println!("📁 Output: {}", cli.output.display());
+    let license = job
+        .license
+        .clone()
+        .or_else(|| detect_directory_license(&path));
+    println!(
+        "⚖️  Repository license: {}",
+        license.as_deref().unwrap_or("none found")
+    );
 
-    // Clean up temporary repository if requested
-    if cli.cleanup && cli.source.starts_with("http") {
-        let repo_name = extract_repo_name(&cli.source)?;
-        let temp_dir = std::env::temp_dir().join(format!("rustcodeflow_{}", repo_name));
+    let plan = if let Some(base) = &job.diff_base {
+        let base_spec = format!("{base}^{{commit}}");
+        let base_hash = match git_output(&path, &["rev-parse", &base_spec]) {
+            Ok(hash) => hash,
+            // Clones are shallow, so the base usually has to be fetched first
+            Err(_) if clone_dir(&job.source, job.rev.as_deref())?.is_some() => {
+                fetch_rev(&path, base)?
+            }
+            Err(e) => return Err(e),
+        };
+        let filter = FileFilter::new(&job.discovery)?;
+        let mut changed = changed_rust_files(&path, &base_hash, &commit_hash)?;
+        changed.retain(|relative| filter.is_selected(relative));
+        println!(
+            "📚 {} Rust files changed since {}",
+            changed.len(),
+            &base_hash[..base_hash.len().min(12)]
+        );
+        Plan::Diff { base_hash, changed }
+    } else {
+        // Find all Rust files
+        let discovery = find_rust_files(&path, &job.discovery)?;
+        if discovery.files.is_empty() {
+            anyhow::bail!("No .rs files found in repository");
+        }
 
-        if temp_dir.exists() {
-            println!("🧹 Cleaning up temporary repository...");
-            fs::remove_dir_all(&temp_dir)
-                .map_err(|e| anyhow::anyhow!("Failed to clean up temporary repository: {}", e))?;
-            println!("✅ Temporary repository cleaned up");
+        println!("📚 Found {} Rust files to process", discovery.files.len());
+        if discovery.skipped_generated > 0 {
+            println!("🤖 Skipped {} generated files", discovery.skipped_generated);
         }
-    }
+        Plan::Full(discovery.files)
+    };
 
-    Ok(())
+    // Diffs are extracted from git objects, which the cache does not cover
+    let cache = match (cache_dir, &plan) {
+        (Some(cache_dir), Plan::Full(_)) => {
+            Some(ExtractionCache::open(cache_dir, &repo_url, &job.config)?)
+        }
+        _ => None,
+    };
+
+    Ok(Checkout {
+        path,
+        repo_url,
+        commit_hash,
+        workspace,
+        license,
+        plan,
+        cache,
+    })
 }
 
-/// Clone GitHub repository or verify local path exists
-fn clone_or_verify_repo(source: &str) -> anyhow::Result<PathBuf> {
-    let repo_name = extract_repo_name(source)?;
-    let temp_dir = std::env::temp_dir().join(format!("rustcodeflow_{}", repo_name));
-
-    if source.starts_with("http") {
-        // GitHub URL - clone or update
-        if temp_dir.exists() {
-            println!("📂 Updating existing repository at {}", temp_dir.display());
-            let output = std::process::Command::new("git")
-                .args(["pull", "origin", "main"])
-                .current_dir(&temp_dir)
-                .output()?;
-
-            if !output.status.success() {
-                println!("⚠️  Git pull failed, trying master branch...");
-                let output = std::process::Command::new("git")
-                    .args(["pull", "origin", "master"])
-                    .current_dir(&temp_dir)
-                    .output()?;
-
-                if !output.status.success() {
-                    anyhow::bail!(
-                        "Failed to update repository: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    );
+/// Extract, normalize and hash the planned files, streaming items to `output`
+///
+/// Files that fail to parse are reported and skipped; errors returned here
+/// come from the output or the cache.
+fn extract<S: ItemSink>(
+    job: &RepoJob,
+    checkout: Checkout,
+    output: &mut DatasetWriter<S>,
+) -> anyhow::Result<()> {
+    let Checkout {
+        path: repo_path,
+        repo_url,
+        commit_hash,
+        workspace,
+        license,
+        plan,
+        mut cache,
+    } = checkout;
+    let config = &job.config;
+
+    // Set up progress bar
+    let progress_style = ProgressStyle::default_bar()
+        .template("{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {pos}/{len} files ({eta})")?
+        .progress_chars("█░");
+    let progress_bar = ProgressBar::new(plan.len() as u64);
+    progress_bar.set_style(progress_style);
+
+    match plan {
+        Plan::Diff { base_hash, changed } => {
+            // Module paths are resolved against the current checkout for both sides
+            let base_pipeline = Pipeline::new(config, repo_url.clone(), base_hash.clone())
+                .with_workspace(&workspace)
+                .with_license(license.clone());
+            let head_pipeline = Pipeline::new(config, repo_url, commit_hash.clone())
+                .with_workspace(&workspace)
+                .with_license(license);
+
+            // Changes are usually small, so all changed files form one batch
+            let results: Vec<anyhow::Result<Vec<ExtractedItem>>> = changed
+                .par_iter()
+                .map(|relative| {
+                    let file_path = repo_path.join(relative);
+                    let old_source = git_show(&repo_path, &base_hash, relative)?;
+                    let new_source = git_show(&repo_path, &commit_hash, relative)?;
+                    progress_bar.inc(1);
+
+                    if job.discovery.skip_generated
+                        && [&old_source, &new_source]
+                            .into_iter()
+                            .flatten()
+                            .any(|source| FileFilter::is_generated(source))
+                    {
+                        return Ok(Vec::new());
+                    }
+
+                    let old = match old_source {
+                        Some(source) => base_pipeline.process_source(&file_path, &source)?,
+                        None => Vec::new(),
+                    };
+                    let new = match new_source {
+                        Some(source) => head_pipeline.process_source(&file_path, &source)?,
+                        None => Vec::new(),
+                    };
+                    Ok(diff_items(old, new))
+                })
+                .collect();
+
+            for (relative, result) in changed.iter().zip(results) {
+                match result {
+                    Ok(items) => {
+                        for item in items {
+                            output.emit(item)?;
+                        }
+                    }
+                    Err(e) => progress_bar.println(format!(
+                        "Warning: Failed to process {}: {}",
+                        relative.display(),
+                        e
+                    )),
                 }
             }
-        } else {
-            println!("📥 Cloning repository...");
-            let output = std::process::Command::new("git")
-                .args(["clone", "--depth", "1", source, temp_dir.to_str().unwrap()])
-                .output()?;
-
-            if !output.status.success() {
-                anyhow::bail!(
-                    "Failed to clone repository: {}",
-                    String::from_utf8_lossy(&output.stderr)
+        }
+        Plan::Full(rust_files) => {
+            // Process files in parallel: extraction, normalization (identifier
+            // anonymization, docstring extraction) and semantic hashing. Items
+            // are streamed to the output in sorted file order as each chunk
+            // finishes.
+            println!("🔧 Extracting, normalizing and hashing items...");
+            let pipeline = Pipeline::new(config, repo_url, commit_hash)
+                .with_workspace(&workspace)
+                .with_license(license);
+
+            pipeline.run_with_cache(&rust_files, cache.as_mut(), |file_path, result| {
+                progress_bar.inc(1);
+                match result {
+                    Ok(items) => {
+                        for item in items {
+                            output.emit(item)?;
+                        }
+                    }
+                    Err(e) => progress_bar.println(format!(
+                        "Warning: Failed to process {}: {}",
+                        file_path.display(),
+                        e
+                    )),
+                }
+                Ok(())
+            })?;
+
+            if let Some(cache) = cache {
+                println!(
+                    "🗃️  Cache: {} files reused, {} parsed ({})",
+                    cache.hits(),
+                    cache.misses(),
+                    cache.path().display()
                 );
+                cache.finish()?;
             }
         }
+    }
 
-        Ok(temp_dir)
-    } else {
-        // Local path
+    progress_bar.finish_with_message("✅ Processing complete!");
+    Ok(())
+}
+
+/// Remove the temporary clone made for `job`, if any
+fn cleanup(job: &RepoJob) -> anyhow::Result<()> {
+    if let Some(temp_dir) = clone_dir(&job.source, job.rev.as_deref())?
+        && temp_dir.exists()
+    {
+        println!("🧹 Cleaning up temporary repository...");
+        fs::remove_dir_all(&temp_dir)
+            .map_err(|e| anyhow::anyhow!("Failed to clean up temporary repository: {}", e))?;
+        println!("✅ Temporary repository cleaned up");
+    }
+    Ok(())
+}
+
+/// Clone GitHub repository or verify local path exists
+///
+/// A local path is used as is unless a revision is requested; it is then
+/// cloned like a remote so the working tree is left alone.
+fn clone_or_verify_repo(source: &str, rev: Option<&str>) -> anyhow::Result<PathBuf> {
+    let Some(temp_dir) = clone_dir(source, rev)? else {
         let path = PathBuf::from(source);
         if !path.exists() {
             anyhow::bail!("Local path does not exist: {}", source);
         }
-        Ok(path)
+        return Ok(path);
+    };
+
+    let url = if is_remote(source) {
+        source.to_string()
+    } else {
+        fs::canonicalize(source)
+            .map_err(|e| anyhow::anyhow!("Local path does not exist: {}: {}", source, e))?
+            .to_string_lossy()
+            .to_string()
+    };
+
+    if temp_dir.join(".git").exists() {
+        println!("📂 Updating existing repository at {}", temp_dir.display());
+    } else {
+        println!("📥 Cloning repository...");
+    }
+    let commit = sync_repo(&url, &temp_dir, rev)?;
+    println!(
+        "📌 Checked out {} at {}",
+        rev.unwrap_or("default branch"),
+        &commit[..commit.len().min(12)]
+    );
+
+    Ok(temp_dir)
+}
+
+/// Whether `source` is a URL git has to clone rather than a local path
+fn is_remote(source: &str) -> bool {
+    source.contains("://") || source.starts_with("git@")
+}
+
+/// Directory `source` is cloned into, `None` if it is used in place
+fn clone_dir(source: &str, rev: Option<&str>) -> anyhow::Result<Option<PathBuf>> {
+    if !is_remote(source) && rev.is_none() {
+        return Ok(None);
+    }
+    let repo_name = extract_repo_name(source)?;
+    Ok(Some(
+        std::env::temp_dir().join(format!("rustcodeflow_{}", repo_name)),
+    ))
+}
+
+/// Make `dir` a checkout of `rev` (the default branch if `None`) of the
+/// repository at `url`, and return the commit hash
+///
+/// An existing clone is fetched and hard reset rather than pulled, so local
+/// changes and the previously checked out branch do not matter.
+fn sync_repo(url: &str, dir: &Path, rev: Option<&str>) -> anyhow::Result<String> {
+    if dir.join(".git").exists() {
+        git_output(dir, &["remote", "set-url", "origin", url])?;
+    } else {
+        fs::create_dir_all(dir)?;
+        git_output(dir, &["init", "--quiet"])?;
+        git_output(dir, &["remote", "add", "origin", url])?;
+    }
+
+    let commit = match rev {
+        Some(rev) => fetch_rev(dir, rev)?,
+        None => {
+            // HEAD of the remote is its default branch, whatever its name
+            git_output(dir, &["fetch", "--quiet", "--depth", "1", "origin", "HEAD"])?;
+            git_output(dir, &["rev-parse", "FETCH_HEAD^{commit}"])?
+        }
+    };
+
+    git_output(
+        dir,
+        &["checkout", "--quiet", "--force", "--detach", &commit],
+    )?;
+    git_output(dir, &["reset", "--quiet", "--hard", &commit])?;
+    git_output(dir, &["clean", "--quiet", "-ffdx"])?;
+    Ok(commit)
+}
+
+/// Fetch a commit, tag or branch from `origin` and return its commit hash
+fn fetch_rev(dir: &Path, rev: &str) -> anyhow::Result<String> {
+    // Branches, tags and full commit hashes can be fetched directly
+    if git_output(dir, &["fetch", "--quiet", "--depth", "1", "origin", rev]).is_ok() {
+        return git_output(dir, &["rev-parse", "FETCH_HEAD^{commit}"]);
     }
+
+    // Abbreviated hashes can only be resolved against the full history
+    let mut args = vec![
+        "fetch",
+        "--quiet",
+        "--tags",
+        "origin",
+        "+refs/heads/*:refs/remotes/origin/*",
+    ];
+    if git_output(dir, &["rev-parse", "--is-shallow-repository"])? == "true" {
+        args.push("--unshallow");
+    }
+    git_output(dir, &args)?;
+
+    [format!("refs/remotes/origin/{rev}"), rev.to_string()]
+        .iter()
+        .find_map(|spec| {
+            git_output(
+                dir,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("{spec}^{{commit}}"),
+                ],
+            )
+            .ok()
+        })
+        .ok_or_else(|| anyhow::anyhow!("Revision {} not found in the repository", rev))
 }
 
 /// Extract repository name from URL or path
@@ -245,64 +1085,526 @@ fn get_repo_metadata(repo_path: &PathBuf) -> anyhow::Result<(String, String)> {
     Ok((repo_url, commit_hash))
 }
 
-/// Find all .rs files in repository
-fn find_rust_files(repo_path: &PathBuf) -> anyhow::Result<Vec<PathBuf>> {
-    let mut rust_files = Vec::new();
+/// Run a git command in `repo_path` and return its trimmed stdout
+fn git_output(repo_path: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = std::process::Command::new("git")
+        .args(args)
+        .current_dir(repo_path)
+        .output()?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Contents of a file at a commit, `None` if it does not exist there
+///
+/// Any other failure, such as an unknown commit, is an error rather than a
+/// missing file, which would turn every item into an addition or removal.
+fn git_show(repo_path: &Path, commit: &str, relative: &Path) -> anyhow::Result<Option<String>> {
+    let relative = relative.to_string_lossy().replace('\\', "/");
+    // Paths given to `ls-tree` are relative to `repo_path`, like `relative`
+    let listed = git_output(
+        repo_path,
+        &["ls-tree", "--name-only", commit, "--", &relative],
+    )?;
+    if listed.is_empty() {
+        return Ok(None);
+    }
+
+    // `./` makes the path relative to `repo_path` rather than the git root
+    let spec = format!("{}:./{}", commit, relative);
+    let output = std::process::Command::new("git")
+        .args(["show", &spec])
+        .current_dir(repo_path)
+        .output()?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "git show {} failed: {}",
+            spec,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Some(String::from_utf8(output.stdout)?))
+}
+
+/// Rust files added, modified or deleted between two commits, relative to
+/// `repo_path`. Renames are reported as a deletion plus an addition.
+fn changed_rust_files(repo_path: &Path, base: &str, head: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let names = git_output(
+        repo_path,
+        &[
+            "diff",
+            "--relative",
+            "--no-renames",
+            "--name-only",
+            base,
+            head,
+            "--",
+            "*.rs",
+        ],
+    )?;
+    let mut files: Vec<PathBuf> = names.lines().map(PathBuf::from).collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Final stage of the pipeline: deduplication, statistics and the sink
+struct DatasetWriter<S: ItemSink> {
+    sink: S,
+    dedup: Deduplicator,
+    near_dup: Option<NearDupIndex>,
+    near_dup_threshold: Option<f64>,
+    near_duplicates: usize,
+    license_policy: LicensePolicy,
+    license_filtered: usize,
+    quality: QualityFilter,
+    quality_filtered: usize,
+    drop_on_secret: bool,
+    secret_dropped: usize,
+    redactions: BTreeMap<String, usize>,
+    spool: Option<Spool>,
+    graph: Option<GraphStage>,
+    summary: Summary,
+}
+
+/// Items held back until the references between them are resolved
+struct GraphStage {
+    builder: GraphBuilder,
+    spool: Spool,
+    writer: Option<GraphWriter<fs::File>>,
+    edges: usize,
+}
+
+impl<S: ItemSink> DatasetWriter<S> {
+    fn new(sink: S, args: &DedupArgs, filter: &FilterArgs, graph: &GraphArgs) -> CoreResult<Self> {
+        let dedup = Deduplicator::new(args.dedup.into());
+        // Choosing the shortest path among duplicates needs to see every
+        // item first, so items are spooled to a temporary file for a second
+        // pass
+        let spool = if dedup.needs_prepass() {
+            Some(Spool::new()?)
+        } else {
+            None
+        };
+        Ok(Self {
+            sink,
+            dedup,
+            near_dup: args.near_dup.map(|threshold| {
+                NearDupIndex::new(&NearDupConfig {
+                    threshold,
+                    ..Default::default()
+                })
+            }),
+            near_dup_threshold: args.near_dup,
+            near_duplicates: 0,
+            license_policy: LicensePolicy::new(&filter.allow_license, &filter.deny_license)?,
+            license_filtered: 0,
+            quality: filter.quality(),
+            quality_filtered: 0,
+            drop_on_secret: filter.drop_on_secret,
+            secret_dropped: 0,
+            redactions: BTreeMap::new(),
+            spool,
+            graph: if graph.enabled() {
+                Some(GraphStage {
+                    builder: GraphBuilder::new(),
+                    spool: Spool::new()?,
+                    writer: match &graph.graph_output {
+                        Some(path) => Some(GraphWriter::create(path, graph.graph_format.into())?),
+                        None => None,
+                    },
+                    edges: 0,
+                })
+            } else {
+                None
+            },
+            summary: Summary::default(),
+        })
+    }
+
+    /// Write an item, or spool it if deduplication needs a first pass
+    ///
+    /// Filtered items are dropped here, before deduplication sees them.
+    fn emit(&mut self, item: ExtractedItem) -> CoreResult<()> {
+        if !self
+            .license_policy
+            .allows(item.project_context.license.as_deref())
+        {
+            self.license_filtered += 1;
+            return Ok(());
+        }
+        if !self.quality.allows(&item.item_meta.metrics) {
+            self.quality_filtered += 1;
+            return Ok(());
+        }
+        for name in &item.item_meta.redactions {
+            *self.redactions.entry(name.clone()).or_insert(0) += 1;
+        }
+        if self.drop_on_secret
+            && item
+                .item_meta
+                .redactions
+                .iter()
+                .any(|name| name.starts_with("SECRET_"))
+        {
+            self.secret_dropped += 1;
+            return Ok(());
+        }
+        match self.spool.as_mut() {
+            Some(spool) => {
+                self.dedup.observe(&item)?;
+                spool.write_item(&item)
+            }
+            None => self.write(item),
+        }
+    }
+
+    /// Whether items are spooled rather than written
+    fn spooling(&self) -> bool {
+        self.spool.is_some()
+    }
+
+    /// Number of items spooled so far
+    fn spooled(&self) -> usize {
+        self.spool.as_ref().map_or(0, Spool::len)
+    }
+
+    /// Spooled items, to be passed to `write` once all are emitted
+    fn take_spool(&mut self) -> Option<Spool> {
+        self.spool.take()
+    }
+
+    /// Write an item unless it is dropped as a duplicate or near duplicate
+    fn write(&mut self, item: ExtractedItem) -> CoreResult<()> {
+        let Some(item) = self.dedup.apply(item)? else {
+            return Ok(());
+        };
+        // Exact duplicates kept by `--dedup exact` are already tagged
+        if item.item_meta.duplicate_of.is_none()
+            && let Some(index) = self.near_dup.as_mut()
+        {
+            let near = index.insert(&item);
+            // Items are tokenized on this thread for the whole run and no
+            // span is kept, so the span source map is emptied after each
+            proc_macro2::extra::invalidate_current_thread_spans();
+            if near.is_some() {
+                self.near_duplicates += 1;
+                return Ok(());
+            }
+        }
+        if let Some(graph) = self.graph.as_mut() {
+            graph.builder.observe(&item);
+            proc_macro2::extra::invalidate_current_thread_spans();
+            return graph.spool.write_item(&item);
+        }
+        self.summary.add(&item)?;
+        self.sink.write_item(&item)
+    }
+
+    /// Resolve the references between the items written since the last
+    /// flush and pass them on to the sink and the graph file
+    ///
+    /// Called at the end of every repository, which keeps the items of
+    /// only one repository waiting.
+    fn flush_graph(&mut self) -> CoreResult<()> {
+        let Some(mut stage) = self.graph.take() else {
+            return Ok(());
+        };
+        let graph = std::mem::take(&mut stage.builder).finish();
+        let spool = std::mem::replace(&mut stage.spool, Spool::new()?);
 
-    for entry in WalkDir::new(repo_path) {
-        let entry = entry?;
-        let path = entry.path();
+        for item in spool.into_reader()? {
+            let mut item = item?;
+            graph.apply(&mut item);
+            if let Some(writer) = stage.writer.as_mut() {
+                writer.write_node(&item)?;
+            }
+            self.summary.add(&item)?;
+            self.sink.write_item(&item)?;
+        }
+        if let Some(writer) = stage.writer.as_mut() {
+            for edge in &graph.edges {
+                writer.write_edge(edge)?;
+            }
+        }
+        stage.edges += graph.edges.len();
+
+        self.graph = Some(stage);
+        Ok(())
+    }
 
-        if path.extension().and_then(|s| s.to_str()) == Some("rs") {
-            rust_files.push(path.to_path_buf());
+    fn finish(&mut self) -> CoreResult<()> {
+        self.flush_graph()?;
+        if let Some(writer) = self.graph.as_mut().and_then(|stage| stage.writer.as_mut()) {
+            writer.finish()?;
         }
+        self.sink.finish()
     }
 
-    rust_files.sort();
-    Ok(rust_files)
+    /// Print summary statistics and deduplication counts
+    fn print_summary(&self) {
+        self.summary.print();
+        if self.dedup.mode() != DedupMode::None {
+            println!(
+                "  Duplicates ({}): {}",
+                self.dedup.mode(),
+                self.dedup.duplicates()
+            );
+        }
+        if let Some(threshold) = self.near_dup_threshold {
+            println!(
+                "  Near duplicates (Jaccard >= {}): {}",
+                threshold, self.near_duplicates
+            );
+        }
+        if !self.license_policy.is_empty() {
+            println!("  Filtered by license: {}", self.license_filtered);
+        }
+        if !self.quality.is_empty() {
+            println!("  Filtered by quality: {}", self.quality_filtered);
+        }
+        if self.drop_on_secret {
+            println!("  Dropped for secrets: {}", self.secret_dropped);
+        }
+        if let Some(graph) = &self.graph {
+            println!("  Reference edges: {}", graph.edges);
+        }
+        if !self.redactions.is_empty() {
+            println!("\n🔒 Redactions:");
+            for (name, count) in &self.redactions {
+                println!("  {}: {}", name, count);
+            }
+        }
+    }
 }
 
-/// Write items to JSONL file
-fn write_jsonl(items: &[ExtractedItem], output_file: &PathBuf) -> CoreResult<()> {
-    let file = fs::File::create(output_file)?;
-    let mut writer = std::io::BufWriter::new(file);
+/// Summary statistics accumulated while items are streamed to the output
+#[derive(Default)]
+struct Summary {
+    kind_counts: HashMap<ItemKind, usize>,
+    change_counts: HashMap<ChangeKind, usize>,
+    total_items: usize,
+    total_lines: usize,
+    hash_stats: HashStatsAggregator,
+}
 
-    for item in items {
-        let json_line = serde_json::to_string(item)?;
-        writeln!(writer, "{}", json_line)?;
+impl Summary {
+    fn add(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        *self
+            .kind_counts
+            .entry(item.item_meta.kind.clone())
+            .or_insert(0) += 1;
+        if let Some(change) = item.item_meta.change {
+            *self.change_counts.entry(change).or_insert(0) += 1;
+        }
+        self.total_items += 1;
+        // A record edited by hand may end before it starts; count it as one line
+        let lines = item
+            .item_meta
+            .end_line
+            .saturating_sub(item.item_meta.start_line)
+            + 1;
+        self.total_lines += lines as usize;
+        self.hash_stats.add(item)
     }
 
-    Ok(())
+    /// Print summary statistics
+    fn print(&self) {
+        println!("\n📈 Summary Statistics:");
+        println!("  Total items: {}", self.total_items);
+        println!("  Total lines of code: {}", self.total_lines);
+        if self.total_items > 0 {
+            println!(
+                "  Average lines per item: {:.1}",
+                self.total_lines as f64 / self.total_items as f64
+            );
+        }
+
+        println!("\n📋 Item breakdown:");
+        for (kind, count) in &self.kind_counts {
+            println!("  {}: {}", kind, count);
+        }
+
+        if !self.change_counts.is_empty() {
+            println!("\n🔀 Changes:");
+            for (change, count) in &self.change_counts {
+                println!("  {}: {}", change, count);
+            }
+        }
+
+        // Hash statistics
+        self.hash_stats.stats().print();
+    }
 }
 
-/// Print summary statistics
-fn print_summary(items: &[ExtractedItem]) -> CoreResult<()> {
-    use std::collections::HashMap;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut kind_counts = HashMap::new();
-    let mut total_lines = 0;
+    pub(crate) fn git(dir: &Path, args: &[&str]) -> String {
+        let mut full = vec!["-c", "user.name=test", "-c", "user.email=test@example.com"];
+        full.extend_from_slice(args);
+        git_output(dir, &full).unwrap()
+    }
 
-    for item in items {
-        *kind_counts.entry(&item.item_meta.kind).or_insert(0) += 1;
-        total_lines += (item.item_meta.end_line - item.item_meta.start_line + 1) as usize;
+    pub(crate) fn commit(work: &Path, contents: &str) -> String {
+        fs::write(work.join("lib.rs"), contents).unwrap();
+        git(work, &["add", "lib.rs"]);
+        git(work, &["commit", "--quiet", "-m", contents]);
+        git(work, &["rev-parse", "HEAD"])
     }
 
-    println!("\n📈 Summary Statistics:");
-    println!("  Total items: {}", items.len());
-    println!("  Total lines of code: {}", total_lines);
-    println!(
-        "  Average lines per item: {:.1}",
-        total_lines as f64 / items.len() as f64
-    );
+    #[test]
+    fn test_sync_repo_checks_out_revisions() {
+        let temp = tempfile::tempdir().unwrap();
+        let work = temp.path().join("work");
+        let bare = temp.path().join("remote.git");
+        let clone = temp.path().join("clone");
+        let url = bare.to_str().unwrap();
+
+        // The default branch is neither main nor master
+        fs::create_dir(&work).unwrap();
+        git(&work, &["init", "--quiet", "--initial-branch", "trunk"]);
+        let first = commit(&work, "fn first() {}");
+        git(&work, &["tag", "v1"]);
+        git(&work, &["branch", "feature"]);
+        let second = commit(&work, "fn second() {}");
+        git(
+            temp.path(),
+            &["clone", "--quiet", "--bare", work.to_str().unwrap(), url],
+        );
 
-    println!("\n📋 Item breakdown:");
-    for (kind, count) in kind_counts {
-        println!("  {}: {}", kind, count);
+        assert_eq!(sync_repo(url, &clone, None).unwrap(), second);
+        assert_eq!(
+            fs::read_to_string(clone.join("lib.rs")).unwrap(),
+            "fn second() {}"
+        );
+
+        // The cached clone is fetched and reset, discarding local edits
+        fs::write(clone.join("lib.rs"), "changed").unwrap();
+        fs::write(clone.join("untracked.rs"), "").unwrap();
+        assert_eq!(sync_repo(url, &clone, Some("v1")).unwrap(), first);
+        assert_eq!(
+            fs::read_to_string(clone.join("lib.rs")).unwrap(),
+            "fn first() {}"
+        );
+        assert!(!clone.join("untracked.rs").exists());
+
+        assert_eq!(sync_repo(url, &clone, Some("feature")).unwrap(), first);
+        assert_eq!(sync_repo(url, &clone, Some(&second)).unwrap(), second);
+        assert_eq!(sync_repo(url, &clone, Some(&first[..10])).unwrap(), first);
+
+        // New commits on the remote are picked up
+        let third = commit(&work, "fn third() {}");
+        git(&work, &["push", "--quiet", url, "trunk"]);
+        assert_eq!(sync_repo(url, &clone, Some("trunk")).unwrap(), third);
+        assert_eq!(sync_repo(url, &clone, None).unwrap(), third);
+
+        assert!(sync_repo(url, &clone, Some("missing")).is_err());
     }
 
-    // Hash statistics
-    let hash_stats = HashStats::analyze(items)?;
-    hash_stats.print();
+    #[test]
+    fn test_diff_base_emits_changed_items() {
+        let temp = tempfile::tempdir().unwrap();
+        let repo = temp.path().join("repo");
+        let output = temp.path().join("diff.jsonl");
+        fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "--quiet"]);
+        let base = commit(
+            &repo,
+            "fn keep() {}\nfn gone() {}\nfn edit() -> u32 { 1 }\n",
+        );
+        commit(&repo, "fn keep() {}\nfn edit() -> u32 { 2 }\nfn new() {}\n");
 
-    Ok(())
+        let cli = Cli::try_parse_from([
+            "rustcodesflow",
+            repo.to_str().unwrap(),
+            "--diff-base",
+            &base,
+            "-o",
+            output.to_str().unwrap(),
+        ])
+        .unwrap();
+        run(&cli.run).unwrap();
+
+        let mut changes: Vec<(String, ChangeKind)> = JsonlReader::open(&output)
+            .unwrap()
+            .map(|item| {
+                let item = item.unwrap();
+                (item.item_meta.name, item.item_meta.change.unwrap())
+            })
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            changes,
+            [
+                ("edit".to_string(), ChangeKind::Changed),
+                ("gone".to_string(), ChangeKind::Removed),
+                ("new".to_string(), ChangeKind::Added),
+            ]
+        );
+
+        // A file missing from a commit is not an error, a bad commit is
+        let lib = Path::new("lib.rs");
+        assert!(
+            git_show(&repo, &base, Path::new("other.rs"))
+                .unwrap()
+                .is_none()
+        );
+        assert!(git_show(&repo, &base, lib).unwrap().is_some());
+        assert!(git_show(&repo, &"0".repeat(40), lib).is_err());
+    }
+
+    #[test]
+    fn test_no_cleanup_keeps_the_clone_for_the_next_run() {
+        let temp = tempfile::tempdir().unwrap();
+        // The clone directory is named after the repository, keep it unique
+        let repo = temp.path().join(temp.path().file_name().unwrap());
+        let output = temp.path().join("out.jsonl");
+        fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "--quiet", "--initial-branch", "trunk"]);
+        commit(&repo, "fn first() {}");
+
+        let run_once = |extra: &[&str]| {
+            let mut argv = vec![
+                "rustcodesflow",
+                repo.to_str().unwrap(),
+                "--rev",
+                "trunk",
+                "-o",
+                output.to_str().unwrap(),
+            ];
+            argv.extend_from_slice(extra);
+            let cli = Cli::try_parse_from(argv).unwrap();
+            run(&cli.run).unwrap();
+            JsonlReader::open(&output)
+                .unwrap()
+                .map(|item| item.unwrap().item_meta.name)
+                .collect::<Vec<_>>()
+        };
+        let clone = clone_dir(repo.to_str().unwrap(), Some("trunk"))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(run_once(&["--no-cleanup"]), ["first"]);
+        assert!(clone.join(".git").exists());
+
+        // The kept clone is fetched and reset to the new commit
+        commit(&repo, "fn second() {}");
+        fs::write(clone.join("stale.rs"), "fn stale() {}").unwrap();
+        assert_eq!(run_once(&["--no-cleanup"]), ["second"]);
+        assert!(!clone.join("stale.rs").exists());
+
+        // Cleaning up is still the default
+        assert_eq!(run_once(&[]), ["second"]);
+        assert!(!clone.exists());
+    }
 }
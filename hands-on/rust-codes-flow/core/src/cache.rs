@@ -0,0 +1,305 @@
+use crate::extractor::rebase_items;
+use crate::models::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Version of the cache layout and of the items it holds
+///
+/// Bump whenever extraction, normalization or hashing output changes, so
+/// that stale items are not served from older caches.
+const CACHE_VERSION: u32 = 6;
+
+/// BLAKE3 hash of a file's contents, the key of the extraction cache
+pub fn content_hash(source: &str) -> String {
+    format!("blake3:{}", blake3::hash(source.as_bytes()).to_hex())
+}
+
+/// Sidecar cache mapping file content hashes to extracted items
+///
+/// Each repository gets one cache file in the cache directory. The file
+/// starts with a header line identifying the cache version and extraction
+/// settings, followed by one `<key>\t<JSON items>` line per file. The key
+/// combines the content hash with the file's crate and module, since those
+/// end up in fully qualified names.
+///
+/// Only the byte offsets of the previous run's entries are held in memory.
+/// Entries for the files seen in this run are written to a fresh file that
+/// replaces the previous one on `finish`, so the cache never keeps items of
+/// files that no longer exist.
+pub struct ExtractionCache {
+    path: PathBuf,
+    previous: Option<PreviousEntries>,
+    next: BufWriter<tempfile::NamedTempFile>,
+    stored: HashSet<String>,
+    hits: usize,
+    misses: usize,
+}
+
+/// Entries of the cache file written by the previous run
+struct PreviousEntries {
+    reader: BufReader<File>,
+    offsets: HashMap<String, u64>,
+}
+
+impl ExtractionCache {
+    /// Open the cache of `repo_url` in `cache_dir` for the given settings
+    ///
+    /// A missing cache file, or one written by another version or with other
+    /// settings, is treated as empty.
+    pub fn open(cache_dir: &Path, repo_url: &str, config: &ExtractConfig) -> CoreResult<Self> {
+        std::fs::create_dir_all(cache_dir)?;
+        let repo_key = blake3::hash(repo_url.as_bytes()).to_hex();
+        let path = cache_dir.join(format!("{}.cache", &repo_key[..16]));
+        let header = cache_header(config);
+
+        let previous = match File::open(&path) {
+            Ok(file) => PreviousEntries::load(file, &header)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut next = BufWriter::new(tempfile::NamedTempFile::new_in(cache_dir)?);
+        writeln!(next, "{header}")?;
+
+        Ok(Self {
+            path,
+            previous,
+            next,
+            stored: HashSet::new(),
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    /// Path of the cache file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Items cached for a file with this content hash in the same crate
+    /// and module, rebased onto the given file, repository and commit
+    pub fn lookup(
+        &mut self,
+        content_hash: &str,
+        context: &ProjectContext,
+    ) -> CoreResult<Option<Vec<ExtractedItem>>> {
+        let cached = match self.previous.as_mut() {
+            Some(previous) => previous.read(&entry_key(content_hash, context))?,
+            None => None,
+        };
+
+        match cached {
+            Some(mut items) => {
+                self.hits += 1;
+                rebase_items(&mut items, context);
+                Ok(Some(items))
+            }
+            None => {
+                self.misses += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Record the items of a file for the next run
+    pub fn store(
+        &mut self,
+        content_hash: &str,
+        context: &ProjectContext,
+        items: &[ExtractedItem],
+    ) -> CoreResult<()> {
+        let key = entry_key(content_hash, context);
+        if self.stored.insert(key.clone()) {
+            write!(self.next, "{key}\t")?;
+            serde_json::to_writer(&mut self.next, items)?;
+            self.next.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Number of lookups answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of lookups that required extraction
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Replace the previous cache file with the entries stored in this run
+    pub fn finish(self) -> CoreResult<()> {
+        let next = self
+            .next
+            .into_inner()
+            .map_err(|e| CoreError::Io(e.into_error()))?;
+        next.persist(&self.path)
+            .map_err(|e| CoreError::Io(e.error))?;
+        Ok(())
+    }
+}
+
+impl PreviousEntries {
+    /// Index the entries of a cache file, `None` if its header does not match
+    fn load(file: File, header: &str) -> CoreResult<Option<Self>> {
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim_end() != header {
+            return Ok(None);
+        }
+
+        // Only the key in front of the tab is read; the items are skipped
+        let mut offsets = HashMap::new();
+        let mut offset = line.len() as u64;
+        let mut entry = Vec::new();
+        loop {
+            entry.clear();
+            let read = reader.read_until(b'\n', &mut entry)?;
+            if read == 0 {
+                break;
+            }
+            if let Some(tab) = entry.iter().position(|&b| b == b'\t') {
+                let key = String::from_utf8_lossy(&entry[..tab]).into_owned();
+                offsets.insert(key, offset + tab as u64 + 1);
+            }
+            offset += read as u64;
+        }
+
+        Ok(Some(Self { reader, offsets }))
+    }
+
+    fn read(&mut self, content_hash: &str) -> CoreResult<Option<Vec<ExtractedItem>>> {
+        let Some(&offset) = self.offsets.get(content_hash) else {
+            return Ok(None);
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+/// Cache key of a file: its contents plus everything about its crate and
+/// module that shapes the extracted items
+fn entry_key(content_hash: &str, context: &ProjectContext) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(content_hash.as_bytes());
+    for field in [
+        context.crate_name.clone(),
+        context.crate_version.clone(),
+        context.target_kind.map(|kind| kind.to_string()),
+        context.edition.clone(),
+        context.module_path.clone(),
+    ] {
+        hasher.update(b"\0");
+        hasher.update(field.unwrap_or_default().as_bytes());
+    }
+    format!("blake3:{}", hasher.finalize().to_hex())
+}
+
+/// First line of a cache file: items are only reused when it matches
+fn cache_header(config: &ExtractConfig) -> String {
+    let fim = config.fim.as_ref().map_or_else(
+        || "none".to_string(),
+        |fim| serde_json::json!(fim).to_string(),
+    );
+    format!(
+        "rustcodesflow-cache v{} keep_docs={} include_context={} context_lines={} scrub={} fim={}",
+        CACHE_VERSION,
+        config.keep_docs,
+        config.include_context,
+        config.context_lines,
+        config.scrub,
+        fim
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::{extract_items_from_source, item_id};
+
+    fn context(file_path: &str, commit_hash: &str) -> ProjectContext {
+        ProjectContext {
+            repo_url: "repo".to_string(),
+            commit_hash: commit_hash.to_string(),
+            file_path: file_path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trip_rebases_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ExtractConfig::default();
+        let source = "struct S;\nimpl S {\n    fn f(&self) {}\n}\n";
+        let hash = content_hash(source);
+        let items = extract_items_from_source(
+            source,
+            "a.rs".to_string(),
+            "repo".to_string(),
+            "c1".to_string(),
+            &config,
+        )
+        .unwrap();
+
+        let mut cache = ExtractionCache::open(dir.path(), "repo", &config).unwrap();
+        assert!(
+            cache
+                .lookup(&hash, &context("a.rs", "c1"))
+                .unwrap()
+                .is_none()
+        );
+        cache.store(&hash, &context("a.rs", "c1"), &items).unwrap();
+        cache.finish().unwrap();
+
+        let mut cache = ExtractionCache::open(dir.path(), "repo", &config).unwrap();
+        let cached = cache
+            .lookup(&hash, &context("b.rs", "c2"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cached.len(), items.len());
+
+        let method = cached
+            .iter()
+            .find(|item| item.item_meta.kind == ItemKind::Method)
+            .unwrap();
+        let parent = cached
+            .iter()
+            .find(|item| item.item_meta.kind == ItemKind::Impl)
+            .unwrap();
+        assert_eq!(
+            method.item_meta.id,
+            item_id(&context("b.rs", "c2"), "S::f", &ItemKind::Method, 0)
+        );
+        assert_eq!(
+            method.item_meta.parent_id.as_ref(),
+            Some(&parent.item_meta.id)
+        );
+        assert_eq!(method.project_context.commit_hash, "c2");
+
+        // The same contents in another module are extracted again
+        let other_module = ProjectContext {
+            module_path: Some("krate::other".to_string()),
+            ..context("b.rs", "c2")
+        };
+        assert!(cache.lookup(&hash, &other_module).unwrap().is_none());
+
+        // Other settings do not reuse the cache
+        let keep_docs = ExtractConfig {
+            keep_docs: true,
+            ..Default::default()
+        };
+        let mut cache = ExtractionCache::open(dir.path(), "repo", &keep_docs).unwrap();
+        assert!(
+            cache
+                .lookup(&hash, &context("a.rs", "c1"))
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use crate::models::*;
+use crate::output::{Compression, ItemSink};
+use arrow_array::RecordBatch;
+use arrow_json::reader::{Decoder, ReaderBuilder};
+use arrow_schema::{DataType, Field, Fields, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{GzipLevel, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Arrow schema of an `ExtractedItem`, one nested struct per section
+///
+/// Field names and nesting follow the JSON serialization, so rows are
+/// converted through serde rather than by hand.
+pub fn item_schema() -> SchemaRef {
+    let utf8 = |name: &str| Field::new(name, DataType::Utf8, false);
+    let nullable_utf8 = |name: &str| Field::new(name, DataType::Utf8, true);
+    let uint32 = |name: &str| Field::new(name, DataType::UInt32, false);
+    let uint64 = |name: &str| Field::new(name, DataType::UInt64, false);
+    let boolean = |name: &str| Field::new(name, DataType::Boolean, false);
+    let utf8_list = |name: &str| {
+        let item = Field::new("item", DataType::Utf8, false);
+        Field::new(name, DataType::List(Arc::new(item)), false)
+    };
+    let section = |name: &str, fields: Vec<Field>| {
+        Field::new(name, DataType::Struct(Fields::from(fields)), false)
+    };
+
+    Arc::new(Schema::new(vec![
+        utf8("schema_version"),
+        section(
+            "project_context",
+            vec![
+                utf8("repo_url"),
+                utf8("commit_hash"),
+                utf8("file_path"),
+                nullable_utf8("crate_name"),
+                nullable_utf8("crate_version"),
+                nullable_utf8("target_kind"),
+                nullable_utf8("edition"),
+                nullable_utf8("module_path"),
+                nullable_utf8("license"),
+            ],
+        ),
+        section(
+            "item_meta",
+            vec![
+                utf8("id"),
+                nullable_utf8("parent_id"),
+                utf8("kind"),
+                utf8("name"),
+                utf8("fully_qualified_name"),
+                uint32("start_line"),
+                uint32("end_line"),
+                uint32("start_column"),
+                uint32("end_column"),
+                uint64("start_byte"),
+                uint64("end_byte"),
+                nullable_utf8("duplicate_of"),
+                nullable_utf8("change"),
+                utf8_list("redactions"),
+                section(
+                    "metrics",
+                    vec![
+                        uint32("token_count"),
+                        uint32("line_count"),
+                        uint32("cyclomatic_complexity"),
+                        uint32("nesting_depth"),
+                        boolean("is_test"),
+                        boolean("is_bench"),
+                        boolean("is_unsafe"),
+                        boolean("is_async"),
+                        boolean("is_const"),
+                        boolean("is_generated"),
+                    ],
+                ),
+                utf8_list("references"),
+                utf8_list("referenced_by"),
+            ],
+        ),
+        section(
+            "content",
+            vec![
+                utf8("signature"),
+                nullable_utf8("generics"),
+                utf8("body_normalized"),
+                utf8("semantic_hash"),
+                nullable_utf8("docstring"),
+                utf8_list("imports"),
+            ],
+        ),
+        section(
+            "rag_context",
+            vec![
+                nullable_utf8("context_before"),
+                nullable_utf8("context_after"),
+            ],
+        ),
+        Field::new(
+            "fim",
+            DataType::Struct(Fields::from(vec![
+                utf8("order"),
+                utf8("span_kind"),
+                utf8("prefix"),
+                utf8("middle"),
+                utf8("suffix"),
+                utf8("prompt"),
+            ])),
+            true,
+        ),
+    ]))
+}
+
+/// Settings shared by the columnar writers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnarOptions {
+    /// Rows per record batch, and per row group for Parquet
+    pub batch_size: usize,
+    pub compression: Compression,
+}
+
+impl Default for ColumnarOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 8192,
+            compression: Compression::None,
+        }
+    }
+}
+
+/// Collects items into record batches of a fixed number of rows
+struct BatchEncoder {
+    decoder: Decoder,
+    batch_size: usize,
+}
+
+impl BatchEncoder {
+    fn new(batch_size: usize) -> CoreResult<Self> {
+        // Strict mode rejects fields missing from the schema, so the schema
+        // cannot silently fall behind the models
+        let decoder = ReaderBuilder::new(item_schema())
+            .with_strict_mode(true)
+            .build_decoder()?;
+        Ok(Self {
+            decoder,
+            batch_size: batch_size.max(1),
+        })
+    }
+
+    /// Add an item, returning a batch once `batch_size` rows are buffered
+    fn push(&mut self, item: &ExtractedItem) -> CoreResult<Option<RecordBatch>> {
+        self.decoder.serialize(std::slice::from_ref(item))?;
+        if self.decoder.len() >= self.batch_size {
+            return self.flush();
+        }
+        Ok(None)
+    }
+
+    fn flush(&mut self) -> CoreResult<Option<RecordBatch>> {
+        Ok(self.decoder.flush()?)
+    }
+}
+
+/// Writes items to a Parquet file, one row group per batch
+pub struct ParquetWriter {
+    encoder: BatchEncoder,
+    writer: Option<ArrowWriter<File>>,
+}
+
+impl ParquetWriter {
+    pub fn create(path: &Path, options: ColumnarOptions) -> CoreResult<Self> {
+        let mut properties =
+            WriterProperties::builder().set_max_row_group_size(options.batch_size.max(1));
+        match options.compression {
+            Compression::None => {}
+            Compression::Gzip => {
+                properties = properties
+                    .set_compression(parquet::basic::Compression::GZIP(GzipLevel::default()));
+            }
+            Compression::Zstd => {
+                properties = properties
+                    .set_compression(parquet::basic::Compression::ZSTD(ZstdLevel::default()));
+            }
+        }
+
+        let writer =
+            ArrowWriter::try_new(File::create(path)?, item_schema(), Some(properties.build()))?;
+        Ok(Self {
+            encoder: BatchEncoder::new(options.batch_size)?,
+            writer: Some(writer),
+        })
+    }
+
+    fn writer(&mut self) -> CoreResult<&mut ArrowWriter<File>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| CoreError::InvalidInput("Parquet writer already finished".into()))
+    }
+}
+
+impl ItemSink for ParquetWriter {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        if let Some(batch) = self.encoder.push(item)? {
+            self.writer()?.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        if let Some(batch) = self.encoder.flush()? {
+            self.writer()?.write(&batch)?;
+        }
+        if let Some(writer) = self.writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes items to an Arrow IPC file, one record batch per `batch_size` rows
+pub struct ArrowIpcWriter {
+    encoder: BatchEncoder,
+    writer: arrow_ipc::writer::FileWriter<BufWriter<File>>,
+}
+
+impl ArrowIpcWriter {
+    pub fn create(path: &Path, options: ColumnarOptions) -> CoreResult<Self> {
+        let compression = match options.compression {
+            Compression::None => None,
+            Compression::Gzip => {
+                return Err(CoreError::InvalidInput(
+                    "Arrow IPC supports zstd compression only".to_string(),
+                ));
+            }
+            Compression::Zstd => Some(arrow_ipc::CompressionType::ZSTD),
+        };
+        let write_options =
+            arrow_ipc::writer::IpcWriteOptions::default().try_with_compression(compression)?;
+
+        let writer = arrow_ipc::writer::FileWriter::try_new_with_options(
+            BufWriter::new(File::create(path)?),
+            &item_schema(),
+            write_options,
+        )?;
+        Ok(Self {
+            encoder: BatchEncoder::new(options.batch_size)?,
+            writer,
+        })
+    }
+}
+
+impl ItemSink for ArrowIpcWriter {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        if let Some(batch) = self.encoder.push(item)? {
+            self.writer.write(&batch)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        if let Some(batch) = self.encoder.flush()? {
+            self.writer.write(&batch)?;
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn items() -> Vec<ExtractedItem> {
+        (0..5)
+            .map(|index| ExtractedItem {
+                item_meta: ItemMeta {
+                    id: format!("lib.rs:{index}:0"),
+                    parent_id: (index > 0).then(|| "lib.rs:0:0".to_string()),
+                    change: Some(ChangeKind::Added),
+                    ..Default::default()
+                },
+                content: Content {
+                    imports: vec!["use std::fmt".to_string()],
+                    ..Default::default()
+                },
+                fim: (index == 1).then(|| FimSample {
+                    order: FimOrder::Psm,
+                    span_kind: FimSpanKind::Statement,
+                    prefix: "fn f() { ".to_string(),
+                    middle: "g();".to_string(),
+                    suffix: " }".to_string(),
+                    prompt: "<fim_prefix>fn f() { <fim_suffix> }<fim_middle>".to_string(),
+                }),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parquet_and_ipc_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = ColumnarOptions {
+            batch_size: 2,
+            compression: Compression::Zstd,
+        };
+
+        let parquet_path = dir.path().join("items.parquet");
+        let mut writer = ParquetWriter::create(&parquet_path, options).unwrap();
+        for item in &items() {
+            writer.write_item(item).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&parquet_path).unwrap()).unwrap();
+        assert_eq!(builder.metadata().num_row_groups(), 3);
+        assert_eq!(builder.schema().fields(), item_schema().fields());
+        let rows: usize = builder
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum();
+        assert_eq!(rows, 5);
+
+        let ipc_path = dir.path().join("items.arrow");
+        let mut writer = ArrowIpcWriter::create(&ipc_path, options).unwrap();
+        for item in &items() {
+            writer.write_item(item).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(File::open(&ipc_path).unwrap(), None).unwrap();
+        let batches: Vec<RecordBatch> = reader.map(|batch| batch.unwrap()).collect();
+        assert_eq!(batches.len(), 3);
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 5);
+    }
+}
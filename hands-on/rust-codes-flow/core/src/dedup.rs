@@ -0,0 +1,209 @@
+use crate::hashing::semantic_hash_item;
+use crate::models::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// How items with identical semantic hashes are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupMode {
+    /// Write every item as is
+    #[default]
+    None,
+    /// Keep every item, tagging duplicates with `duplicate_of`
+    Exact,
+    /// Drop duplicates, keeping the first occurrence
+    KeepFirst,
+    /// Drop duplicates, keeping the occurrence with the shortest file path
+    KeepShortestPath,
+}
+
+impl fmt::Display for DedupMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DedupMode::None => write!(f, "none"),
+            DedupMode::Exact => write!(f, "exact"),
+            DedupMode::KeepFirst => write!(f, "keep-first"),
+            DedupMode::KeepShortestPath => write!(f, "keep-shortest-path"),
+        }
+    }
+}
+
+/// Canonical item of a group of identical hashes
+struct Canonical {
+    id: String,
+    path_len: usize,
+}
+
+/// Streaming exact deduplication on semantic hashes
+///
+/// Only the canonical id per distinct hash is remembered. `KeepFirst` and
+/// `Exact` decide on the fly; `KeepShortestPath` needs every item to be
+/// `observe`d in a first pass before `apply` is called in a second one.
+pub struct Deduplicator {
+    mode: DedupMode,
+    canonical: HashMap<String, Canonical>,
+    duplicates: usize,
+}
+
+impl Deduplicator {
+    pub fn new(mode: DedupMode) -> Self {
+        Self {
+            mode,
+            canonical: HashMap::new(),
+            duplicates: 0,
+        }
+    }
+
+    pub fn mode(&self) -> DedupMode {
+        self.mode
+    }
+
+    /// Whether all items must be `observe`d before the first `apply`
+    pub fn needs_prepass(&self) -> bool {
+        self.mode == DedupMode::KeepShortestPath
+    }
+
+    /// Register an item as a canonical candidate (first pass)
+    pub fn observe(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        let hash = item_hash(item)?;
+        let path_len = item.project_context.file_path.len();
+
+        let shorter = self
+            .canonical
+            .get(&hash)
+            .is_none_or(|canonical| path_len < canonical.path_len);
+        if shorter {
+            self.canonical.insert(
+                hash,
+                Canonical {
+                    id: item.item_meta.id.clone(),
+                    path_len,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Decide what happens to an item: `None` drops it, otherwise the item
+    /// is returned, tagged with `duplicate_of` in `Exact` mode.
+    pub fn apply(&mut self, mut item: ExtractedItem) -> CoreResult<Option<ExtractedItem>> {
+        if self.mode == DedupMode::None {
+            return Ok(Some(item));
+        }
+
+        let hash = item_hash(&item)?;
+        let canonical_id = match self.canonical.get(&hash) {
+            Some(canonical) => canonical.id.clone(),
+            None => {
+                self.canonical.insert(
+                    hash,
+                    Canonical {
+                        id: item.item_meta.id.clone(),
+                        path_len: item.project_context.file_path.len(),
+                    },
+                );
+                return Ok(Some(item));
+            }
+        };
+
+        if canonical_id == item.item_meta.id {
+            return Ok(Some(item));
+        }
+
+        self.duplicates += 1;
+        match self.mode {
+            DedupMode::Exact => {
+                item.item_meta.duplicate_of = Some(canonical_id);
+                Ok(Some(item))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Number of duplicates tagged or dropped so far
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+}
+
+/// Semantic hash of an item, computed if the item was not hashed yet
+fn item_hash(item: &ExtractedItem) -> CoreResult<String> {
+    if item.content.semantic_hash.is_empty() {
+        semantic_hash_item(item)
+    } else {
+        Ok(item.content.semantic_hash.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(id: &str, path: &str, hash: &str) -> ExtractedItem {
+        ExtractedItem {
+            project_context: ProjectContext {
+                file_path: path.to_string(),
+                ..Default::default()
+            },
+            item_meta: ItemMeta {
+                id: id.to_string(),
+                ..Default::default()
+            },
+            content: Content {
+                semantic_hash: hash.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn run(mode: DedupMode, items: Vec<ExtractedItem>) -> Vec<(String, Option<String>)> {
+        let mut dedup = Deduplicator::new(mode);
+        if dedup.needs_prepass() {
+            for item in &items {
+                dedup.observe(item).unwrap();
+            }
+        }
+        items
+            .into_iter()
+            .filter_map(|item| dedup.apply(item).unwrap())
+            .map(|item| (item.item_meta.id, item.item_meta.duplicate_of))
+            .collect()
+    }
+
+    fn items() -> Vec<ExtractedItem> {
+        vec![
+            item("a", "vendor/copy/src/lib.rs", "h1"),
+            item("b", "src/lib.rs", "h1"),
+            item("c", "src/other.rs", "h2"),
+            item("d", "src/x.rs", "h1"),
+        ]
+    }
+
+    #[test]
+    fn test_exact_tags_duplicates() {
+        assert_eq!(
+            run(DedupMode::Exact, items()),
+            [
+                ("a".to_string(), None),
+                ("b".to_string(), Some("a".to_string())),
+                ("c".to_string(), None),
+                ("d".to_string(), Some("a".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keep_modes_drop_duplicates() {
+        let ids = |kept: Vec<(String, Option<String>)>| -> Vec<String> {
+            kept.into_iter().map(|(id, _)| id).collect()
+        };
+
+        assert_eq!(ids(run(DedupMode::None, items())), ["a", "b", "c", "d"]);
+        assert_eq!(ids(run(DedupMode::KeepFirst, items())), ["a", "c"]);
+        assert_eq!(ids(run(DedupMode::KeepShortestPath, items())), ["c", "d"]);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::models::*;
+use std::collections::HashMap;
+
+/// Key matching an item across two versions of a file: kind, fully
+/// qualified name and the ordinal among items sharing both
+type ItemKey = (ItemKind, String, usize);
+
+fn keyed(items: Vec<ExtractedItem>) -> Vec<(ItemKey, ExtractedItem)> {
+    let mut ordinals: HashMap<(ItemKind, String), usize> = HashMap::new();
+    items
+        .into_iter()
+        .map(|item| {
+            let name = (
+                item.item_meta.kind.clone(),
+                item.item_meta.fully_qualified_name.clone(),
+            );
+            let ordinal = ordinals.entry(name.clone()).or_insert(0);
+            let key = (name.0, name.1, *ordinal);
+            *ordinal += 1;
+            (key, item)
+        })
+        .collect()
+}
+
+/// Compare the items of one file at two commits
+///
+/// Items are matched by kind and fully qualified name. Returns the added
+/// and changed items of `new` in order, followed by the removed items of
+/// `old`, each tagged with its `change`. Unchanged items are left out.
+pub fn diff_items(old: Vec<ExtractedItem>, new: Vec<ExtractedItem>) -> Vec<ExtractedItem> {
+    let mut old: HashMap<ItemKey, ExtractedItem> = keyed(old).into_iter().collect();
+    let mut changes = Vec::new();
+
+    for (key, mut item) in keyed(new) {
+        let change = match old.remove(&key) {
+            None => ChangeKind::Added,
+            Some(previous) if previous.content != item.content => ChangeKind::Changed,
+            Some(_) => continue,
+        };
+        item.item_meta.change = Some(change);
+        changes.push(item);
+    }
+
+    // Keep removed items in source order for stable output
+    let mut removed: Vec<ExtractedItem> = old.into_values().collect();
+    removed.sort_by_key(|item| item.item_meta.start_byte);
+    for mut item in removed {
+        item.item_meta.change = Some(ChangeKind::Removed);
+        changes.push(item);
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::extract_items_from_source;
+    use crate::hashing::hash_and_update_items;
+    use crate::normalizer::normalize_items;
+
+    fn items(source: &str) -> Vec<ExtractedItem> {
+        let config = ExtractConfig::default();
+        let mut items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "repo".to_string(),
+            "commit".to_string(),
+            &config,
+        )
+        .unwrap();
+        normalize_items(&mut items, &config).unwrap();
+        hash_and_update_items(&mut items).unwrap();
+        items
+    }
+
+    #[test]
+    fn test_diff_reports_added_changed_and_removed() {
+        let old = items("fn kept() {}\nfn edited() -> u8 { 1 }\nfn dropped() {}\n");
+        let new = items("fn kept() {}\n\nfn edited() -> u8 { 2 }\nfn created() {}\n");
+
+        let changes: Vec<(String, ChangeKind)> = diff_items(old, new)
+            .into_iter()
+            .map(|item| (item.item_meta.name, item.item_meta.change.unwrap()))
+            .collect();
+
+        assert_eq!(
+            changes,
+            [
+                ("edited".to_string(), ChangeKind::Changed),
+                ("created".to_string(), ChangeKind::Added),
+                ("dropped".to_string(), ChangeKind::Removed),
+            ]
+        );
+    }
+}
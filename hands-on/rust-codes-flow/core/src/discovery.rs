@@ -0,0 +1,309 @@
+use crate::models::*;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Excluded unless `DiscoveryOptions::default_excludes` is turned off:
+/// build output and vendored dependencies
+pub const DEFAULT_EXCLUDES: &[&str] = &["**/target/**", "**/vendor/**"];
+
+/// Which `.rs` files of a repository are processed
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    /// Globs relative to the root; when non-empty, only matching files are kept
+    pub include: Vec<String>,
+    /// Globs relative to the root of files to leave out
+    pub exclude: Vec<String>,
+    /// Also exclude `DEFAULT_EXCLUDES`
+    pub default_excludes: bool,
+    /// Skip files that look generated, see `FileFilter::is_generated`
+    pub skip_generated: bool,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            default_excludes: true,
+            skip_generated: false,
+        }
+    }
+}
+
+/// Result of walking a repository
+#[derive(Debug, Default)]
+pub struct Discovery {
+    /// Selected files, sorted
+    pub files: Vec<PathBuf>,
+    /// Number of files left out by `skip_generated`
+    pub skipped_generated: usize,
+}
+
+/// Compiled include and exclude globs
+pub struct FileFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileFilter {
+    pub fn new(options: &DiscoveryOptions) -> CoreResult<Self> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(glob_set(options.include.iter().map(String::as_str))?)
+        };
+
+        let defaults = if options.default_excludes {
+            DEFAULT_EXCLUDES
+        } else {
+            &[]
+        };
+        let exclude = glob_set(
+            defaults
+                .iter()
+                .copied()
+                .chain(options.exclude.iter().map(String::as_str)),
+        )?;
+
+        Ok(Self { include, exclude })
+    }
+
+    /// Whether a path relative to the repository root passes the globs
+    pub fn is_selected(&self, relative: &Path) -> bool {
+        let included = self
+            .include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative));
+        included && !self.exclude.is_match(relative)
+    }
+
+    /// Whether a source file looks generated: an `@generated` marker near
+    /// the top, as written by most code generators
+    pub fn is_generated(source: &str) -> bool {
+        source
+            .lines()
+            .take(GENERATED_HEADER_LINES)
+            .any(|line| line.contains("@generated"))
+    }
+}
+
+/// Lines searched for the `@generated` marker
+const GENERATED_HEADER_LINES: usize = 10;
+
+/// `include!("path.rs")`
+static INCLUDE_LITERAL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"include!\s*\(\s*"([^"]+)"\s*\)"#).expect("valid regex"));
+
+/// `include!(concat!(env!("OUT_DIR"), "/path.rs"))`
+static INCLUDE_OUT_DIR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"env!\s*\(\s*"OUT_DIR"\s*\)\s*,\s*"/?([^"]+)""#).expect("valid regex")
+});
+
+/// Walk the files under `root` as discovery sees them: ignore files are
+/// honoured and hidden directories visited, except git's own `.git`
+pub(crate) fn walk(root: &Path) -> ignore::Walk {
+    ignore::WalkBuilder::new(root)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build()
+}
+
+/// Find the `.rs` files under `root`
+///
+/// `.gitignore`, `.ignore` and git's global and `info/exclude` rules are
+/// honoured (also outside of a git checkout), then the include and exclude
+/// globs are applied. With `skip_generated`, files with an `@generated`
+/// header, files pulled in by another file's `include!`, and build script
+/// outputs (`OUT_DIR`) are dropped.
+pub fn find_rust_files(root: &Path, options: &DiscoveryOptions) -> CoreResult<Discovery> {
+    let filter = FileFilter::new(options)?;
+
+    let mut files = Vec::new();
+    for entry in walk(root) {
+        let entry = entry.map_err(|e| CoreError::InvalidInput(e.to_string()))?;
+        let path = entry.path();
+        if !entry.file_type().is_some_and(|kind| kind.is_file())
+            || path.extension().and_then(|s| s.to_str()) != Some("rs")
+        {
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        if filter.is_selected(relative) {
+            files.push(path.to_path_buf());
+        }
+    }
+    files.sort();
+
+    if !options.skip_generated {
+        return Ok(Discovery {
+            files,
+            skipped_generated: 0,
+        });
+    }
+
+    let mut generated = HashSet::new();
+    let mut out_dir_names = HashSet::new();
+    for file in &files {
+        let Ok(source) = std::fs::read_to_string(file) else {
+            continue;
+        };
+        if FileFilter::is_generated(&source) {
+            generated.insert(file.clone());
+        }
+        let dir = file.parent().unwrap_or(root);
+        for capture in INCLUDE_LITERAL.captures_iter(&source) {
+            generated.insert(normalize(&dir.join(&capture[1])));
+        }
+        for capture in INCLUDE_OUT_DIR.captures_iter(&source) {
+            out_dir_names.insert(capture[1].to_string());
+        }
+    }
+
+    let total = files.len();
+    files.retain(|file| {
+        !generated.contains(&normalize(file))
+            && !is_out_dir_file(file.strip_prefix(root).unwrap_or(file), &out_dir_names)
+    });
+
+    Ok(Discovery {
+        skipped_generated: total - files.len(),
+        files,
+    })
+}
+
+/// Whether a file lives in a build script's `OUT_DIR` (`build/<crate>/out/`)
+/// or is named like a file that is `include!`d from `OUT_DIR`
+fn is_out_dir_file(relative: &Path, out_dir_names: &HashSet<String>) -> bool {
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    let in_out_dir = components
+        .windows(3)
+        .any(|window| window[0] == "build" && window[2] == "out");
+
+    in_out_dir
+        || components
+            .iter()
+            .rposition(|c| c == "out")
+            .is_some_and(|out| {
+                let rest = components[out + 1..].join("/");
+                out_dir_names.contains(&rest)
+            })
+}
+
+/// Resolve `.` and `..` without touching the file system
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+fn glob_set<'a>(globs: impl Iterator<Item = &'a str>) -> CoreResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob = Glob::new(glob)
+            .map_err(|e| CoreError::InvalidInput(format!("invalid glob `{glob}`: {e}")))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| CoreError::InvalidInput(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn relative(root: &Path, discovery: &Discovery) -> Vec<String> {
+        discovery
+            .files
+            .iter()
+            .map(|file| {
+                file.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ignore_files_and_globs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(root, ".gitignore", "ignored/\n");
+        write(root, "src/lib.rs", "");
+        write(root, "src/fixtures/case.rs", "");
+        write(root, "ignored/a.rs", "");
+        write(root, "target/debug/build/x/out/gen.rs", "");
+        write(root, "vendor/dep/src/lib.rs", "");
+        write(root, "tests/it.rs", "");
+        write(root, ".git/hooks/hook.rs", "");
+        write(root, ".cargo/config.rs", "");
+
+        let discovery = find_rust_files(root, &DiscoveryOptions::default()).unwrap();
+        assert_eq!(
+            relative(root, &discovery),
+            [
+                ".cargo/config.rs",
+                "src/fixtures/case.rs",
+                "src/lib.rs",
+                "tests/it.rs"
+            ]
+        );
+
+        let options = DiscoveryOptions {
+            include: vec!["src/**".to_string()],
+            exclude: vec!["**/fixtures/**".to_string()],
+            ..Default::default()
+        };
+        let discovery = find_rust_files(root, &options).unwrap();
+        assert_eq!(relative(root, &discovery), ["src/lib.rs"]);
+    }
+
+    #[test]
+    fn test_skip_generated() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(
+            root,
+            "src/lib.rs",
+            "include!(\"tables.rs\");\ninclude!(concat!(env!(\"OUT_DIR\"), \"/bindings.rs\"));\n",
+        );
+        write(root, "src/tables.rs", "const T: [u8; 1] = [0];");
+        write(
+            root,
+            "src/proto.rs",
+            "// @generated by protoc\npub struct M;",
+        );
+        write(root, "src/main.rs", "fn main() {}");
+        write(root, "gen/out/bindings.rs", "pub struct B;");
+
+        let options = DiscoveryOptions {
+            skip_generated: true,
+            ..Default::default()
+        };
+        let discovery = find_rust_files(root, &options).unwrap();
+        assert_eq!(relative(root, &discovery), ["src/lib.rs", "src/main.rs"]);
+        assert_eq!(discovery.skipped_generated, 3);
+    }
+}
@@ -1,18 +1,137 @@
+use crate::discovery::FileFilter;
+use crate::fim::fim_sample;
+use crate::metrics::{Measure, line_count, measure};
 use crate::models::*;
-use quote::ToTokens;
-use std::collections::HashMap;
+use crate::normalizer::{Import, collect_imports};
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::{ToTokens, quote};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use syn::{self, visit_mut::VisitMut};
+use syn::{self, spanned::Spanned, visit_mut::VisitMut};
+
+/// Source range of an item, resolved from its span
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub start_line: u32,
+    pub end_line: u32,
+    pub start_column: u32,
+    pub end_column: u32,
+    pub start_byte: usize,
+    pub end_byte: usize,
+}
+
+impl Location {
+    /// Resolve a span into line, column and byte positions.
+    ///
+    /// Relies on proc-macro2's `span-locations` feature, which records
+    /// positions for every token parsed outside of a procedural macro.
+    pub fn from_span(span: Span) -> Self {
+        let start = span.start();
+        let end = span.end();
+        let bytes = span.byte_range();
+
+        Self {
+            start_line: start.line as u32,
+            end_line: end.line as u32,
+            start_column: start.column as u32,
+            end_column: end.column as u32,
+            start_byte: bytes.start,
+            end_byte: bytes.end,
+        }
+    }
+}
+
+/// Stable identifier of an item: 32 hex digits of the BLAKE3 hash of its
+/// repository, commit, file path, fully qualified name and kind.
+///
+/// `occurrence` counts the earlier items of the file with the same name and
+/// kind, e.g. `#[cfg]` variants of a function or several `impl` blocks of a
+/// type, so that they get distinct ids. Unlike line numbers, none of this
+/// moves when unrelated code is edited, and the same item of the same
+/// commit gets the same id in every run.
+pub fn item_id(
+    context: &ProjectContext,
+    fully_qualified_name: &str,
+    kind: &ItemKind,
+    occurrence: usize,
+) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for field in [
+        context.repo_url.as_str(),
+        context.commit_hash.as_str(),
+        context.file_path.as_str(),
+        fully_qualified_name,
+        &kind.to_string(),
+        &occurrence.to_string(),
+    ] {
+        hasher.update(field.as_bytes());
+        hasher.update(b"\0");
+    }
+    hasher.finalize().to_hex()[..32].to_string()
+}
+
+/// Number of items of a file seen so far per name and kind
+#[derive(Default)]
+struct Occurrences(HashMap<(String, ItemKind), usize>);
+
+impl Occurrences {
+    fn next(&mut self, fully_qualified_name: &str, kind: &ItemKind) -> usize {
+        let count = self
+            .0
+            .entry((fully_qualified_name.to_string(), kind.clone()))
+            .or_insert(0);
+        *count += 1;
+        *count - 1
+    }
+}
+
+/// Move items extracted from one file to another file, repository or commit
+///
+/// Used for cached items whose source is unchanged but whose context is
+/// not: the context is replaced and ids (and `parent_id` links) re-derived.
+/// `items` must be all the items of the file, in extraction order.
+pub fn rebase_items(items: &mut [ExtractedItem], context: &ProjectContext) {
+    let mut new_ids = HashMap::new();
+    let mut occurrences = Occurrences::default();
+    for item in items.iter_mut() {
+        let meta = &item.item_meta;
+        let occurrence = occurrences.next(&meta.fully_qualified_name, &meta.kind);
+        let id = item_id(context, &meta.fully_qualified_name, &meta.kind, occurrence);
+        new_ids.insert(std::mem::replace(&mut item.item_meta.id, id.clone()), id);
+        item.project_context = context.clone();
+    }
+
+    for item in items.iter_mut() {
+        if let Some(parent_id) = item.item_meta.parent_id.as_mut()
+            && let Some(new_id) = new_ids.get(parent_id)
+        {
+            *parent_id = new_id.clone();
+        }
+    }
+}
+
+/// Enclosing impl or trait block that methods are attached to
+struct ParentItem {
+    /// Qualified path of the block, e.g. `module::Type` or `module::<Type as Trait>`
+    path: String,
+    id: String,
+}
 
 /// Visitor that extracts Rust code items from AST
 pub struct ExtractVisitor<'ast> {
     pub items: Vec<ExtractedItem>,
-    current_file: Option<String>,
-    repo_url: Option<String>,
-    commit_hash: Option<String>,
+    context: ProjectContext,
     current_module: Vec<String>,
-    line_mapping: HashMap<usize, usize>, // byte pos -> line number
+    parents: Vec<ParentItem>,
+    // Imports declared in the file and in each enclosing inline module
+    import_scopes: Vec<Vec<Import>>,
+    occurrences: Occurrences,
+    source: String,
     source_lines: Vec<String>,
+    // Whether the source has an `@generated` marker
+    generated_source: bool,
+    // Whether an enclosing item is test-only, e.g. a `#[cfg(test)]` module
+    in_test: bool,
     config: &'ast ExtractConfig,
 }
 
@@ -20,35 +139,182 @@ impl<'ast> ExtractVisitor<'ast> {
     pub fn new(config: &'ast ExtractConfig) -> Self {
         Self {
             items: Vec::new(),
-            current_file: None,
-            repo_url: None,
-            commit_hash: None,
+            context: ProjectContext::default(),
             current_module: Vec::new(),
-            line_mapping: HashMap::new(),
+            parents: Vec::new(),
+            import_scopes: Vec::new(),
+            occurrences: Occurrences::default(),
+            source: String::new(),
             source_lines: Vec::new(),
+            generated_source: false,
+            in_test: false,
             config,
         }
     }
 
     pub fn set_source(&mut self, source: &str) {
-        self.line_mapping.clear();
-        self.source_lines.clear();
+        self.source = source.to_string();
+        self.source_lines = source.lines().map(str::to_string).collect();
+        self.generated_source = FileFilter::is_generated(source);
+    }
 
-        // Build line mapping
-        let mut byte_pos = 0;
-        let lines: Vec<&str> = source.lines().collect();
+    pub fn set_file_info(&mut self, file_path: String, repo_url: String, commit_hash: String) {
+        self.set_context(ProjectContext {
+            repo_url,
+            commit_hash,
+            file_path,
+            ..Default::default()
+        });
+    }
+
+    /// Set the context copied into every item; names are qualified with
+    /// its `module_path`
+    pub fn set_context(&mut self, context: ProjectContext) {
+        self.current_module = context
+            .module_path
+            .iter()
+            .flat_map(|path| path.split("::"))
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect();
+        self.occurrences = Occurrences::default();
+        self.context = context;
+    }
 
-        for (line_num, line) in lines.iter().enumerate() {
-            self.line_mapping.insert(byte_pos, line_num + 1);
-            self.source_lines.push(line.to_string());
-            byte_pos += line.len() + 1; // +1 for newline
+    /// Build the fully qualified name of an item in the current module
+    fn qualify(&self, name: &str) -> String {
+        if self.current_module.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}::{}", self.current_module.join("::"), name)
         }
     }
 
-    pub fn set_file_info(&mut self, file_path: String, repo_url: String, commit_hash: String) {
-        self.current_file = Some(file_path);
-        self.repo_url = Some(repo_url);
-        self.commit_hash = Some(commit_hash);
+    /// Imports of the enclosing module that are referenced by `tokens`.
+    ///
+    /// Only the innermost module counts: an inline module does not inherit
+    /// the imports of its parent, and a module item gets the imports of its
+    /// own body. `use` declarations inside function bodies are not tracked.
+    /// Named imports are kept when their bound
+    /// name appears anywhere in the tokens (macro input included). Globs and
+    /// `as _` imports cannot be checked by name and are always kept.
+    fn referenced_imports(&self, tokens: &TokenStream) -> Vec<String> {
+        let mut idents = HashSet::new();
+        collect_idents(tokens.clone(), &mut idents);
+
+        let mut imports: Vec<String> = Vec::new();
+        for import in self.import_scopes.last().into_iter().flatten() {
+            let referenced = import
+                .binding
+                .as_ref()
+                .is_none_or(|binding| idents.contains(binding));
+            if referenced && !imports.contains(&import.declaration) {
+                imports.push(import.declaration.clone());
+            }
+        }
+
+        imports
+    }
+
+    /// Record `node` as an extracted item and return its id.
+    ///
+    /// The body is the node's full token stream and the location is taken
+    /// from its span. Methods are linked to the innermost enclosing impl or
+    /// trait block.
+    fn push_item<T: Measure>(
+        &mut self,
+        kind: ItemKind,
+        name: String,
+        fully_qualified_name: String,
+        node: &T,
+        signature: String,
+        generics: Option<String>,
+    ) -> String {
+        let location = Location::from_span(node.span());
+        let tokens = node.to_token_stream();
+        let occurrence = self.occurrences.next(&fully_qualified_name, &kind);
+        let id = item_id(&self.context, &fully_qualified_name, &kind, occurrence);
+
+        let parent_id = match kind {
+            ItemKind::Method => self.parents.last().map(|parent| parent.id.clone()),
+            _ => None,
+        };
+
+        let mut metrics = measure(node);
+        metrics.line_count = line_count(
+            self.source_lines
+                .get(location.start_line.saturating_sub(1) as usize..location.end_line as usize)
+                .unwrap_or(&[]),
+        );
+        metrics.is_test |= self.in_test || self.context.target_kind == Some(TargetKind::Test);
+        metrics.is_bench |= self.context.target_kind == Some(TargetKind::Bench);
+        metrics.is_generated |= self.generated_source;
+
+        let item_meta = ItemMeta {
+            id: id.clone(),
+            parent_id,
+            kind,
+            name,
+            fully_qualified_name,
+            start_line: location.start_line,
+            end_line: location.end_line,
+            start_column: location.start_column,
+            end_column: location.end_column,
+            start_byte: location.start_byte,
+            end_byte: location.end_byte,
+            duplicate_of: None,
+            change: None,
+            redactions: Vec::new(),
+            metrics,
+            references: Vec::new(),
+            referenced_by: Vec::new(),
+        };
+
+        let content = Content {
+            signature,
+            generics,
+            body_normalized: tokens.to_string(),
+            semantic_hash: String::new(),
+            docstring: None,
+            imports: self.referenced_imports(&tokens),
+        };
+
+        let rag_context =
+            self.extract_context(location.start_line as usize, location.end_line as usize);
+
+        self.items.push(ExtractedItem {
+            schema_version: SCHEMA_VERSION.to_string(),
+            project_context: self.context.clone(),
+            item_meta,
+            content,
+            rag_context,
+            fim: None,
+        });
+
+        id
+    }
+
+    /// Attach a FIM sample to the function just pushed, whose body is `block`
+    fn attach_fim(&mut self, block: &syn::Block) {
+        let Some(config) = &self.config.fim else {
+            return;
+        };
+        let Some(item) = self.items.last_mut() else {
+            return;
+        };
+        let meta = &item.item_meta;
+        item.fim = fim_sample(&self.source, meta.start_byte..meta.end_byte, block, config);
+    }
+
+    /// Mark everything visited until the returned flag is restored as test
+    /// code if the item just pushed is a test, e.g. a `#[cfg(test)]` module
+    fn enter_test_scope(&mut self) -> bool {
+        let outer = self.in_test;
+        self.in_test |= self
+            .items
+            .last()
+            .is_some_and(|item| item.item_meta.metrics.is_test);
+        outer
     }
 
     fn extract_context(&self, start_line: usize, end_line: usize) -> RagContext {
@@ -101,162 +367,350 @@ impl<'ast> ExtractVisitor<'ast> {
 }
 
 impl<'ast> VisitMut for ExtractVisitor<'ast> {
+    fn visit_file_mut(&mut self, file: &mut syn::File) {
+        self.import_scopes.push(collect_imports(&file.items));
+        syn::visit_mut::visit_file_mut(self, file);
+        self.import_scopes.pop();
+    }
+
     fn visit_item_fn_mut(&mut self, item: &mut syn::ItemFn) {
-        let start_line = 1; // Placeholder: Real implementation requires span-to-line mapping
-        let end_line = 1;
+        self.push_item(
+            ItemKind::Function,
+            item.sig.ident.to_string(),
+            self.qualify(&item.sig.ident.to_string()),
+            item,
+            item.sig.clone().into_token_stream().to_string(),
+            generics_string(&item.sig.generics),
+        );
+        self.attach_fim(&item.block);
 
-        let item_meta = ItemMeta {
-            kind: ItemKind::Function,
-            name: item.sig.ident.to_string(),
-            fully_qualified_name: format!("{}::{}", self.current_module.join("::"), item.sig.ident),
-            start_line: start_line as u32,
-            end_line: end_line as u32,
-        };
+        let in_test = self.enter_test_scope();
+        syn::visit_mut::visit_item_fn_mut(self, item);
+        self.in_test = in_test;
+    }
 
-        let content = Content {
-            signature: item.sig.clone().into_token_stream().to_string(),
-            body_normalized: item.clone().into_token_stream().to_string(),
-            semantic_hash: String::new(),
-            docstring: None,
-            imports: Vec::new(),
+    fn visit_item_impl_mut(&mut self, item: &mut syn::ItemImpl) {
+        let self_ty = type_name(&item.self_ty);
+        let path = match &item.trait_ {
+            Some((_, trait_path, _)) => {
+                self.qualify(&format!("<{} as {}>", self_ty, path_name(trait_path)))
+            }
+            None => self.qualify(&self_ty),
         };
 
-        let rag_context = self.extract_context(start_line, end_line);
+        let id = self.push_item(
+            ItemKind::Impl,
+            "impl".to_string(),
+            path.clone(),
+            item,
+            item.clone().into_token_stream().to_string(),
+            generics_string(&item.generics),
+        );
 
-        let project_context = ProjectContext {
-            repo_url: self.repo_url.clone().unwrap_or_default(),
-            commit_hash: self.commit_hash.clone().unwrap_or_default(),
-            file_path: self.current_file.clone().unwrap_or_default(),
-        };
+        let in_test = self.enter_test_scope();
+        self.parents.push(ParentItem { path, id });
+        syn::visit_mut::visit_item_impl_mut(self, item);
+        self.parents.pop();
+        self.in_test = in_test;
+    }
 
-        self.items.push(ExtractedItem {
-            project_context,
-            item_meta,
-            content,
-            rag_context,
-        });
+    fn visit_impl_item_fn_mut(&mut self, item: &mut syn::ImplItemFn) {
+        if let Some(parent) = self.parents.last() {
+            let fully_qualified_name = format!("{}::{}", parent.path, item.sig.ident);
 
-        syn::visit_mut::visit_item_fn_mut(self, item);
+            self.push_item(
+                ItemKind::Method,
+                item.sig.ident.to_string(),
+                fully_qualified_name,
+                item,
+                item.sig.clone().into_token_stream().to_string(),
+                generics_string(&item.sig.generics),
+            );
+            self.attach_fim(&item.block);
+        }
+
+        syn::visit_mut::visit_impl_item_fn_mut(self, item);
     }
 
-    fn visit_item_impl_mut(&mut self, item: &mut syn::ItemImpl) {
-        let start_line = 1;
-        let end_line = 1;
+    fn visit_trait_item_fn_mut(&mut self, item: &mut syn::TraitItemFn) {
+        // Only provided methods have a body worth extracting
+        if let Some(block) = &item.default
+            && let Some(parent) = self.parents.last()
+        {
+            let fully_qualified_name = format!("{}::{}", parent.path, item.sig.ident);
 
-        let item_meta = ItemMeta {
-            kind: ItemKind::Impl,
-            name: "impl".to_string(),
-            fully_qualified_name: format!("{}::impl", self.current_module.join("::")),
-            start_line: start_line as u32,
-            end_line: end_line as u32,
-        };
+            self.push_item(
+                ItemKind::Method,
+                item.sig.ident.to_string(),
+                fully_qualified_name,
+                item,
+                item.sig.clone().into_token_stream().to_string(),
+                generics_string(&item.sig.generics),
+            );
+            self.attach_fim(block);
+        }
 
-        let content = Content {
-            signature: item.clone().into_token_stream().to_string(),
-            body_normalized: item.clone().into_token_stream().to_string(),
-            semantic_hash: String::new(),
-            docstring: None,
-            imports: Vec::new(),
-        };
+        syn::visit_mut::visit_trait_item_fn_mut(self, item);
+    }
+
+    fn visit_item_trait_mut(&mut self, item: &mut syn::ItemTrait) {
+        let path = self.qualify(&item.ident.to_string());
+
+        let id = self.push_item(
+            ItemKind::Trait,
+            item.ident.to_string(),
+            path.clone(),
+            item,
+            item.clone().into_token_stream().to_string(),
+            generics_string(&item.generics),
+        );
+
+        let in_test = self.enter_test_scope();
+        self.parents.push(ParentItem { path, id });
+        syn::visit_mut::visit_item_trait_mut(self, item);
+        self.parents.pop();
+        self.in_test = in_test;
+    }
 
-        let rag_context = self.extract_context(start_line, end_line);
+    fn visit_item_mod_mut(&mut self, item: &mut syn::ItemMod) {
+        self.current_module.push(item.ident.to_string());
 
-        let project_context = ProjectContext {
-            repo_url: self.repo_url.clone().unwrap_or_default(),
-            commit_hash: self.commit_hash.clone().unwrap_or_default(),
-            file_path: self.current_file.clone().unwrap_or_default(),
+        // `use` declarations apply to the whole module, wherever they appear,
+        // and a module's own imports are those of its body
+        let imports = match &item.content {
+            Some((_, items)) => collect_imports(items),
+            None => Vec::new(),
         };
+        self.import_scopes.push(imports);
 
-        self.items.push(ExtractedItem {
-            project_context,
-            item_meta,
-            content,
-            rag_context,
-        });
+        self.push_item(
+            ItemKind::Module,
+            item.ident.to_string(),
+            self.current_module.join("::"),
+            item,
+            item.clone().into_token_stream().to_string(),
+            None,
+        );
 
-        syn::visit_mut::visit_item_impl_mut(self, item);
+        let in_test = self.enter_test_scope();
+
+        syn::visit_mut::visit_item_mod_mut(self, item);
+
+        self.in_test = in_test;
+        self.import_scopes.pop();
+        self.current_module.pop();
     }
 
-    fn visit_item_trait_mut(&mut self, item: &mut syn::ItemTrait) {
-        let start_line = 1;
-        let end_line = 1;
+    fn visit_item_struct_mut(&mut self, item: &mut syn::ItemStruct) {
+        let syn::ItemStruct {
+            vis,
+            ident,
+            generics,
+            ..
+        } = &*item;
+        let where_clause = &generics.where_clause;
 
-        let item_meta = ItemMeta {
-            kind: ItemKind::Trait,
-            name: item.ident.to_string(),
-            fully_qualified_name: format!("{}::{}", self.current_module.join("::"), item.ident),
-            start_line: start_line as u32,
-            end_line: end_line as u32,
-        };
+        self.push_item(
+            ItemKind::Struct,
+            ident.to_string(),
+            self.qualify(&ident.to_string()),
+            item,
+            quote!(#vis struct #ident #generics #where_clause).to_string(),
+            generics_string(generics),
+        );
 
-        let content = Content {
-            signature: item.clone().into_token_stream().to_string(),
-            body_normalized: item.clone().into_token_stream().to_string(),
-            semantic_hash: String::new(),
-            docstring: None,
-            imports: Vec::new(),
-        };
+        syn::visit_mut::visit_item_struct_mut(self, item);
+    }
 
-        let rag_context = self.extract_context(start_line, end_line);
+    fn visit_item_enum_mut(&mut self, item: &mut syn::ItemEnum) {
+        let syn::ItemEnum {
+            vis,
+            ident,
+            generics,
+            ..
+        } = &*item;
+        let where_clause = &generics.where_clause;
 
-        let project_context = ProjectContext {
-            repo_url: self.repo_url.clone().unwrap_or_default(),
-            commit_hash: self.commit_hash.clone().unwrap_or_default(),
-            file_path: self.current_file.clone().unwrap_or_default(),
-        };
+        self.push_item(
+            ItemKind::Enum,
+            ident.to_string(),
+            self.qualify(&ident.to_string()),
+            item,
+            quote!(#vis enum #ident #generics #where_clause).to_string(),
+            generics_string(generics),
+        );
 
-        self.items.push(ExtractedItem {
-            project_context,
-            item_meta,
-            content,
-            rag_context,
-        });
+        syn::visit_mut::visit_item_enum_mut(self, item);
+    }
 
-        syn::visit_mut::visit_item_trait_mut(self, item);
+    fn visit_item_union_mut(&mut self, item: &mut syn::ItemUnion) {
+        let syn::ItemUnion {
+            vis,
+            ident,
+            generics,
+            ..
+        } = &*item;
+        let where_clause = &generics.where_clause;
+
+        self.push_item(
+            ItemKind::Union,
+            ident.to_string(),
+            self.qualify(&ident.to_string()),
+            item,
+            quote!(#vis union #ident #generics #where_clause).to_string(),
+            generics_string(generics),
+        );
+
+        syn::visit_mut::visit_item_union_mut(self, item);
     }
 
-    fn visit_item_mod_mut(&mut self, item: &mut syn::ItemMod) {
-        let start_line = 1;
-        let end_line = 1;
+    fn visit_item_type_mut(&mut self, item: &mut syn::ItemType) {
+        let syn::ItemType {
+            vis,
+            ident,
+            generics,
+            ..
+        } = &*item;
+        let where_clause = &generics.where_clause;
 
-        self.current_module.push(item.ident.to_string());
+        self.push_item(
+            ItemKind::TypeAlias,
+            ident.to_string(),
+            self.qualify(&ident.to_string()),
+            item,
+            quote!(#vis type #ident #generics #where_clause).to_string(),
+            generics_string(generics),
+        );
 
-        let item_meta = ItemMeta {
-            kind: ItemKind::Module,
-            name: item.ident.to_string(),
-            fully_qualified_name: self.current_module.join("::"),
-            start_line: start_line as u32,
-            end_line: end_line as u32,
-        };
+        syn::visit_mut::visit_item_type_mut(self, item);
+    }
 
-        let content = Content {
-            signature: item.clone().into_token_stream().to_string(),
-            body_normalized: item.clone().into_token_stream().to_string(),
-            semantic_hash: String::new(),
-            docstring: None,
-            imports: Vec::new(),
-        };
+    fn visit_item_const_mut(&mut self, item: &mut syn::ItemConst) {
+        let syn::ItemConst {
+            vis,
+            ident,
+            generics,
+            ty,
+            ..
+        } = &*item;
 
-        let rag_context = self.extract_context(start_line, end_line);
+        self.push_item(
+            ItemKind::Const,
+            ident.to_string(),
+            self.qualify(&ident.to_string()),
+            item,
+            quote!(#vis const #ident #generics: #ty).to_string(),
+            generics_string(generics),
+        );
 
-        let project_context = ProjectContext {
-            repo_url: self.repo_url.clone().unwrap_or_default(),
-            commit_hash: self.commit_hash.clone().unwrap_or_default(),
-            file_path: self.current_file.clone().unwrap_or_default(),
-        };
+        syn::visit_mut::visit_item_const_mut(self, item);
+    }
 
-        self.items.push(ExtractedItem {
-            project_context,
-            item_meta,
-            content,
-            rag_context,
-        });
+    fn visit_item_static_mut(&mut self, item: &mut syn::ItemStatic) {
+        let syn::ItemStatic {
+            vis,
+            mutability,
+            ident,
+            ty,
+            ..
+        } = &*item;
 
-        syn::visit_mut::visit_item_mod_mut(self, item);
-        self.current_module.pop();
+        self.push_item(
+            ItemKind::Static,
+            ident.to_string(),
+            self.qualify(&ident.to_string()),
+            item,
+            quote!(#vis static #mutability #ident: #ty).to_string(),
+            None,
+        );
+
+        syn::visit_mut::visit_item_static_mut(self, item);
+    }
+
+    fn visit_item_macro_mut(&mut self, item: &mut syn::ItemMacro) {
+        // Only `macro_rules!` definitions carry a name; other item-level
+        // macro invocations are not definitions
+        if let Some(ident) = &item.ident {
+            let path = &item.mac.path;
+
+            self.push_item(
+                ItemKind::Macro,
+                ident.to_string(),
+                self.qualify(&ident.to_string()),
+                item,
+                quote!(#path! #ident).to_string(),
+                None,
+            );
+        }
+
+        syn::visit_mut::visit_item_macro_mut(self, item);
     }
 }
 
+/// Collect every identifier in a token stream, descending into groups
+fn collect_idents(tokens: TokenStream, idents: &mut HashSet<String>) {
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) => {
+                idents.insert(ident.to_string());
+            }
+            TokenTree::Group(group) => collect_idents(group.stream(), idents),
+            _ => {}
+        }
+    }
+}
+
+/// Generic parameters and where clause of an item, if it has any
+fn generics_string(generics: &syn::Generics) -> Option<String> {
+    if generics.params.is_empty() && generics.where_clause.is_none() {
+        return None;
+    }
+
+    let where_clause = &generics.where_clause;
+    Some(quote!(#generics #where_clause).to_string())
+}
+
+/// Name of a path without generic arguments, e.g. `fmt::Display`
+fn path_name(path: &syn::Path) -> String {
+    let segments: Vec<String> = path
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect();
+    segments.join("::")
+}
+
+/// Name of an impl's self type as used in qualified names: `Vec<T>` becomes
+/// `Vec`, other types (`&T`, `[T; N]`, `dyn Trait`) are printed compactly.
+fn type_name(ty: &syn::Type) -> String {
+    match ty {
+        syn::Type::Path(type_path) if type_path.qself.is_none() => path_name(&type_path.path),
+        syn::Type::Group(group) => type_name(&group.elem),
+        syn::Type::Paren(paren) => type_name(&paren.elem),
+        other => compact_tokens(&other.to_token_stream().to_string()),
+    }
+}
+
+/// Drop the spaces `TokenStream::to_string` puts around punctuation
+fn compact_tokens(tokens: &str) -> String {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let chars: Vec<char> = tokens.chars().collect();
+    let mut compact = String::with_capacity(tokens.len());
+
+    for (index, &c) in chars.iter().enumerate() {
+        if c == ' ' {
+            let before = index.checked_sub(1).map(|i| chars[i]);
+            let after = chars.get(index + 1).copied();
+            if !(before.is_some_and(is_word) && after.is_some_and(is_word)) {
+                continue;
+            }
+        }
+        compact.push(c);
+    }
+
+    compact
+}
+
 /// Main extraction function taking a raw string source
 pub fn extract_items_from_source(
     source: &str,
@@ -264,16 +718,37 @@ pub fn extract_items_from_source(
     repo_url: String,
     commit_hash: String,
     config: &ExtractConfig,
+) -> CoreResult<Vec<ExtractedItem>> {
+    let context = ProjectContext {
+        repo_url,
+        commit_hash,
+        file_path,
+        ..Default::default()
+    };
+    extract_items_with_context(source, context, config)
+}
+
+/// Extract items from a raw string source into the given context, whose
+/// `module_path` prefixes every fully qualified name
+pub fn extract_items_with_context(
+    source: &str,
+    context: ProjectContext,
+    config: &ExtractConfig,
 ) -> CoreResult<Vec<ExtractedItem>> {
     let parsed_file = syn::parse_file(source)?;
 
     let mut visitor = ExtractVisitor::new(config);
     visitor.set_source(source);
-    visitor.set_file_info(file_path, repo_url, commit_hash);
+    visitor.set_context(context);
 
     let mut parsed_file_mut = parsed_file;
     visitor.visit_file_mut(&mut parsed_file_mut);
 
+    // Span positions are kept in a thread-local source map that would
+    // otherwise grow with every file parsed on this thread
+    drop(parsed_file_mut);
+    proc_macro2::extra::invalidate_current_thread_spans();
+
     Ok(visitor.items)
 }
 
@@ -285,7 +760,17 @@ pub fn extract_items_from_file(
     config: &ExtractConfig,
 ) -> CoreResult<Vec<ExtractedItem>> {
     let source = std::fs::read_to_string(file_path)?;
+    extract_items_from_source(
+        &source,
+        dataset_path(file_path),
+        repo_url,
+        commit_hash,
+        config,
+    )
+}
 
+/// Path of a file as recorded in `ProjectContext::file_path`
+pub fn dataset_path(file_path: &Path) -> String {
     // Heuristic:
     // Split path into components. Find the component starting with "rustcodeflow_".
     // Truncate everything before it.
@@ -300,7 +785,7 @@ pub fn extract_items_from_file(
         .iter()
         .position(|c| c.starts_with("rustcodeflow_"));
 
-    let relative_path_str = if let Some(idx) = start_index {
+    if let Some(idx) = start_index {
         // e.g. ["tmp", "x", "rustcodeflow_rust", "src", "lib.rs"] -> idx=2
         // repo_dir = "rustcodeflow_rust" -> "rust"
         let repo_dir = &components[idx];
@@ -313,7 +798,387 @@ pub fn extract_items_from_file(
     } else {
         // Fallback: If pattern not found (e.g. local run), just normalize slashes
         file_path.to_string_lossy().replace('\\', "/")
-    };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    extract_items_from_source(&source, relative_path_str, repo_url, commit_hash, config)
+    #[test]
+    fn test_item_locations_follow_spans() {
+        let source = "\
+use std::fmt;
+
+/// Adds one
+fn add_one(x: u32) -> u32 {
+    x + 1
+}
+
+mod inner {
+    pub trait Named {
+        fn name(&self) -> String;
+    }
+}
+";
+        let config = ExtractConfig::default();
+        let items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            &config,
+        )
+        .unwrap();
+
+        let add_one = &items[0].item_meta;
+        assert_eq!(add_one.name, "add_one");
+        assert_eq!((add_one.start_line, add_one.end_line), (3, 6));
+        assert_eq!((add_one.start_column, add_one.end_column), (0, 1));
+        assert_eq!(
+            &source[add_one.start_byte..add_one.end_byte],
+            "/// Adds one\nfn add_one(x: u32) -> u32 {\n    x + 1\n}"
+        );
+
+        let module = &items[1].item_meta;
+        assert_eq!((module.start_line, module.end_line), (8, 12));
+
+        let named = &items[2].item_meta;
+        assert_eq!((named.start_line, named.end_line), (9, 11));
+        assert_eq!(named.start_column, 4);
+    }
+
+    #[test]
+    fn test_methods_are_linked_to_their_parent() {
+        let source = "\
+mod shapes {
+    struct Circle(f64);
+
+    impl Circle {
+        pub fn area(&self) -> f64 { self.0 * self.0 * 3.14 }
+    }
+
+    impl<T> From<T> for Circle where T: Into<f64> {
+        fn from(radius: T) -> Self { Circle(radius.into()) }
+    }
+
+    trait Shape {
+        fn area(&self) -> f64;
+        fn describe(&self) -> String { format!(\"{}\", self.area()) }
+    }
+}
+";
+        let config = ExtractConfig::default();
+        let items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            &config,
+        )
+        .unwrap();
+
+        let methods: Vec<&ItemMeta> = items
+            .iter()
+            .map(|item| &item.item_meta)
+            .filter(|meta| meta.kind == ItemKind::Method)
+            .collect();
+        let names: Vec<&str> = methods
+            .iter()
+            .map(|meta| meta.fully_qualified_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "shapes::Circle::area",
+                "shapes::<Circle as From>::from",
+                "shapes::Shape::describe"
+            ]
+        );
+
+        for method in methods {
+            let parent = items
+                .iter()
+                .find(|item| Some(&item.item_meta.id) == method.parent_id.as_ref())
+                .unwrap();
+            assert!(
+                method
+                    .fully_qualified_name
+                    .starts_with(&parent.item_meta.fully_qualified_name)
+            );
+        }
+    }
+
+    #[test]
+    fn test_type_definitions_and_macros() {
+        let source = "\
+pub struct Wrapper<T: Clone> where T: Default { inner: T }
+enum Shape { Circle(f64), Square(f64) }
+type Map<K> = std::collections::HashMap<K, String>;
+const LIMIT: usize = 10;
+static mut COUNTER: u32 = 0;
+union Bits { int: u32, float: f32 }
+macro_rules! square { ($x:expr) => { $x * $x }; }
+lazy_static! { static ref NAME: String = String::new(); }
+";
+        let config = ExtractConfig::default();
+        let mut items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            &config,
+        )
+        .unwrap();
+        crate::normalizer::normalize_items(&mut items, &config).unwrap();
+
+        let summary: Vec<(String, &str, &str)> = items
+            .iter()
+            .map(|item| {
+                (
+                    item.item_meta.kind.to_string(),
+                    item.item_meta.name.as_str(),
+                    item.content.signature.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "struct".to_string(),
+                    "Wrapper",
+                    "pub struct Wrapper < T : Clone > where T : Default"
+                ),
+                ("enum".to_string(), "Shape", "enum Shape"),
+                ("type_alias".to_string(), "Map", "type Map < K >"),
+                ("const".to_string(), "LIMIT", "const LIMIT : usize"),
+                ("static".to_string(), "COUNTER", "static mut COUNTER : u32"),
+                ("union".to_string(), "Bits", "union Bits"),
+                ("macro".to_string(), "square", "macro_rules ! square"),
+            ]
+        );
+
+        assert_eq!(
+            items[0].content.generics.as_deref(),
+            Some("< T : Clone > where T : Default")
+        );
+        assert_eq!(items[1].content.generics, None);
+        assert!(items[0].content.body_normalized.contains("inner: T0"));
+        assert!(
+            items[6]
+                .content
+                .body_normalized
+                .starts_with("macro_rules! square")
+        );
+    }
+
+    #[test]
+    fn test_imports_are_flattened_and_filtered() {
+        let source = "\
+use std::collections::{HashMap, HashSet as Set};
+use std::io::{self, Read as _};
+use std::fmt::*;
+
+fn count(words: &[String]) -> HashMap<String, usize> { HashMap::new() }
+
+mod nested {
+    use super::{count, Set};
+    use std::io;
+    use std::sync::Arc;
+
+    fn shared(items: Vec<String>) -> Arc<Set<String>> {
+        let _ = count(&items);
+        Arc::new(items.into_iter().collect())
+    }
+
+    fn read() -> io::Result<()> { Ok(()) }
+}
+";
+        let config = ExtractConfig::default();
+        let items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            &config,
+        )
+        .unwrap();
+
+        let imports = |name: &str| {
+            items
+                .iter()
+                .find(|item| item.item_meta.name == name)
+                .unwrap()
+                .content
+                .imports
+                .clone()
+        };
+
+        assert_eq!(
+            imports("count"),
+            [
+                "use std::collections::HashMap",
+                "use std::io::Read as _",
+                "use std::fmt::*"
+            ]
+        );
+        assert_eq!(
+            imports("shared"),
+            ["use super::count", "use super::Set", "use std::sync::Arc"]
+        );
+        assert_eq!(imports("read"), ["use std::io"]);
+        assert_eq!(
+            imports("nested"),
+            [
+                "use super::count",
+                "use super::Set",
+                "use std::io",
+                "use std::sync::Arc"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_names_are_qualified_with_module_path() {
+        let source = "fn top() {}\nmod inner {\n    struct S;\n}\n";
+        let config = ExtractConfig::default();
+        let names = |items: Vec<ExtractedItem>| -> Vec<String> {
+            items
+                .into_iter()
+                .map(|item| item.item_meta.fully_qualified_name)
+                .collect()
+        };
+
+        let items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            String::new(),
+            String::new(),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(names(items), ["top", "inner", "inner::S"]);
+
+        let context = ProjectContext {
+            module_path: Some("krate::outer".to_string()),
+            ..Default::default()
+        };
+        let items = extract_items_with_context(source, context, &config).unwrap();
+        assert_eq!(
+            names(items),
+            [
+                "krate::outer::top",
+                "krate::outer::inner",
+                "krate::outer::inner::S"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ids_are_stable() {
+        let config = ExtractConfig::default();
+        let ids = |source: &str, commit: &str| -> Vec<String> {
+            extract_items_from_source(
+                source,
+                "lib.rs".to_string(),
+                "repo".to_string(),
+                commit.to_string(),
+                &config,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|item| item.item_meta.id)
+            .collect()
+        };
+
+        let source = "#[cfg(unix)]\nfn f() {}\n#[cfg(windows)]\nfn f() {}\n";
+        let before = ids(source, "c1");
+        assert_eq!(before.len(), 2);
+        assert_ne!(before[0], before[1]);
+
+        // Moving items around does not change their ids, the commit does
+        assert_eq!(ids(&format!("\n\n// moved\n{source}"), "c1"), before);
+        assert_ne!(ids(source, "c2"), before);
+    }
+
+    #[test]
+    fn test_context_uses_real_lines() {
+        let source = "// one\n// two\nfn f() {}\n// four\n";
+        let config = ExtractConfig {
+            include_context: true,
+            context_lines: 1,
+            ..ExtractConfig::default()
+        };
+        let items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            &config,
+        )
+        .unwrap();
+
+        assert_eq!(
+            items[0].rag_context.context_before.as_deref(),
+            Some("// two")
+        );
+        assert_eq!(
+            items[0].rag_context.context_after.as_deref(),
+            Some("// four")
+        );
+    }
+
+    #[test]
+    fn test_items_are_classified() {
+        let source = r#"
+pub fn add(a: u32, b: u32) -> u32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn helper() -> u32 {
+        add(1, 2)
+    }
+
+    #[test]
+    fn adds() {
+        assert_eq!(helper(), 3);
+    }
+}
+"#;
+        let items =
+            extract_items_with_context(source, ProjectContext::default(), &Default::default())
+                .unwrap();
+        let tests: Vec<(&str, bool)> = items
+            .iter()
+            .map(|item| (item.item_meta.name.as_str(), item.item_meta.metrics.is_test))
+            .collect();
+        assert_eq!(
+            tests,
+            [
+                ("add", false),
+                ("tests", true),
+                ("helper", true),
+                ("adds", true)
+            ]
+        );
+        assert_eq!(items[0].item_meta.metrics.line_count, 3);
+        assert!(!items[0].item_meta.metrics.is_generated);
+
+        let context = ProjectContext {
+            target_kind: Some(TargetKind::Bench),
+            ..Default::default()
+        };
+        let generated = format!("// @generated by build.rs\n{source}");
+        let items = extract_items_with_context(&generated, context, &Default::default()).unwrap();
+        assert!(
+            items
+                .iter()
+                .all(|item| item.item_meta.metrics.is_bench && item.item_meta.metrics.is_generated)
+        );
+    }
 }
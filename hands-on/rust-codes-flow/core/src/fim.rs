@@ -0,0 +1,239 @@
+use crate::models::*;
+use std::ops::Range;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+
+/// Build the FIM sample of a function from its source.
+///
+/// `item` is the byte range of the whole function in `source` and `body`
+/// its block. The middle is a statement, expression, block or match arm of
+/// the body, drawn by kind first and then by node, from a generator seeded
+/// with `config.seed` and the function's source: the same function and seed
+/// always give the same sample, wherever the function is.
+pub fn fim_sample(
+    source: &str,
+    item: Range<usize>,
+    body: &syn::Block,
+    config: &FimConfig,
+) -> Option<FimSample> {
+    let mut collector = SpanCollector {
+        kinds: &config.span_kinds,
+        spans: Vec::new(),
+    };
+    collector.visit_block(body);
+
+    let kinds: Vec<FimSpanKind> = config
+        .span_kinds
+        .iter()
+        .copied()
+        .filter(|kind| collector.spans.iter().any(|(found, _)| found == kind))
+        .collect();
+    if kinds.is_empty() {
+        return None;
+    }
+
+    let text = source.get(item.clone())?;
+    let mut rng = SplitMix64::new(config.seed ^ seed_of(text));
+    let span_kind = kinds[rng.below(kinds.len())];
+    let spans: Vec<&Range<usize>> = collector
+        .spans
+        .iter()
+        .filter(|(kind, _)| *kind == span_kind)
+        .map(|(_, span)| span)
+        .collect();
+    let span = spans[rng.below(spans.len())];
+
+    if !(item.start <= span.start && span.end <= item.end) {
+        return None;
+    }
+    let prefix = source.get(item.start..span.start)?.to_string();
+    let middle = source.get(span.clone())?.to_string();
+    let suffix = source.get(span.end..item.end)?.to_string();
+
+    Some(FimSample {
+        order: config.order,
+        span_kind,
+        prompt: fim_prompt(config.order, &config.sentinels, &prefix, &suffix),
+        prefix,
+        middle,
+        suffix,
+    })
+}
+
+/// Prefix and suffix with sentinels in the given order, ending with the
+/// middle sentinel so that the middle is the completion
+pub fn fim_prompt(order: FimOrder, sentinels: &FimSentinels, prefix: &str, suffix: &str) -> String {
+    match order {
+        FimOrder::Psm => format!(
+            "{}{}{}{}{}",
+            sentinels.prefix, prefix, sentinels.suffix, suffix, sentinels.middle
+        ),
+        FimOrder::Spm => format!(
+            "{}{}{}{}{}",
+            sentinels.suffix, suffix, sentinels.prefix, prefix, sentinels.middle
+        ),
+    }
+}
+
+/// Byte ranges of the nodes of a function body that can be the middle
+struct SpanCollector<'a> {
+    kinds: &'a [FimSpanKind],
+    spans: Vec<(FimSpanKind, Range<usize>)>,
+}
+
+impl SpanCollector<'_> {
+    fn push(&mut self, kind: FimSpanKind, node: &impl Spanned) {
+        if self.kinds.contains(&kind) {
+            self.spans.push((kind, node.span().byte_range()));
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for SpanCollector<'_> {
+    fn visit_block(&mut self, node: &'ast syn::Block) {
+        self.push(FimSpanKind::Block, node);
+        visit::visit_block(self, node);
+    }
+
+    fn visit_stmt(&mut self, node: &'ast syn::Stmt) {
+        self.push(FimSpanKind::Statement, node);
+        visit::visit_stmt(self, node);
+    }
+
+    fn visit_expr(&mut self, node: &'ast syn::Expr) {
+        // Single paths and literals are too small to be worth completing
+        if !matches!(node, syn::Expr::Path(_) | syn::Expr::Lit(_)) {
+            self.push(FimSpanKind::Expression, node);
+        }
+        visit::visit_expr(self, node);
+    }
+
+    fn visit_arm(&mut self, node: &'ast syn::Arm) {
+        self.push(FimSpanKind::MatchArm, node);
+        visit::visit_arm(self, node);
+    }
+
+    fn visit_item(&mut self, _: &'ast syn::Item) {
+        // Nested items get their own samples
+    }
+}
+
+/// Seed derived from an item's source, so that every item draws
+/// independently of the order items are visited in
+fn seed_of(text: &str) -> u64 {
+    let hash = blake3::hash(text.as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash.as_bytes()[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// SplitMix64, a small generator that is plenty for picking spans
+pub(crate) struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::extract_items_with_context;
+
+    const SOURCE: &str = r#"/// Classify a number
+fn classify(n: i32) -> &'static str {
+    let sign = n.signum();
+    match sign {
+        -1 => "negative",
+        0 => "zero",
+        _ => "positive",
+    }
+}
+"#;
+
+    fn sample(config: FimConfig) -> FimSample {
+        let config = ExtractConfig {
+            fim: Some(config),
+            ..Default::default()
+        };
+        let items = extract_items_with_context(SOURCE, ProjectContext::default(), &config).unwrap();
+        items[0].fim.clone().unwrap()
+    }
+
+    #[test]
+    fn test_middle_is_a_whole_node() {
+        for seed in 0..20 {
+            let arm = sample(FimConfig {
+                seed,
+                span_kinds: vec![FimSpanKind::MatchArm],
+                ..Default::default()
+            });
+            assert!(
+                [
+                    "-1 => \"negative\",",
+                    "0 => \"zero\",",
+                    "_ => \"positive\","
+                ]
+                .contains(&arm.middle.as_str()),
+                "{:?}",
+                arm.middle
+            );
+            assert_eq!(
+                format!("{}{}{}", arm.prefix, arm.middle, arm.suffix),
+                SOURCE.trim_end()
+            );
+        }
+
+        let statement = sample(FimConfig {
+            span_kinds: vec![FimSpanKind::Statement],
+            ..Default::default()
+        });
+        assert!(statement.middle.starts_with("let sign") || statement.middle.starts_with("match"));
+    }
+
+    #[test]
+    fn test_orders_sentinels_and_seed() {
+        let config = FimConfig {
+            order: FimOrder::Spm,
+            sentinels: FimSentinels {
+                prefix: "<PRE>".to_string(),
+                suffix: "<SUF>".to_string(),
+                middle: "<MID>".to_string(),
+            },
+            seed: 7,
+            ..Default::default()
+        };
+        let first = sample(config.clone());
+        assert_eq!(first, sample(config.clone()));
+        assert_eq!(
+            first.prompt,
+            format!("<SUF>{}<PRE>{}<MID>", first.suffix, first.prefix)
+        );
+
+        // Different seeds pick different spans
+        let middles: std::collections::HashSet<String> = (0..20)
+            .map(|seed| {
+                sample(FimConfig {
+                    seed,
+                    ..config.clone()
+                })
+                .middle
+            })
+            .collect();
+        assert!(middles.len() > 1);
+    }
+}
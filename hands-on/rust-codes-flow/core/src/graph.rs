@@ -0,0 +1,904 @@
+use crate::models::*;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use syn::punctuated::Punctuated;
+use syn::visit::{self, Visit};
+
+/// How one item refers to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EdgeKind {
+    /// Call of a function or associated function by path, `parse(..)` or
+    /// `Config::new(..)`
+    Call,
+    /// Method call resolved by name, `config.validate()`
+    MethodCall,
+    /// Type named in a signature, body, pattern or bound
+    Type,
+    /// Any other path: constants, statics, unit structs and variants,
+    /// functions passed as values
+    Value,
+    /// Trait implemented by an impl block
+    Impl,
+    /// Invocation of a `macro_rules!` macro
+    Macro,
+}
+
+impl fmt::Display for EdgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdgeKind::Call => write!(f, "call"),
+            EdgeKind::MethodCall => write!(f, "method-call"),
+            EdgeKind::Type => write!(f, "type"),
+            EdgeKind::Value => write!(f, "value"),
+            EdgeKind::Impl => write!(f, "impl"),
+            EdgeKind::Macro => write!(f, "macro"),
+        }
+    }
+}
+
+/// A reference from the item `source` to the item `target`, both item ids
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    pub kind: EdgeKind,
+}
+
+/// Resolves the references between the items of each crate.
+///
+/// Items are observed one at a time: their definitions are indexed and the
+/// paths, method calls and macros in their bodies recorded. `finish` then
+/// resolves what was recorded against the index, so references may point
+/// to items observed later.
+///
+/// Resolution is best-effort and by name, without type inference: paths go
+/// through `crate`, `self`, `super` and `Self`, the imports of the item and
+/// its module, and enum variants and associated items resolve to their
+/// type when they are not items themselves. A method call on `self`
+/// resolves to the method of that name on `Self`; on any other receiver it
+/// resolves when a single type the item names (`Type`, `Type::new(..)`)
+/// has a method of that name. Nothing outside the crate is resolved.
+#[derive(Debug, Default)]
+pub struct GraphBuilder {
+    crates: HashMap<CrateKey, CrateIndex>,
+    pending: Vec<Pending>,
+}
+
+/// Repository and crate name of an item
+type CrateKey = (String, Option<String>);
+
+/// Definitions of one crate
+#[derive(Debug, Default)]
+struct CrateIndex {
+    /// Item id by fully qualified name
+    paths: HashMap<String, String>,
+    /// Method id and owner type (or trait) name by method name
+    methods: HashMap<String, Vec<(String, String)>>,
+    /// `macro_rules!` ids by macro name
+    macros: HashMap<String, Vec<String>>,
+}
+
+/// References of an item, waiting for every definition to be indexed
+#[derive(Debug)]
+struct Pending {
+    id: String,
+    key: CrateKey,
+    scope: Scope,
+    references: Vec<Reference>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Reference {
+    Path(Vec<String>, EdgeKind),
+    /// Method name and whether the receiver is `self`
+    Method(String, bool),
+    Macro(String),
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index an item and record its references
+    ///
+    /// The item's code is parsed with `syn` and only names are kept; callers
+    /// observing a whole dataset should call
+    /// `proc_macro2::extra::invalidate_current_thread_spans` between items.
+    pub fn observe(&mut self, item: &ExtractedItem) {
+        let key = (
+            item.project_context.repo_url.clone(),
+            item.project_context.crate_name.clone(),
+        );
+        let meta = &item.item_meta;
+        let scope = Scope::of(item);
+        let index = self.crates.entry(key.clone()).or_default();
+
+        match meta.kind {
+            // An inherent impl has the name of its type
+            ItemKind::Impl | ItemKind::Module => {}
+            ItemKind::Macro => index
+                .macros
+                .entry(meta.name.clone())
+                .or_default()
+                .push(meta.id.clone()),
+            ItemKind::Method => {
+                index
+                    .paths
+                    .entry(meta.fully_qualified_name.clone())
+                    .or_insert_with(|| meta.id.clone());
+                // `<Type as Trait>::method` is also called as `Type::method`
+                if let Some(owner) = &scope.self_ty {
+                    let mut alias = owner.clone();
+                    alias.push(meta.name.clone());
+                    index
+                        .paths
+                        .entry(alias.join("::"))
+                        .or_insert_with(|| meta.id.clone());
+                }
+                let owner = scope
+                    .self_ty
+                    .as_ref()
+                    .and_then(|path| path.last())
+                    .cloned()
+                    .unwrap_or_default();
+                index
+                    .methods
+                    .entry(meta.name.clone())
+                    .or_default()
+                    .push((meta.id.clone(), owner));
+            }
+            _ => {
+                index
+                    .paths
+                    .entry(meta.fully_qualified_name.clone())
+                    .or_insert_with(|| meta.id.clone());
+            }
+        }
+
+        let references = collect_references(item);
+        if !references.is_empty() {
+            self.pending.push(Pending {
+                id: meta.id.clone(),
+                key,
+                scope,
+                references,
+            });
+        }
+    }
+
+    /// Resolve every recorded reference
+    pub fn finish(self) -> Graph {
+        let mut graph = Graph::default();
+
+        for pending in &self.pending {
+            let Some(index) = self.crates.get(&pending.key) else {
+                continue;
+            };
+            let self_ty: Vec<&str> = pending
+                .scope
+                .self_ty
+                .iter()
+                .filter_map(|path| path.last())
+                .map(String::as_str)
+                .collect();
+            // Types the item names, the likely receivers of its method calls
+            let mut named: Vec<&str> = Vec::new();
+            for reference in &pending.references {
+                let owner = match reference {
+                    Reference::Path(segments, EdgeKind::Type) => segments.last(),
+                    Reference::Path(segments, _) if segments.len() > 1 => {
+                        segments.get(segments.len() - 2)
+                    }
+                    _ => None,
+                };
+                named.extend(owner.map(String::as_str));
+            }
+
+            for reference in &pending.references {
+                let resolved = match reference {
+                    Reference::Path(segments, kind) => index
+                        .resolve_path(&pending.scope, segments)
+                        .map(|id| (id, *kind)),
+                    Reference::Method(name, on_self) => {
+                        let owners = if *on_self { &self_ty } else { &named };
+                        index
+                            .resolve_method(name, owners)
+                            .map(|id| (id, EdgeKind::MethodCall))
+                    }
+                    Reference::Macro(name) => match index.macros.get(name).map(Vec::as_slice) {
+                        Some([id]) => Some((id, EdgeKind::Macro)),
+                        _ => None,
+                    },
+                };
+                if let Some((target, kind)) = resolved {
+                    graph.add(&pending.id, target, kind);
+                }
+            }
+        }
+
+        graph
+    }
+}
+
+/// Edges between items, and the references of each item
+#[derive(Debug, Default)]
+pub struct Graph {
+    pub edges: Vec<Edge>,
+    seen: HashSet<Edge>,
+    references: HashMap<String, Vec<String>>,
+    referenced_by: HashMap<String, Vec<String>>,
+}
+
+impl Graph {
+    fn add(&mut self, source: &str, target: &str, kind: EdgeKind) {
+        if source == target {
+            return;
+        }
+        let edge = Edge {
+            source: source.to_string(),
+            target: target.to_string(),
+            kind,
+        };
+        if !self.seen.insert(edge.clone()) {
+            return;
+        }
+        let references = self.references.entry(edge.source.clone()).or_default();
+        if !references.contains(&edge.target) {
+            references.push(edge.target.clone());
+            self.referenced_by
+                .entry(edge.target.clone())
+                .or_default()
+                .push(edge.source.clone());
+        }
+        self.edges.push(edge);
+    }
+
+    /// Set an item's `references` and `referenced_by`
+    pub fn apply(&self, item: &mut ExtractedItem) {
+        let id = &item.item_meta.id;
+        item.item_meta.references = self.references.get(id).cloned().unwrap_or_default();
+        item.item_meta.referenced_by = self.referenced_by.get(id).cloned().unwrap_or_default();
+    }
+}
+
+impl CrateIndex {
+    fn resolve_path(&self, scope: &Scope, segments: &[String]) -> Option<&String> {
+        for (path, min) in scope.candidates(segments) {
+            // `Enum::Variant` and `Type::CONST` fall back to their type
+            for len in (min.max(1)..=path.len()).rev() {
+                if let Some(id) = self.paths.get(&path[..len].join("::")) {
+                    return Some(id);
+                }
+            }
+        }
+        None
+    }
+
+    fn resolve_method(&self, name: &str, owners: &[&str]) -> Option<&String> {
+        let mut owned = self
+            .methods
+            .get(name)?
+            .iter()
+            .filter(|(_, owner)| owners.contains(&owner.as_str()));
+        match (owned.next(), owned.next()) {
+            (Some((id, _)), None) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+/// Where an item's paths are resolved from
+#[derive(Debug, Default)]
+struct Scope {
+    /// Module the item is declared in
+    module: Vec<String>,
+    /// Crate name that `crate::` stands for, when names are qualified with it
+    root: Vec<String>,
+    /// Path of `Self`: the type of an impl, the trait of a provided method
+    self_ty: Option<Vec<String>>,
+    /// Imports of the item, with `None` bindings for globs
+    imports: Vec<(Option<String>, Vec<String>)>,
+}
+
+impl Scope {
+    fn of(item: &ExtractedItem) -> Self {
+        let meta = &item.item_meta;
+        let mut segments = split_path(&meta.fully_qualified_name);
+        let root = item
+            .project_context
+            .module_path
+            .as_deref()
+            .and_then(|path| path.split("::").find(|segment| !segment.is_empty()))
+            .map(|root| vec![root.to_string()])
+            .unwrap_or_default();
+
+        let self_ty = match meta.kind {
+            ItemKind::Method | ItemKind::Impl => {
+                if meta.kind == ItemKind::Method {
+                    segments.pop();
+                }
+                let parent = segments.pop().unwrap_or_default();
+                // `<Type as Trait>` blocks are on `Type`
+                let ty = match parent.strip_prefix('<').and_then(|p| p.strip_suffix('>')) {
+                    Some(header) => header.split(" as ").next().unwrap_or_default(),
+                    None => &parent,
+                };
+                Some(join(&segments, &split_path(ty)))
+            }
+            ItemKind::Module => None,
+            ItemKind::Struct | ItemKind::Enum | ItemKind::Union => {
+                let self_ty = segments.clone();
+                segments.pop();
+                Some(self_ty)
+            }
+            _ => {
+                segments.pop();
+                None
+            }
+        };
+
+        let imports = item
+            .content
+            .imports
+            .iter()
+            .filter_map(|declaration| parse_import(declaration))
+            .collect();
+
+        Self {
+            module: segments,
+            root,
+            self_ty,
+            imports,
+        }
+    }
+
+    /// Absolute paths `segments` may stand for, most likely first, each with
+    /// the number of leading segments that must be kept
+    fn candidates(&self, segments: &[String]) -> Vec<(Vec<String>, usize)> {
+        let Some(first) = segments.first() else {
+            return Vec::new();
+        };
+        if let Some((_, path)) = self
+            .imports
+            .iter()
+            .find(|(binding, _)| binding.as_ref() == Some(first))
+        {
+            return self
+                .bases(path)
+                .into_iter()
+                .map(|(base, _)| {
+                    let min = base.len();
+                    (join(&base, &segments[1..]), min)
+                })
+                .collect();
+        }
+
+        let mut candidates = self.bases(segments);
+        if !matches!(first.as_str(), "crate" | "self" | "super" | "Self") {
+            for (binding, glob) in &self.imports {
+                if binding.is_none() {
+                    for (base, _) in self.bases(glob) {
+                        let min = base.len() + 1;
+                        candidates.push((join(&base, segments), min));
+                    }
+                }
+            }
+        }
+        candidates
+    }
+
+    /// `segments` made absolute without looking at imports, with the number
+    /// of segments up to the first one written after any `crate`, `self`,
+    /// `super` or `Self`
+    fn bases(&self, segments: &[String]) -> Vec<(Vec<String>, usize)> {
+        let anchored = |base: &[String], rest: &[String]| (join(base, rest), base.len() + 1);
+        let Some(first) = segments.first() else {
+            return Vec::new();
+        };
+        match first.as_str() {
+            "crate" => vec![anchored(&self.root, &segments[1..])],
+            "self" => vec![anchored(&self.module, &segments[1..])],
+            "super" => {
+                let supers = segments.iter().take_while(|s| *s == "super").count();
+                let parent = &self.module[..self.module.len().saturating_sub(supers)];
+                vec![anchored(parent, &segments[supers..])]
+            }
+            "Self" => match &self.self_ty {
+                Some(self_ty) => vec![(join(self_ty, &segments[1..]), self_ty.len())],
+                None => Vec::new(),
+            },
+            _ => {
+                let mut bases = vec![anchored(&self.module, segments)];
+                if !self.root.is_empty() && self.module != self.root {
+                    bases.push(anchored(&self.root, segments));
+                }
+                bases.push(anchored(&[], segments));
+                bases
+            }
+        }
+    }
+}
+
+fn join(base: &[String], rest: &[String]) -> Vec<String> {
+    base.iter().chain(rest).cloned().collect()
+}
+
+/// Split a path on `::` outside of angle brackets, so that
+/// `a::<B as c::D>::f` gives `a`, `<B as c::D>` and `f`
+fn split_path(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    let bytes = path.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'<' => depth += 1,
+            b'>' => depth = depth.saturating_sub(1),
+            b':' if depth == 0 && bytes.get(index + 1) == Some(&b':') => {
+                segments.push(path[start..index].to_string());
+                index += 2;
+                start = index;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    segments.push(path[start..].to_string());
+    segments.retain(|segment| !segment.is_empty());
+    segments
+}
+
+/// Binding and path of an import declaration as kept in `content.imports`;
+/// globs have no binding and their path stops before the `*`
+fn parse_import(declaration: &str) -> Option<(Option<String>, Vec<String>)> {
+    let declaration = declaration.strip_prefix("use ")?;
+    let (path, rename) = match declaration.split_once(" as ") {
+        Some((path, rename)) => (path, Some(rename)),
+        None => (declaration, None),
+    };
+    let mut segments = split_path(path);
+    if segments.last().is_some_and(|last| last == "*") {
+        segments.pop();
+        return Some((None, segments));
+    }
+    let binding = match rename {
+        Some("_") => return None,
+        Some(rename) => rename.to_string(),
+        None => segments.last()?.clone(),
+    };
+    Some((Some(binding), segments))
+}
+
+/// Paths, method calls and macros an item refers to.
+///
+/// Bodies are parsed from `body_normalized`; local names the normalizer
+/// renamed cannot resolve, which keeps locals from shadowing items.
+/// Impls only refer to their trait and type, traits to their supertraits:
+/// the items inside them are items of their own.
+fn collect_references(item: &ExtractedItem) -> Vec<Reference> {
+    let body = &item.content.body_normalized;
+    let mut collector = ReferenceCollector::default();
+
+    match item.item_meta.kind {
+        ItemKind::Module | ItemKind::Macro => {}
+        // Methods are normalized as free functions
+        ItemKind::Method => {
+            if let Ok(function) = syn::parse_str::<syn::ItemFn>(body) {
+                collector.visit_item_fn(&function);
+            }
+        }
+        _ => match syn::parse_str::<syn::Item>(body) {
+            Ok(syn::Item::Impl(item)) => {
+                collector.visit_generics(&item.generics);
+                if let Some((_, path, _)) = &item.trait_ {
+                    collector.push_path(path, EdgeKind::Impl);
+                    visit::visit_path(&mut collector, path);
+                }
+                collector.visit_type(&item.self_ty);
+            }
+            Ok(syn::Item::Trait(item)) => {
+                collector.visit_generics(&item.generics);
+                for bound in &item.supertraits {
+                    collector.visit_type_param_bound(bound);
+                }
+            }
+            Ok(item) => visit::visit_item(&mut collector, &item),
+            Err(_) => {}
+        },
+    }
+
+    collector.references
+}
+
+#[derive(Default)]
+struct ReferenceCollector {
+    references: Vec<Reference>,
+}
+
+impl ReferenceCollector {
+    fn push(&mut self, reference: Reference) {
+        if !self.references.contains(&reference) {
+            self.references.push(reference);
+        }
+    }
+
+    fn push_path(&mut self, path: &syn::Path, kind: EdgeKind) {
+        let segments = path
+            .segments
+            .iter()
+            .map(|segment| segment.ident.to_string())
+            .collect();
+        self.push(Reference::Path(segments, kind));
+    }
+
+    /// Record a path unless it is only the tail of `<T>::name`
+    fn push_qualified(&mut self, qself: Option<&syn::QSelf>, path: &syn::Path, kind: EdgeKind) {
+        if qself.is_none_or(|qself| qself.position > 0) {
+            self.push_path(path, kind);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for ReferenceCollector {
+    fn visit_expr_call(&mut self, node: &'ast syn::ExprCall) {
+        if let syn::Expr::Path(func) = &*node.func {
+            self.push_qualified(func.qself.as_ref(), &func.path, EdgeKind::Call);
+            if let Some(qself) = &func.qself {
+                self.visit_type(&qself.ty);
+            }
+            for arg in &node.args {
+                self.visit_expr(arg);
+            }
+        } else {
+            visit::visit_expr_call(self, node);
+        }
+    }
+
+    fn visit_expr_method_call(&mut self, node: &'ast syn::ExprMethodCall) {
+        let on_self =
+            matches!(&*node.receiver, syn::Expr::Path(receiver) if receiver.path.is_ident("self"));
+        self.push(Reference::Method(node.method.to_string(), on_self));
+        visit::visit_expr_method_call(self, node);
+    }
+
+    fn visit_expr_path(&mut self, node: &'ast syn::ExprPath) {
+        self.push_qualified(node.qself.as_ref(), &node.path, EdgeKind::Value);
+        visit::visit_expr_path(self, node);
+    }
+
+    fn visit_type_path(&mut self, node: &'ast syn::TypePath) {
+        self.push_qualified(node.qself.as_ref(), &node.path, EdgeKind::Type);
+        visit::visit_type_path(self, node);
+    }
+
+    fn visit_expr_struct(&mut self, node: &'ast syn::ExprStruct) {
+        self.push_qualified(node.qself.as_ref(), &node.path, EdgeKind::Type);
+        visit::visit_expr_struct(self, node);
+    }
+
+    fn visit_pat_struct(&mut self, node: &'ast syn::PatStruct) {
+        self.push_qualified(node.qself.as_ref(), &node.path, EdgeKind::Type);
+        visit::visit_pat_struct(self, node);
+    }
+
+    fn visit_pat_tuple_struct(&mut self, node: &'ast syn::PatTupleStruct) {
+        self.push_qualified(node.qself.as_ref(), &node.path, EdgeKind::Type);
+        visit::visit_pat_tuple_struct(self, node);
+    }
+
+    fn visit_trait_bound(&mut self, node: &'ast syn::TraitBound) {
+        self.push_path(&node.path, EdgeKind::Type);
+        visit::visit_trait_bound(self, node);
+    }
+
+    fn visit_macro(&mut self, node: &'ast syn::Macro) {
+        if let Some(name) = node.path.segments.last() {
+            self.push(Reference::Macro(name.ident.to_string()));
+        }
+        // Most macros (`vec!`, `assert_eq!`, `format!`) take expressions
+        let parser = Punctuated::<syn::Expr, syn::Token![,]>::parse_terminated;
+        if let Ok(exprs) = node.parse_body_with(parser) {
+            for expr in &exprs {
+                self.visit_expr(expr);
+            }
+        }
+    }
+
+    fn visit_receiver(&mut self, node: &'ast syn::Receiver) {
+        // `&self` carries an implicit `Self` type, already linked by `parent_id`
+        if node.colon_token.is_some() {
+            visit::visit_receiver(self, node);
+        }
+    }
+
+    fn visit_attribute(&mut self, _: &'ast syn::Attribute) {}
+
+    fn visit_item(&mut self, _: &'ast syn::Item) {
+        // Nested items are items of their own
+    }
+}
+
+/// File format of a reference graph
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GraphFormat {
+    /// One `{"type": "node", ...}` or `{"type": "edge", ...}` object per line
+    #[default]
+    Jsonl,
+    /// GraphML, directed, with the item and edge kinds as attributes
+    Graphml,
+}
+
+impl fmt::Display for GraphFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphFormat::Jsonl => write!(f, "jsonl"),
+            GraphFormat::Graphml => write!(f, "graphml"),
+        }
+    }
+}
+
+/// Streams the nodes and edges of a reference graph to a file.
+///
+/// Nodes and edges may be interleaved, so a graph can be written one
+/// repository at a time.
+pub struct GraphWriter<W: Write> {
+    writer: BufWriter<W>,
+    format: GraphFormat,
+}
+
+impl GraphWriter<File> {
+    /// Create (or truncate) a graph file
+    pub fn create(path: &Path, format: GraphFormat) -> CoreResult<Self> {
+        Self::new(File::create(path)?, format)
+    }
+}
+
+impl<W: Write> GraphWriter<W> {
+    pub fn new(writer: W, format: GraphFormat) -> CoreResult<Self> {
+        let mut writer = BufWriter::new(writer);
+        if format == GraphFormat::Graphml {
+            writer.write_all(GRAPHML_HEADER.as_bytes())?;
+        }
+        Ok(Self { writer, format })
+    }
+
+    /// Write an item as a node
+    pub fn write_node(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        let meta = &item.item_meta;
+        match self.format {
+            GraphFormat::Jsonl => {
+                let node = json!({
+                    "type": "node",
+                    "id": meta.id,
+                    "kind": meta.kind,
+                    "name": meta.name,
+                    "fully_qualified_name": meta.fully_qualified_name,
+                    "file_path": item.project_context.file_path,
+                });
+                serde_json::to_writer(&mut self.writer, &node)?;
+                self.writer.write_all(b"\n")?;
+            }
+            GraphFormat::Graphml => writeln!(
+                self.writer,
+                "    <node id=\"{}\"><data key=\"kind\">{}</data><data key=\"name\">{}</data>\
+                 <data key=\"fqn\">{}</data><data key=\"file\">{}</data></node>",
+                escape_xml(&meta.id),
+                meta.kind,
+                escape_xml(&meta.name),
+                escape_xml(&meta.fully_qualified_name),
+                escape_xml(&item.project_context.file_path),
+            )?,
+        }
+        Ok(())
+    }
+
+    pub fn write_edge(&mut self, edge: &Edge) -> CoreResult<()> {
+        match self.format {
+            GraphFormat::Jsonl => {
+                let edge = json!({
+                    "type": "edge",
+                    "source": edge.source,
+                    "target": edge.target,
+                    "kind": edge.kind,
+                });
+                serde_json::to_writer(&mut self.writer, &edge)?;
+                self.writer.write_all(b"\n")?;
+            }
+            GraphFormat::Graphml => writeln!(
+                self.writer,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"edge_kind\">{}</data></edge>",
+                escape_xml(&edge.source),
+                escape_xml(&edge.target),
+                edge.kind,
+            )?,
+        }
+        Ok(())
+    }
+
+    /// Close the graph and flush the file
+    pub fn finish(&mut self) -> CoreResult<()> {
+        if self.format == GraphFormat::Graphml {
+            self.writer.write_all(b"  </graph>\n</graphml>\n")?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+const GRAPHML_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="kind" for="node" attr.name="kind" attr.type="string"/>
+  <key id="name" for="node" attr.name="name" attr.type="string"/>
+  <key id="fqn" for="node" attr.name="fully_qualified_name" attr.type="string"/>
+  <key id="file" for="node" attr.name="file_path" attr.type="string"/>
+  <key id="edge_kind" for="edge" attr.name="kind" attr.type="string"/>
+  <graph id="G" edgedefault="directed">
+"#;
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::extract_items_with_context;
+    use crate::normalizer::normalize_items;
+
+    const SOURCE: &str = r#"
+pub trait Shape {
+    fn area(&self) -> f64;
+}
+
+pub struct Square {
+    side: f64,
+}
+
+impl Square {
+    pub fn new(side: f64) -> Self {
+        Square { side }
+    }
+}
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.side * self.side
+    }
+}
+
+mod util {
+    pub const SCALE: f64 = 2.0;
+
+    pub fn total(shapes: &[super::Square]) -> f64 {
+        shapes.len() as f64
+    }
+}
+
+mod report {
+    use super::util::total;
+    use crate::Square;
+
+    pub fn print() -> f64 {
+        let square = Square::new(1.0);
+        assert!(square.area() > 0.0);
+        total(&[]) * super::util::SCALE
+    }
+}
+"#;
+
+    fn graph() -> (Vec<ExtractedItem>, Graph) {
+        let context = ProjectContext {
+            module_path: Some("shapes".to_string()),
+            ..Default::default()
+        };
+        let config = ExtractConfig::default();
+        let mut items = extract_items_with_context(SOURCE, context, &config).unwrap();
+        normalize_items(&mut items, &config).unwrap();
+
+        let mut builder = GraphBuilder::new();
+        for item in &items {
+            builder.observe(item);
+        }
+        let graph = builder.finish();
+        for item in &mut items {
+            graph.apply(item);
+        }
+        (items, graph)
+    }
+
+    fn edges_from(items: &[ExtractedItem], graph: &Graph, fqn: &str) -> Vec<(String, EdgeKind)> {
+        let name = |id: &str| {
+            items
+                .iter()
+                .find(|item| item.item_meta.id == id)
+                .map(|item| item.item_meta.fully_qualified_name.clone())
+                .unwrap()
+        };
+        graph
+            .edges
+            .iter()
+            .filter(|edge| name(&edge.source) == fqn)
+            .map(|edge| (name(&edge.target), edge.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_references_are_resolved() {
+        let (items, graph) = graph();
+
+        assert_eq!(
+            edges_from(&items, &graph, "shapes::report::print"),
+            [
+                ("shapes::Square::new".to_string(), EdgeKind::Call),
+                (
+                    "shapes::<Square as Shape>::area".to_string(),
+                    EdgeKind::MethodCall
+                ),
+                ("shapes::util::total".to_string(), EdgeKind::Call),
+                ("shapes::util::SCALE".to_string(), EdgeKind::Value),
+            ]
+        );
+        assert_eq!(
+            edges_from(&items, &graph, "shapes::<Square as Shape>"),
+            [
+                ("shapes::Shape".to_string(), EdgeKind::Impl),
+                ("shapes::Square".to_string(), EdgeKind::Type),
+            ]
+        );
+        assert_eq!(
+            edges_from(&items, &graph, "shapes::Square::new"),
+            [("shapes::Square".to_string(), EdgeKind::Type)]
+        );
+        assert_eq!(
+            edges_from(&items, &graph, "shapes::util::total"),
+            [("shapes::Square".to_string(), EdgeKind::Type)]
+        );
+
+        let square = items
+            .iter()
+            .find(|item| item.item_meta.fully_qualified_name == "shapes::Square")
+            .unwrap();
+        // Both impls, `new` and `total`; `&self` receivers do not count
+        assert_eq!(square.item_meta.referenced_by.len(), 4);
+        assert!(square.item_meta.references.is_empty());
+    }
+
+    #[test]
+    fn test_graphml_is_escaped() {
+        let (items, graph) = graph();
+        let mut writer = GraphWriter::new(Vec::new(), GraphFormat::Graphml).unwrap();
+        for item in &items {
+            writer.write_node(item).unwrap();
+        }
+        for edge in &graph.edges {
+            writer.write_edge(edge).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let xml = String::from_utf8(writer.writer.into_inner().unwrap()).unwrap();
+        assert!(xml.contains("<data key=\"fqn\">shapes::&lt;Square as Shape&gt;</data>"));
+        assert!(xml.contains("<data key=\"edge_kind\">impl</data>"));
+        assert!(xml.ends_with("</graph>\n</graphml>\n"));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::models::*;
 use blake3::Hasher;
+use std::collections::HashMap;
 
 /// Semantic hashing utilities for deduplication
 ///
@@ -9,19 +10,17 @@ use blake3::Hasher;
 ///
 /// Generate a semantic hash from an ExtractedItem's normalized content
 ///
-/// This function creates a BLAKE3 hash of the normalized code body,
-/// signature, and other semantic content to create a unique identifier
-/// for deduplication purposes.
+/// This function creates a BLAKE3 hash of the normalized code body
+/// and item kind to create a unique identifier for deduplication purposes.
 pub fn semantic_hash_item(item: &ExtractedItem) -> CoreResult<String> {
     // Create a hasher for this item
     let mut hasher = Hasher::new();
 
-    // Hash the normalized body (most important for semantic similarity)
+    // Hash the normalized body (most important for semantic similarity).
+    // It already contains the normalized signature; the raw `signature`
+    // field keeps the original parameter names and is left out on purpose.
     hasher.update(item.content.body_normalized.as_bytes());
 
-    // Hash the signature
-    hasher.update(item.content.signature.as_bytes());
-
     // Hash the item kind
     hasher.update(item.item_meta.kind.to_string().as_bytes());
 
@@ -82,7 +81,7 @@ pub fn hash_and_update_items(items: &mut [ExtractedItem]) -> CoreResult<()> {
 /// Verify semantic similarity between two items
 ///
 /// Returns true if the two items have identical semantic content
-/// (normalized body, signature, and type).
+/// (normalized body and type).
 pub fn are_semantically_similar(item1: &ExtractedItem, item2: &ExtractedItem) -> CoreResult<bool> {
     let hash1 = semantic_hash_item(item1)?;
     let hash2 = semantic_hash_item(item2)?;
@@ -98,68 +97,326 @@ pub fn hash_bytes(item: &ExtractedItem) -> CoreResult<[u8; 32]> {
     let mut hasher = Hasher::new();
 
     hasher.update(item.content.body_normalized.as_bytes());
-    hasher.update(item.content.signature.as_bytes());
     hasher.update(item.item_meta.kind.to_string().as_bytes());
 
     let hash = hasher.finalize();
     Ok(*hash.as_bytes())
 }
 
-/// Calculate Hamming distance between two semantic hashes
+/// Settings for MinHash near-duplicate detection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NearDupConfig {
+    /// Estimated Jaccard similarity at or above which items are near duplicates
+    pub threshold: f64,
+    /// Number of MinHash permutations per signature
+    pub num_perm: usize,
+    /// Number of consecutive tokens per shingle
+    pub shingle_size: usize,
+}
+
+impl Default for NearDupConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.8,
+            num_perm: 128,
+            shingle_size: 5,
+        }
+    }
+}
+
+/// MinHash signature of the token shingles of a code fragment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MinHashSignature(Vec<u64>);
+
+impl MinHashSignature {
+    /// Estimated Jaccard similarity of the two underlying shingle sets
+    pub fn jaccard(&self, other: &MinHashSignature) -> f64 {
+        if self.0.is_empty() || self.0.len() != other.0.len() {
+            return 0.0;
+        }
+        let equal = self.0.iter().zip(&other.0).filter(|(a, b)| a == b).count();
+        equal as f64 / self.0.len() as f64
+    }
+}
+
+/// Computes MinHash signatures over normalized tokens
 ///
-/// Returns the number of differing bits between two hash values.
-/// Useful for approximate similarity matching.
-pub fn hash_hamming_distance(hash1: &str, hash2: &str) -> Option<usize> {
-    if !hash1.starts_with("blake3:") || !hash2.starts_with("blake3:") {
-        return None;
+/// Hashing is seeded deterministically, so signatures computed in different
+/// runs (or processes) with the same configuration are comparable.
+#[derive(Debug, Clone)]
+pub struct MinHasher {
+    seeds: Vec<u64>,
+    shingle_size: usize,
+}
+
+impl MinHasher {
+    pub fn new(config: &NearDupConfig) -> Self {
+        let mut state = 0x5eed_u64;
+        let seeds = (0..config.num_perm.max(1))
+            .map(|_| {
+                state = splitmix64(state);
+                state
+            })
+            .collect();
+        Self {
+            seeds,
+            shingle_size: config.shingle_size.max(1),
+        }
     }
 
-    let bytes1 = &hex::decode(&hash1[7..]).ok()?[0..32];
-    let bytes2 = &hex::decode(&hash2[7..]).ok()?[0..32];
+    /// Signature of a normalized code fragment, `None` if it has no tokens
+    pub fn signature(&self, code: &str) -> Option<MinHashSignature> {
+        let tokens = code_tokens(code);
+        if tokens.is_empty() {
+            return None;
+        }
 
-    let mut distance = 0;
-    for i in 0..32 {
-        let xored = bytes1[i] ^ bytes2[i];
-        // Count set bits in the xor result
-        distance += xored.count_ones() as usize;
+        // Fragments shorter than a shingle form a single shingle
+        let shingles: Vec<u64> = tokens
+            .windows(self.shingle_size.min(tokens.len()))
+            .map(|window| fnv1a(window.iter().map(String::as_bytes)))
+            .collect();
+
+        let minimums = self
+            .seeds
+            .iter()
+            .map(|&seed| {
+                shingles
+                    .iter()
+                    .map(|&shingle| splitmix64(shingle ^ seed))
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect();
+
+        Some(MinHashSignature(minimums))
     }
+}
+
+/// An indexed item that a new item nearly duplicates
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearMatch {
+    pub id: String,
+    pub similarity: f64,
+}
 
-    Some(distance)
+struct IndexedSignature {
+    id: String,
+    kind: ItemKind,
+    signature: MinHashSignature,
 }
 
-/// Find similar items within a collection
+/// Streaming near-duplicate index using MinHash with LSH banding
+///
+/// Signatures are split into bands; items sharing any band bucket are
+/// candidates, and candidates are confirmed against the threshold with the
+/// full signature. Lookups therefore only touch a few candidates instead of
+/// every earlier item. Only items of the same kind are compared.
 ///
-/// Given a collection of items and a similarity threshold (0-255),
-/// returns pairs of indices that are semantically similar.
-/// Lower threshold means more similarity required.
+/// Code is tokenized with `proc_macro2`, whose span source map keeps a copy
+/// of everything parsed on the thread; callers inserting a whole dataset
+/// should call `proc_macro2::extra::invalidate_current_thread_spans` once
+/// they hold no spans, as the CLI does after each written item.
+pub struct NearDupIndex {
+    hasher: MinHasher,
+    threshold: f64,
+    rows: usize,
+    buckets: Vec<HashMap<u64, Vec<usize>>>,
+    entries: Vec<IndexedSignature>,
+}
+
+impl NearDupIndex {
+    pub fn new(config: &NearDupConfig) -> Self {
+        let hasher = MinHasher::new(config);
+        let (bands, rows) = lsh_bands(hasher.seeds.len(), config.threshold);
+        Self {
+            hasher,
+            threshold: config.threshold,
+            rows,
+            buckets: vec![HashMap::new(); bands],
+            entries: Vec::new(),
+        }
+    }
+
+    /// Number of items held by the index
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return the most similar indexed item at or above the threshold, or
+    /// index the item if there is none.
+    pub fn insert(&mut self, item: &ExtractedItem) -> Option<NearMatch> {
+        let (index, similarity) = self.insert_entry(item)?;
+        Some(NearMatch {
+            id: self.entries[index].id.clone(),
+            similarity,
+        })
+    }
+
+    /// `insert`, returning the position of the matched entry in the index
+    /// rather than its id; an indexed item becomes entry `len()`
+    fn insert_entry(&mut self, item: &ExtractedItem) -> Option<(usize, f64)> {
+        let signature = self.hasher.signature(&item.content.body_normalized)?;
+        let kind = item.item_meta.kind.clone();
+        let keys = self.band_keys(&kind, &signature);
+
+        let mut best: Option<(usize, f64)> = None;
+        let mut seen = std::collections::HashSet::new();
+        for (bucket, key) in self.buckets.iter().zip(&keys) {
+            for &index in bucket.get(key).into_iter().flatten() {
+                if !seen.insert(index) {
+                    continue;
+                }
+                let entry = &self.entries[index];
+                if entry.kind != kind {
+                    continue;
+                }
+                let similarity = entry.signature.jaccard(&signature);
+                if similarity >= self.threshold && best.is_none_or(|(_, best)| similarity > best) {
+                    best = Some((index, similarity));
+                }
+            }
+        }
+        if best.is_some() {
+            return best;
+        }
+
+        let index = self.entries.len();
+        for (bucket, key) in self.buckets.iter_mut().zip(keys) {
+            bucket.entry(key).or_default().push(index);
+        }
+        self.entries.push(IndexedSignature {
+            id: item.item_meta.id.clone(),
+            kind,
+            signature,
+        });
+        None
+    }
+
+    fn band_keys(&self, kind: &ItemKind, signature: &MinHashSignature) -> Vec<u64> {
+        let kind = kind.to_string();
+        signature
+            .0
+            .chunks(self.rows)
+            .take(self.buckets.len())
+            .map(|band| {
+                let rows = band.iter().map(|value| value.to_le_bytes());
+                fnv1a(std::iter::once(kind.as_bytes().to_vec()).chain(rows.map(Vec::from)))
+            })
+            .collect()
+    }
+}
+
+/// Find near-duplicate pairs within a collection
+///
+/// Returns `(earlier, later, similarity)` for every later item whose
+/// estimated Jaccard similarity to an earlier, non-duplicate item reaches
+/// `config.threshold`. Each later item is reported at most once, paired
+/// with its most similar earlier item; items are told apart by position,
+/// so empty or repeated ids are fine.
 pub fn find_similar_items(
     items: &[ExtractedItem],
-    max_distance: u8,
-) -> CoreResult<Vec<(usize, usize, usize)>> {
+    config: &NearDupConfig,
+) -> Vec<(usize, usize, f64)> {
+    let mut index = NearDupIndex::new(config);
+    // Position in `items` of each entry of the index
+    let mut positions = Vec::new();
     let mut similar_pairs = Vec::new();
-    let hashes: Vec<String> = semantic_hash_items(items)?;
 
-    for i in 0..items.len() {
-        for j in (i + 1)..items.len() {
-            if let Some(distance) = hash_hamming_distance(&hashes[i], &hashes[j]) {
-                if distance <= max_distance as usize {
-                    similar_pairs.push((i, j, distance));
+    for (position, item) in items.iter().enumerate() {
+        match index.insert_entry(item) {
+            Some((entry, similarity)) => {
+                similar_pairs.push((positions[entry], position, similarity));
+            }
+            None if index.len() > positions.len() => positions.push(position),
+            None => {}
+        }
+    }
+
+    similar_pairs
+}
+
+/// Band count and rows per band with the highest LSH threshold
+/// `(1/b)^(1/r)` that does not exceed the similarity threshold
+///
+/// Erring low trades extra candidates, which are verified anyway, for fewer
+/// missed near duplicates.
+fn lsh_bands(num_perm: usize, threshold: f64) -> (usize, usize) {
+    let lsh_threshold =
+        |(bands, rows): (usize, usize)| (1.0 / bands as f64).powf(1.0 / rows as f64);
+    (1..=num_perm)
+        .filter(|&rows| num_perm.is_multiple_of(rows))
+        .map(|rows| (num_perm / rows, rows))
+        .filter(|&bands| lsh_threshold(bands) <= threshold)
+        .max_by(|&a, &b| lsh_threshold(a).total_cmp(&lsh_threshold(b)))
+        .unwrap_or((num_perm, 1))
+}
+
+/// Lexical tokens of a code fragment, with whitespace and layout dropped
+fn code_tokens(code: &str) -> Vec<String> {
+    fn walk(stream: proc_macro2::TokenStream, out: &mut Vec<String>) {
+        for tree in stream {
+            match tree {
+                proc_macro2::TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        proc_macro2::Delimiter::Parenthesis => ("(", ")"),
+                        proc_macro2::Delimiter::Brace => ("{", "}"),
+                        proc_macro2::Delimiter::Bracket => ("[", "]"),
+                        proc_macro2::Delimiter::None => ("", ""),
+                    };
+                    if !open.is_empty() {
+                        out.push(open.to_string());
+                    }
+                    walk(group.stream(), out);
+                    if !close.is_empty() {
+                        out.push(close.to_string());
+                    }
                 }
+                other => out.push(other.to_string()),
             }
         }
     }
 
-    Ok(similar_pairs)
+    match code.parse::<proc_macro2::TokenStream>() {
+        Ok(stream) => {
+            let mut tokens = Vec::new();
+            walk(stream, &mut tokens);
+            tokens
+        }
+        Err(_) => code.split_whitespace().map(str::to_string).collect(),
+    }
+}
+
+/// 64-bit FNV-1a over a sequence of byte strings, separated so that
+/// `["ab", "c"]` and `["a", "bc"]` differ
+fn fnv1a<B: AsRef<[u8]>>(parts: impl Iterator<Item = B>) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    for part in parts {
+        for &byte in part.as_ref().iter().chain(&[0xff]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
 }
 
 /// Build a deduplication map from semantic hashes
 ///
 /// Returns a HashMap where keys are semantic hashes and values are
 /// indices of items with that hash. Useful for removing duplicates.
-pub fn build_dedup_map(
-    items: &[ExtractedItem],
-) -> CoreResult<std::collections::HashMap<String, Vec<usize>>> {
-    let mut dedup_map = std::collections::HashMap::new();
+pub fn build_dedup_map(items: &[ExtractedItem]) -> CoreResult<HashMap<String, Vec<usize>>> {
+    let mut dedup_map = HashMap::new();
     let hashes = semantic_hash_items(items)?;
 
     for (idx, hash) in hashes.into_iter().enumerate() {
@@ -185,30 +442,39 @@ impl HashStats {
     /// Analyze hash statistics for a collection of items
     pub fn analyze(items: &[ExtractedItem]) -> CoreResult<Self> {
         let dedup_map = build_dedup_map(items)?;
-        let unique_hashes = dedup_map.len();
+        Ok(Self::from_group_sizes(
+            items.len(),
+            dedup_map.values().map(Vec::len),
+        ))
+    }
+
+    /// Compute statistics from the sizes of groups of identical hashes
+    fn from_group_sizes(total_items: usize, group_sizes: impl Iterator<Item = usize>) -> Self {
+        let mut unique_hashes = 0;
         let mut collision_count = 0;
         let mut max_collision_group = 0;
 
-        for group in dedup_map.values() {
-            if group.len() > 1 {
-                collision_count += group.len() - 1;
+        for group_size in group_sizes {
+            unique_hashes += 1;
+            if group_size > 1 {
+                collision_count += group_size - 1;
             }
-            max_collision_group = max_collision_group.max(group.len());
+            max_collision_group = max_collision_group.max(group_size);
         }
 
-        let collision_rate = if items.is_empty() {
+        let collision_rate = if total_items == 0 {
             0.0
         } else {
-            collision_count as f64 / items.len() as f64
+            collision_count as f64 / total_items as f64
         };
 
-        Ok(HashStats {
-            total_items: items.len(),
+        HashStats {
+            total_items,
             unique_hashes,
             collision_count,
             max_collision_group,
             collision_rate,
-        })
+        }
     }
 
     /// Pretty-print the statistics
@@ -222,6 +488,35 @@ impl HashStats {
     }
 }
 
+/// Running hash statistics for items that are streamed rather than collected
+///
+/// Only one counter per distinct hash is kept, so memory does not grow with
+/// the size of the items themselves.
+#[derive(Debug, Default)]
+pub struct HashStatsAggregator {
+    group_sizes: HashMap<String, usize>,
+    total_items: usize,
+}
+
+impl HashStatsAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Account for one more item
+    pub fn add(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        let hash = semantic_hash_item(item)?;
+        *self.group_sizes.entry(hash).or_insert(0) += 1;
+        self.total_items += 1;
+        Ok(())
+    }
+
+    /// Statistics for all items added so far
+    pub fn stats(&self) -> HashStats {
+        HashStats::from_group_sizes(self.total_items, self.group_sizes.values().copied())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,20 +526,40 @@ mod tests {
         use crate::models::*;
 
         let item1 = ExtractedItem {
+            schema_version: SCHEMA_VERSION.to_string(),
             project_context: ProjectContext {
                 repo_url: "test".to_string(),
                 commit_hash: "test".to_string(),
                 file_path: "test.rs".to_string(),
+                crate_name: None,
+                crate_version: None,
+                target_kind: None,
+                edition: None,
+                module_path: None,
+                license: None,
             },
             item_meta: ItemMeta {
+                id: "test.rs:1:0".to_string(),
+                parent_id: None,
                 kind: ItemKind::Function,
                 name: "test_fn".to_string(),
                 fully_qualified_name: "test::test_fn".to_string(),
                 start_line: 1,
                 end_line: 5,
+                start_column: 0,
+                end_column: 1,
+                start_byte: 0,
+                end_byte: 12,
+                duplicate_of: None,
+                change: None,
+                redactions: Vec::new(),
+                metrics: ItemMetrics::default(),
+                references: Vec::new(),
+                referenced_by: Vec::new(),
             },
             content: Content {
                 signature: "fn test()".to_string(),
+                generics: None,
                 body_normalized: "fn test() {}".to_string(),
                 semantic_hash: String::new(),
                 docstring: None,
@@ -254,6 +569,7 @@ mod tests {
                 context_before: None,
                 context_after: None,
             },
+            fim: None,
         };
 
         let item2 = item1.clone();
@@ -265,14 +581,86 @@ mod tests {
     }
 
     #[test]
-    fn test_hamming_distance() {
-        let hash1 = "blake3:0000000000000000000000000000000000000000000000000000000000000000";
-        let hash2 = "blake3:0000000000000000000000000000000000000000000000000000000000000001";
-
-        let distance = hash_hamming_distance(hash1, hash2).unwrap();
-        assert_eq!(
-            distance, 1,
-            "Single bit difference should give distance of 1"
-        );
+    fn test_minhash_estimates_similarity() {
+        let hasher = MinHasher::new(&NearDupConfig::default());
+        let base = "fn f(a: u32) -> u32 { let b = a + 1; let c = b * 2; let d = c - 3; d / 4 }";
+        let edited = "fn f(a: u32) -> u32 { let b = a + 1; let c = b * 2; let d = c - 3; d / 5 }";
+        let other = "struct S { field: Vec<String>, other: Option<Box<S>> }";
+
+        let base = hasher.signature(base).unwrap();
+        assert_eq!(base.jaccard(&base), 1.0);
+        assert!(base.jaccard(&hasher.signature(edited).unwrap()) > 0.7);
+        assert!(base.jaccard(&hasher.signature(other).unwrap()) < 0.1);
+        assert!(hasher.signature("").is_none());
+    }
+
+    #[test]
+    fn test_near_duplicates_are_found() {
+        let item = |id: &str, kind: ItemKind, body: &str| ExtractedItem {
+            item_meta: ItemMeta {
+                id: id.to_string(),
+                kind,
+                ..Default::default()
+            },
+            content: Content {
+                body_normalized: body.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let body = "fn f(v0: &[u8]) -> usize { let mut v1 = 0; for v2 in v0 { if *v2 > 10 { v1 += 1; } else if *v2 < 5 { v1 -= 1; } else { v1 *= 2; } } while v1 > 100 { v1 /= 3; } v1 }";
+        let near = body.replace("*= 2", "*= 4");
+        let items = vec![
+            item("a", ItemKind::Function, body),
+            item(
+                "b",
+                ItemKind::Function,
+                "fn g() { println!(\"unrelated\") }",
+            ),
+            item("c", ItemKind::Function, &near),
+            item("d", ItemKind::Method, body),
+        ];
+
+        let pairs = find_similar_items(&items, &NearDupConfig::default());
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0, pairs[0].1), (0, 2));
+        assert!(pairs[0].2 >= 0.8);
+
+        // Pairs are found by position, whatever the ids
+        let anonymous: Vec<ExtractedItem> = items
+            .into_iter()
+            .map(|mut item| {
+                item.item_meta.id.clear();
+                item
+            })
+            .collect();
+        let pairs = find_similar_items(&anonymous, &NearDupConfig::default());
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((pairs[0].0, pairs[0].1), (0, 2));
+    }
+
+    #[test]
+    fn test_aggregator_matches_analyze() {
+        let item = |body: &str| ExtractedItem {
+            content: Content {
+                body_normalized: body.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let items = vec![item("a"), item("b"), item("a"), item("a")];
+
+        let mut aggregator = HashStatsAggregator::new();
+        for item in &items {
+            aggregator.add(item).unwrap();
+        }
+        let streamed = aggregator.stats();
+        let analyzed = HashStats::analyze(&items).unwrap();
+
+        assert_eq!(streamed.total_items, analyzed.total_items);
+        assert_eq!(streamed.unique_hashes, 2);
+        assert_eq!(streamed.unique_hashes, analyzed.unique_hashes);
+        assert_eq!(streamed.collision_count, analyzed.collision_count);
+        assert_eq!(streamed.max_collision_group, analyzed.max_collision_group);
     }
 }
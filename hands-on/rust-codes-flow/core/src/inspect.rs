@@ -0,0 +1,199 @@
+use crate::fim::SplitMix64;
+use crate::hashing::semantic_hash_item;
+use crate::models::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Uniform random sample of a stream of items, by reservoir sampling.
+///
+/// Only `size` items are held at a time, and the same seed over the same
+/// stream always gives the same sample, returned in stream order.
+pub struct Sample<T> {
+    size: usize,
+    seen: usize,
+    items: Vec<(usize, T)>,
+    rng: SplitMix64,
+}
+
+impl<T> Sample<T> {
+    pub fn new(size: usize, seed: u64) -> Self {
+        Self {
+            size,
+            seen: 0,
+            items: Vec::with_capacity(size),
+            rng: SplitMix64::new(seed),
+        }
+    }
+
+    /// Offer the next item of the stream
+    pub fn push(&mut self, item: T) {
+        let index = self.seen;
+        self.seen += 1;
+        if self.items.len() < self.size {
+            self.items.push((index, item));
+        } else {
+            let slot = self.rng.below(self.seen);
+            if slot < self.size {
+                self.items[slot] = (index, item);
+            }
+        }
+    }
+
+    /// Number of items offered so far
+    pub fn seen(&self) -> usize {
+        self.seen
+    }
+
+    /// The sampled items, in the order they were pushed
+    pub fn into_items(mut self) -> Vec<T> {
+        self.items.sort_by_key(|(index, _)| *index);
+        self.items.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+/// Checks the records of an existing dataset.
+///
+/// Every item's `semantic_hash` must match its normalized content and its
+/// line, column and byte ranges must be well formed. When the item's file
+/// can be read (paths are taken relative to `root`), the ranges must also
+/// fall inside it; items whose file cannot be read are only counted.
+pub struct Validator {
+    root: PathBuf,
+    /// Line count and length of the files read so far, `None` if unreadable
+    files: HashMap<String, Option<(u32, usize)>>,
+    missing_files: usize,
+}
+
+impl Validator {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            files: HashMap::new(),
+            missing_files: 0,
+        }
+    }
+
+    /// Problems with one item, empty if it is valid
+    pub fn check(&mut self, item: &ExtractedItem) -> CoreResult<Vec<String>> {
+        let mut problems = Vec::new();
+        let meta = &item.item_meta;
+
+        let expected = semantic_hash_item(item)?;
+        if item.content.semantic_hash != expected {
+            problems.push(format!(
+                "semantic_hash {} does not match the content ({})",
+                item.content.semantic_hash, expected
+            ));
+        }
+
+        if meta.start_line == 0 || meta.start_line > meta.end_line {
+            problems.push(format!(
+                "line range {}..={} is empty or not 1-based",
+                meta.start_line, meta.end_line
+            ));
+        }
+        if meta.start_line == meta.end_line && meta.start_column > meta.end_column {
+            problems.push(format!(
+                "column range {}..{} is reversed",
+                meta.start_column, meta.end_column
+            ));
+        }
+        if meta.start_byte > meta.end_byte {
+            problems.push(format!(
+                "byte range {}..{} is reversed",
+                meta.start_byte, meta.end_byte
+            ));
+        }
+
+        match self.extent(&item.project_context.file_path) {
+            Some((lines, bytes)) => {
+                if meta.end_line > lines {
+                    problems.push(format!(
+                        "line range {}..={} is past the end of {} ({} lines)",
+                        meta.start_line, meta.end_line, item.project_context.file_path, lines
+                    ));
+                }
+                if meta.end_byte > bytes {
+                    problems.push(format!(
+                        "byte range {}..{} is past the end of {} ({} bytes)",
+                        meta.start_byte, meta.end_byte, item.project_context.file_path, bytes
+                    ));
+                }
+            }
+            None => self.missing_files += 1,
+        }
+
+        Ok(problems)
+    }
+
+    /// Number of items whose file could not be read
+    pub fn missing_files(&self) -> usize {
+        self.missing_files
+    }
+
+    fn extent(&mut self, file_path: &str) -> Option<(u32, usize)> {
+        let root = &self.root;
+        *self.files.entry(file_path.to_string()).or_insert_with(|| {
+            let source = fs::read_to_string(root.join(file_path)).ok()?;
+            Some((source.lines().count() as u32, source.len()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn test_sample_is_seeded_and_ordered() {
+        let sample = |seed| {
+            let mut sample = Sample::new(5, seed);
+            for n in 0..100 {
+                sample.push(n);
+            }
+            sample.into_items()
+        };
+
+        let first = sample(1);
+        assert_eq!(first.len(), 5);
+        assert!(first.is_sorted());
+        assert_eq!(first, sample(1));
+        assert_ne!(first, sample(2));
+
+        let mut short = Sample::new(5, 0);
+        short.push("only");
+        assert_eq!(short.into_items(), ["only"]);
+    }
+
+    #[test]
+    fn test_validator_checks_hashes_and_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = "fn one() -> u32 {\n    1\n}\n\nfn two() {}\n";
+        fs::write(dir.path().join("lib.rs"), source).unwrap();
+
+        let config = ExtractConfig::default();
+        let pipeline = Pipeline::new(&config, String::new(), String::new());
+        let items = pipeline
+            .process_source(Path::new("lib.rs"), source)
+            .unwrap();
+        let mut validator = Validator::new(dir.path());
+        for item in &items {
+            assert_eq!(validator.check(item).unwrap(), Vec::<String>::new());
+        }
+
+        let mut edited = items[0].clone();
+        edited.content.body_normalized.push_str(" // edited");
+        edited.item_meta.end_line = 9;
+        let problems = validator.check(&edited).unwrap();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("semantic_hash"));
+        assert!(problems[1].contains("past the end of lib.rs (5 lines)"));
+
+        let mut moved = items[1].clone();
+        moved.project_context.file_path = "gone.rs".to_string();
+        assert!(validator.check(&moved).unwrap().is_empty());
+        assert_eq!(validator.missing_files(), 1);
+    }
+}
@@ -3,16 +3,63 @@
 //! Core library for parsing, normalizing, and extracting Rust code items
 //! into structured datasets for AI training.
 
+pub mod cache;
+pub mod columnar;
+pub mod dedup;
+pub mod diff;
+pub mod discovery;
 pub mod extractor;
+pub mod fim;
+pub mod graph;
 pub mod hashing;
+pub mod inspect;
+pub mod license;
+pub mod metrics;
 pub mod models;
 pub mod normalizer;
+pub mod output;
+pub mod pipeline;
+pub mod schema;
+pub mod scrub;
+pub mod sft;
+pub mod shards;
+pub mod workspace;
 
 pub use models::{
-    Content, CoreError, CoreResult, ExtractConfig, ExtractedItem, ItemKind, ItemMeta,
-    ProjectContext, RagContext,
+    ChangeKind, Content, CoreError, CoreResult, ExtractConfig, ExtractedItem, FimConfig, FimOrder,
+    FimSample, FimSentinels, FimSpanKind, ItemKind, ItemMeta, ItemMetrics, ProjectContext,
+    RagContext, SCHEMA_VERSION, TargetKind,
 };
 
-pub use extractor::extract_items_from_file;
-pub use hashing::{HashStats, hash_and_update_items};
+pub use cache::{ExtractionCache, content_hash};
+pub use columnar::{ArrowIpcWriter, ColumnarOptions, ParquetWriter, item_schema};
+pub use dedup::{DedupMode, Deduplicator};
+pub use diff::diff_items;
+pub use discovery::{Discovery, DiscoveryOptions, FileFilter, find_rust_files};
+pub use extractor::{
+    dataset_path, extract_items_from_file, extract_items_from_source, extract_items_with_context,
+    item_id,
+};
+pub use fim::{fim_prompt, fim_sample};
+pub use graph::{Edge, EdgeKind, Graph, GraphBuilder, GraphFormat, GraphWriter};
+pub use hashing::{
+    HashStats, HashStatsAggregator, MinHashSignature, MinHasher, NearDupConfig, NearDupIndex,
+    NearMatch, hash_and_update_items,
+};
+pub use inspect::{Sample, Validator};
+pub use license::{
+    LicenseExpr, LicensePolicy, NOASSERTION, detect_directory_license, identify_license_text,
+    normalize_license, spdx_header,
+};
+pub use metrics::{QualityFilter, measure};
 pub use normalizer::normalize_items;
+pub use output::{
+    Compression, ItemSink, JsonlReader, JsonlWriter, OutputFormat, OutputOptions, Spool,
+    create_sink,
+};
+pub use pipeline::Pipeline;
+pub use schema::json_schema;
+pub use scrub::{scrub_code, scrub_item, scrub_items, scrub_text};
+pub use sft::{SftConfig, SftExporter, SftFormat, SftSample, SftTemplate};
+pub use shards::{DatasetReader, ShardInfo, ShardManifest, ShardSize, ShardedJsonlWriter};
+pub use workspace::{FileModule, Package, Target, WorkspaceIndex};
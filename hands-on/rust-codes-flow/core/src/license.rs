@@ -0,0 +1,514 @@
+use crate::models::*;
+use std::fmt;
+use std::path::Path;
+
+/// License of a file whose license files could not be identified
+pub const NOASSERTION: &str = "NOASSERTION";
+
+/// Parsed SPDX license expression, e.g. `MIT OR Apache-2.0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LicenseExpr {
+    /// A license id, optionally with a `WITH` exception
+    License {
+        id: String,
+        exception: Option<String>,
+    },
+    And(Box<LicenseExpr>, Box<LicenseExpr>),
+    Or(Box<LicenseExpr>, Box<LicenseExpr>),
+}
+
+impl LicenseExpr {
+    /// Parse an expression; `AND` binds tighter than `OR`, operators are
+    /// case-insensitive and `/` is read as `OR` like old `Cargo.toml`s do
+    pub fn parse(text: &str) -> CoreResult<Self> {
+        let spaced = text
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .replace('/', " OR ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        let mut parser = ExprParser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let expr = parser.or().and_then(|expr| match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected `{token}`")),
+        });
+        expr.map_err(|reason| {
+            CoreError::InvalidInput(format!("invalid SPDX expression `{text}`: {reason}"))
+        })
+    }
+
+    /// Whether the expression can be satisfied using only licenses that
+    /// `accept` approves: one side of an `OR`, both sides of an `AND`
+    pub fn is_satisfied_by(&self, accept: &impl Fn(&str, Option<&str>) -> bool) -> bool {
+        match self {
+            LicenseExpr::License { id, exception } => accept(id, exception.as_deref()),
+            LicenseExpr::And(left, right) => {
+                left.is_satisfied_by(accept) && right.is_satisfied_by(accept)
+            }
+            LicenseExpr::Or(left, right) => {
+                left.is_satisfied_by(accept) || right.is_satisfied_by(accept)
+            }
+        }
+    }
+}
+
+impl fmt::Display for LicenseExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Parenthesize an `OR` nested in an `AND`; everything else reads
+        // correctly without
+        let operand = |f: &mut fmt::Formatter<'_>, expr: &LicenseExpr| match expr {
+            LicenseExpr::Or(..) => write!(f, "({expr})"),
+            _ => write!(f, "{expr}"),
+        };
+        match self {
+            LicenseExpr::License {
+                id,
+                exception: None,
+            } => write!(f, "{id}"),
+            LicenseExpr::License {
+                id,
+                exception: Some(exception),
+            } => write!(f, "{id} WITH {exception}"),
+            LicenseExpr::And(left, right) => {
+                operand(f, left)?;
+                write!(f, " AND ")?;
+                operand(f, right)
+            }
+            LicenseExpr::Or(left, right) => write!(f, "{left} OR {right}"),
+        }
+    }
+}
+
+/// Recursive descent over whitespace separated tokens; errors are reasons
+/// to be wrapped by `LicenseExpr::parse`
+struct ExprParser<'a> {
+    tokens: &'a [&'a str],
+    position: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, String> {
+        let token = self.peek().ok_or("unexpected end")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, keyword: &str) -> bool {
+        let found = self
+            .peek()
+            .is_some_and(|token| token.eq_ignore_ascii_case(keyword));
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<LicenseExpr, String> {
+        let mut expr = self.and()?;
+        while self.eat("OR") {
+            expr = LicenseExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<LicenseExpr, String> {
+        let mut expr = self.primary()?;
+        while self.eat("AND") {
+            expr = LicenseExpr::And(Box::new(expr), Box::new(self.primary()?));
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<LicenseExpr, String> {
+        if self.eat("(") {
+            let expr = self.or()?;
+            if !self.eat(")") {
+                return Err("missing `)`".to_string());
+            }
+            return Ok(expr);
+        }
+
+        let id = self.id()?;
+        let exception = if self.eat("WITH") {
+            Some(self.id()?)
+        } else {
+            None
+        };
+        Ok(LicenseExpr::License { id, exception })
+    }
+
+    fn id(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        let is_keyword = ["AND", "OR", "WITH", "(", ")"]
+            .iter()
+            .any(|keyword| token.eq_ignore_ascii_case(keyword));
+        let is_id = token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '+' | ':'));
+        if is_keyword || !is_id {
+            return Err(format!("expected a license id, found `{token}`"));
+        }
+        Ok(token.to_string())
+    }
+}
+
+/// Canonical form of a license expression, `None` if it does not parse
+pub fn normalize_license(text: &str) -> Option<String> {
+    LicenseExpr::parse(text).ok().map(|expr| expr.to_string())
+}
+
+/// Expression of an `SPDX-License-Identifier:` tag in the leading comments
+/// of a source file
+pub fn spdx_header(source: &str) -> Option<String> {
+    const TAG: &str = "SPDX-License-Identifier:";
+    source
+        .lines()
+        .take(20)
+        .map(str::trim)
+        .take_while(|line| {
+            line.is_empty()
+                || line.starts_with("//")
+                || line.starts_with("/*")
+                || line.starts_with('*')
+                || line.starts_with("#!")
+        })
+        .find_map(|line| {
+            let (_, expr) = line.split_once(TAG)?;
+            normalize_license(expr.trim().trim_end_matches("*/"))
+        })
+}
+
+/// Identify a license from the text of a license file
+///
+/// Matches distinctive sentences of common licenses. The GPL family is
+/// reported without `-only` or `-or-later`, which the text alone does not
+/// decide.
+pub fn identify_license_text(text: &str) -> Option<&'static str> {
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    let has = |needle: &str| text.contains(needle);
+
+    let id = if has("apache license") && has("version 2.0") {
+        "Apache-2.0"
+    } else if has("permission is hereby granted, free of charge") {
+        "MIT"
+    } else if has("mozilla public license") && has("2.0") {
+        "MPL-2.0"
+    } else if let Some(gnu) = gnu_license(&text) {
+        gnu?
+    } else if has("redistribution and use in source and binary forms") {
+        if has("endorse or promote") {
+            "BSD-3-Clause"
+        } else {
+            "BSD-2-Clause"
+        }
+    } else if has(
+        "permission to use, copy, modify, and/or distribute this software for any purpose",
+    ) {
+        if has("provided that the above copyright notice") {
+            "ISC"
+        } else {
+            "0BSD"
+        }
+    } else if has("this is free and unencumbered software released into the public domain") {
+        "Unlicense"
+    } else if has("altered source versions must be plainly marked") {
+        "Zlib"
+    } else if has("cc0 1.0 universal") {
+        "CC0-1.0"
+    } else if has("boost software license - version 1.0") {
+        "BSL-1.0"
+    } else {
+        return None;
+    };
+    Some(id)
+}
+
+/// GNU license named first in a normalized license text, `Some(None)` if
+/// its version cannot be told
+///
+/// The full texts mention their siblings (the GPL-3.0 has a section on the
+/// AGPL, the GPL-2.0 ends by pointing to the LGPL), so only the first
+/// mention counts: the title of a full text, or the license a short notice
+/// puts the program under. The version is the one that follows it.
+fn gnu_license(text: &str) -> Option<Option<&'static str>> {
+    const TITLES: [&str; 4] = [
+        "gnu affero general public license",
+        "gnu lesser general public license",
+        "gnu library general public license",
+        "gnu general public license",
+    ];
+    let (at, title) = TITLES
+        .iter()
+        .filter_map(|title| text.find(title).map(|at| (at, *title)))
+        .min()?;
+
+    let rest = &text[at + title.len()..];
+    let window = &rest[..rest.floor_char_boundary(120)];
+    let version = window.split_once("version ").and_then(|(_, after)| {
+        after
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .next()
+            .map(|version| version.trim_end_matches('.'))
+    });
+
+    Some(match (title, version) {
+        ("gnu affero general public license", _) => Some("AGPL-3.0"),
+        ("gnu lesser general public license", Some("2.1")) => Some("LGPL-2.1"),
+        ("gnu lesser general public license", Some("3")) => Some("LGPL-3.0"),
+        ("gnu library general public license", Some("2")) => Some("LGPL-2.0"),
+        ("gnu general public license", Some("1")) => Some("GPL-1.0"),
+        ("gnu general public license", Some("2")) => Some("GPL-2.0"),
+        ("gnu general public license", Some("3")) => Some("GPL-3.0"),
+        _ => None,
+    })
+}
+
+/// License of a directory from its `LICENSE*`, `LICENCE*`, `COPYING*` and
+/// `UNLICENSE` files
+///
+/// Several identified files are read as a choice between them (the usual
+/// `LICENSE-MIT` plus `LICENSE-APACHE` dual license). License files that
+/// cannot be identified give `NOASSERTION`; no license file gives `None`.
+pub fn detect_directory_license(dir: &Path) -> Option<String> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_uppercase())
+                .unwrap_or_default();
+            ["LICENSE", "LICENCE", "COPYING", "UNLICENSE"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+                && !name.contains("THIRD")
+        })
+        .collect();
+    if files.is_empty() {
+        return None;
+    }
+    files.sort();
+
+    let mut ids: Vec<&str> = Vec::new();
+    for path in &files {
+        if let Some(id) = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| identify_license_text(&text))
+            && !ids.contains(&id)
+        {
+            ids.push(id);
+        }
+    }
+    if ids.is_empty() {
+        return Some(NOASSERTION.to_string());
+    }
+    Some(ids.join(" OR "))
+}
+
+/// `--allow-license` / `--deny-license` filter on item licenses
+///
+/// A license is acceptable when it is not denied and, if an allow list is
+/// given, allowed. An expression passes when it can be satisfied with
+/// acceptable licenses only, so `MIT OR GPL-3.0-only` passes a GPL deny
+/// list while `MIT AND GPL-3.0-only` does not. Ids compare case-insensitively
+/// and across the `-only` / `-or-later` / `+` variants of a license; an
+/// allowed or denied license covers its `WITH` exceptions. Items without a
+/// license only pass when there is no allow list.
+#[derive(Debug, Clone, Default)]
+pub struct LicensePolicy {
+    allow: Vec<LicenseExpr>,
+    deny: Vec<LicenseExpr>,
+}
+
+impl LicensePolicy {
+    pub fn new(allow: &[String], deny: &[String]) -> CoreResult<Self> {
+        let parse = |ids: &[String]| -> CoreResult<Vec<LicenseExpr>> {
+            ids.iter()
+                .map(|id| match LicenseExpr::parse(id)? {
+                    expr @ LicenseExpr::License { .. } => Ok(expr),
+                    _ => Err(CoreError::InvalidInput(format!(
+                        "license filters take a single license, not `{id}`"
+                    ))),
+                })
+                .collect()
+        };
+        Ok(Self {
+            allow: parse(allow)?,
+            deny: parse(deny)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Whether items under `license` are kept
+    pub fn allows(&self, license: Option<&str>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let Some(license) = license else {
+            return self.allow.is_empty();
+        };
+        let expr = LicenseExpr::parse(license).unwrap_or_else(|_| LicenseExpr::License {
+            id: license.to_string(),
+            exception: None,
+        });
+        expr.is_satisfied_by(&|id, exception| {
+            let listed =
+                |list: &[LicenseExpr]| list.iter().any(|entry| covers(entry, id, exception));
+            !listed(&self.deny) && (self.allow.is_empty() || listed(&self.allow))
+        })
+    }
+}
+
+/// Whether a filter entry names the license `id WITH exception`
+fn covers(entry: &LicenseExpr, id: &str, exception: Option<&str>) -> bool {
+    let LicenseExpr::License {
+        id: entry_id,
+        exception: entry_exception,
+    } = entry
+    else {
+        return false;
+    };
+    let family = |id: &str| {
+        let id = id.to_lowercase();
+        let id = id.trim_end_matches('+');
+        let id = id.strip_suffix("-only").unwrap_or(id);
+        id.strip_suffix("-or-later").unwrap_or(id).to_string()
+    };
+    family(entry_id) == family(id)
+        && match (entry_exception, exception) {
+            (None, _) => true,
+            (Some(entry), Some(exception)) => entry.eq_ignore_ascii_case(exception),
+            (Some(_), None) => false,
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expressions_and_policy() {
+        let expr =
+            LicenseExpr::parse("(mit or Apache-2.0) and GPL-2.0-only WITH Classpath-exception-2.0")
+                .unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "(mit OR Apache-2.0) AND GPL-2.0-only WITH Classpath-exception-2.0"
+        );
+        assert_eq!(
+            normalize_license("MIT/Apache-2.0").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert!(LicenseExpr::parse("MIT OR").is_err());
+        assert!(LicenseExpr::parse("(MIT").is_err());
+
+        let deny = LicensePolicy::new(&[], &["GPL-3.0".to_string()]).unwrap();
+        assert!(deny.allows(Some("MIT OR GPL-3.0-only")));
+        assert!(!deny.allows(Some("MIT AND GPL-3.0-or-later")));
+        assert!(deny.allows(None));
+
+        let allow =
+            LicensePolicy::new(&["mit".to_string(), "Apache-2.0".to_string()], &[]).unwrap();
+        assert!(allow.allows(Some("MIT OR Apache-2.0")));
+        assert!(allow.allows(Some("Apache-2.0 WITH LLVM-exception")));
+        assert!(!allow.allows(Some("MIT AND MPL-2.0")));
+        assert!(!allow.allows(Some(NOASSERTION)));
+        assert!(!allow.allows(None));
+
+        assert!(LicensePolicy::new(&["MIT OR Apache-2.0".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_license_detection() {
+        assert_eq!(
+            spdx_header("// SPDX-License-Identifier: MIT OR Apache-2.0\n\nfn f() {}").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(
+            spdx_header("/*\n * SPDX-License-Identifier: GPL-2.0+ */\nfn f() {}").as_deref(),
+            Some("GPL-2.0+")
+        );
+        // Only leading comments count
+        assert_eq!(
+            spdx_header("fn f() {}\n// SPDX-License-Identifier: MIT"),
+            None
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(detect_directory_license(dir.path()), None);
+        std::fs::write(dir.path().join("LICENSE-THIRD-PARTY"), "Apache License").unwrap();
+        std::fs::write(dir.path().join("LICENSE-README"), "Ask us.").unwrap();
+        assert_eq!(
+            detect_directory_license(dir.path()).as_deref(),
+            Some(NOASSERTION)
+        );
+
+        std::fs::write(
+            dir.path().join("LICENSE-APACHE"),
+            "                              Apache License\n                        Version 2.0, January 2004",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("LICENSE-MIT"),
+            "Permission is hereby granted, free of\ncharge, to any person obtaining a copy",
+        )
+        .unwrap();
+        assert_eq!(
+            detect_directory_license(dir.path()).as_deref(),
+            Some("Apache-2.0 OR MIT")
+        );
+    }
+
+    #[test]
+    fn test_gnu_licenses_are_told_apart() {
+        // Full texts as distributed by the FSF; each mentions its siblings
+        let gpl3 = include_str!("../testdata/licenses/GPL-3.0");
+        for (text, id) in [
+            (include_str!("../testdata/licenses/GPL-2.0"), "GPL-2.0"),
+            (gpl3, "GPL-3.0"),
+            (include_str!("../testdata/licenses/LGPL-2.1"), "LGPL-2.1"),
+            (include_str!("../testdata/licenses/LGPL-3.0"), "LGPL-3.0"),
+        ] {
+            assert_eq!(identify_license_text(text), Some(id));
+        }
+
+        // The AGPL-3.0 is the GPL-3.0 under another name, with the GPL's
+        // own section 13 still naming the AGPL
+        let agpl = gpl3
+            .replace(
+                "GNU GENERAL PUBLIC LICENSE",
+                "GNU AFFERO GENERAL PUBLIC LICENSE",
+            )
+            .replace(
+                "GNU General Public License",
+                "GNU Affero General Public License",
+            );
+        assert_eq!(identify_license_text(&agpl), Some("AGPL-3.0"));
+
+        // A notice is identified by the license it grants
+        let notice = "This program is free software: you can redistribute it and/or modify\n\
+                      it under the terms of the GNU General Public License as published by\n\
+                      the Free Software Foundation, either version 3 of the License, or\n\
+                      (at your option) any later version.";
+        assert_eq!(identify_license_text(notice), Some("GPL-3.0"));
+        assert_eq!(
+            identify_license_text("GNU General Public License, some version"),
+            None
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use crate::models::ItemMetrics;
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::visit::{self, Visit};
+use syn::{Attribute, Expr, Meta};
+
+/// Syntax nodes that are extracted as items and can be measured
+pub trait Measure: ToTokens {
+    /// Outer attributes of the item
+    fn attrs(&self) -> &[Attribute];
+    /// Walk the item with `visitor`
+    fn accept(&self, visitor: &mut MetricsVisitor);
+}
+
+macro_rules! impl_measure {
+    ($($node:ty => $visit:ident),* $(,)?) => {
+        $(
+            impl Measure for $node {
+                fn attrs(&self) -> &[Attribute] {
+                    &self.attrs
+                }
+
+                fn accept(&self, visitor: &mut MetricsVisitor) {
+                    visitor.$visit(self);
+                }
+            }
+        )*
+    };
+}
+
+impl_measure! {
+    syn::ItemFn => visit_item_fn,
+    syn::ItemImpl => visit_item_impl,
+    syn::ImplItemFn => visit_impl_item_fn,
+    syn::TraitItemFn => visit_trait_item_fn,
+    syn::ItemTrait => visit_item_trait,
+    syn::ItemMod => visit_item_mod,
+    syn::ItemStruct => visit_item_struct,
+    syn::ItemEnum => visit_item_enum,
+    syn::ItemUnion => visit_item_union,
+    syn::ItemType => visit_item_type,
+    syn::ItemConst => visit_item_const,
+    syn::ItemStatic => visit_item_static,
+    syn::ItemMacro => visit_item_macro,
+}
+
+/// Measure an item from its syntax tree.
+///
+/// `line_count` is left at zero since it needs the source text, see
+/// `line_count`. Test and bench classification only looks at the item's own
+/// attributes; enclosing `#[cfg(test)]` modules are the caller's concern.
+pub fn measure<T: Measure>(node: &T) -> ItemMetrics {
+    let mut visitor = MetricsVisitor::default();
+    node.accept(&mut visitor);
+
+    let attrs = node.attrs();
+    ItemMetrics {
+        token_count: token_count(node.to_token_stream()),
+        line_count: 0,
+        cyclomatic_complexity: 1 + visitor.decisions,
+        nesting_depth: visitor.max_depth,
+        is_test: attrs.iter().any(is_test_attr),
+        is_bench: attrs.iter().any(|attr| attr.path().is_ident("bench")),
+        is_unsafe: visitor.is_unsafe,
+        is_async: visitor.is_async,
+        is_const: visitor.is_const,
+        is_generated: attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("automatically_derived")),
+    }
+}
+
+/// Number of lines that are neither blank nor `//` comments
+pub fn line_count<S: AsRef<str>>(lines: &[S]) -> u32 {
+    lines
+        .iter()
+        .map(|line| line.as_ref().trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .count() as u32
+}
+
+/// Number of Rust tokens in a stream, skipping doc attributes
+///
+/// Punctuation spelled with several characters (`::`, `=>`, `..=`) counts
+/// once, and a delimited group counts once for its pair of delimiters.
+pub fn token_count(tokens: TokenStream) -> u32 {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    let mut count = 0;
+    let mut index = 0;
+
+    while index < tokens.len() {
+        if let Some(length) = doc_attribute_length(&tokens[index..]) {
+            index += length;
+            continue;
+        }
+
+        match &tokens[index] {
+            TokenTree::Group(group) => {
+                if group.delimiter() != Delimiter::None {
+                    count += 1;
+                }
+                count += token_count(group.stream());
+            }
+            TokenTree::Punct(punct) => {
+                // Joint punctuation continues into the next character
+                if punct.spacing() == Spacing::Alone {
+                    count += 1;
+                }
+            }
+            TokenTree::Ident(_) | TokenTree::Literal(_) => count += 1,
+        }
+        index += 1;
+    }
+
+    count
+}
+
+/// Length of a `#[doc ...]` or `#![doc ...]` attribute at the start of
+/// `tokens`, if there is one
+fn doc_attribute_length(tokens: &[TokenTree]) -> Option<usize> {
+    let TokenTree::Punct(pound) = tokens.first()? else {
+        return None;
+    };
+    if pound.as_char() != '#' {
+        return None;
+    }
+
+    let bang = matches!(tokens.get(1), Some(TokenTree::Punct(punct)) if punct.as_char() == '!');
+    let length = if bang { 3 } else { 2 };
+    match tokens.get(length - 1)? {
+        TokenTree::Group(group) if group.delimiter() == Delimiter::Bracket => {
+            let is_doc = matches!(
+                group.stream().into_iter().next(),
+                Some(TokenTree::Ident(ident)) if ident == "doc"
+            );
+            is_doc.then_some(length)
+        }
+        _ => None,
+    }
+}
+
+/// `#[test]`, `#[tokio::test]` and similar, or a `cfg` that requires `test`
+pub fn is_test_attr(attr: &Attribute) -> bool {
+    if attr
+        .path()
+        .segments
+        .last()
+        .is_some_and(|segment| segment.ident == "test")
+    {
+        return true;
+    }
+
+    match &attr.meta {
+        Meta::List(list) if list.path.is_ident("cfg") => cfg_requires_test(list.tokens.clone()),
+        _ => false,
+    }
+}
+
+/// Whether a cfg predicate mentions `test` outside of a `not(..)`
+fn cfg_requires_test(tokens: TokenStream) -> bool {
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Ident(ident) if ident == "not" => {
+                // Skip the negated predicate
+                tokens.next_if(|token| matches!(token, TokenTree::Group(_)));
+            }
+            TokenTree::Ident(ident) if ident == "test" => return true,
+            TokenTree::Group(group) if cfg_requires_test(group.stream()) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Item-level filters on size, complexity and classification
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QualityFilter {
+    pub min_tokens: Option<u32>,
+    pub max_tokens: Option<u32>,
+    pub min_lines: Option<u32>,
+    pub max_lines: Option<u32>,
+    pub max_complexity: Option<u32>,
+    pub max_nesting: Option<u32>,
+    pub exclude_tests: bool,
+    pub exclude_benches: bool,
+    pub exclude_generated: bool,
+}
+
+impl QualityFilter {
+    /// Whether the filter lets every item through
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether an item with these metrics passes every filter
+    pub fn allows(&self, metrics: &ItemMetrics) -> bool {
+        let at_least = |min: Option<u32>, value: u32| min.is_none_or(|min| value >= min);
+        let at_most = |max: Option<u32>, value: u32| max.is_none_or(|max| value <= max);
+
+        at_least(self.min_tokens, metrics.token_count)
+            && at_most(self.max_tokens, metrics.token_count)
+            && at_least(self.min_lines, metrics.line_count)
+            && at_most(self.max_lines, metrics.line_count)
+            && at_most(self.max_complexity, metrics.cyclomatic_complexity)
+            && at_most(self.max_nesting, metrics.nesting_depth)
+            && !(self.exclude_tests && metrics.is_test)
+            && !(self.exclude_benches && metrics.is_bench)
+            && !(self.exclude_generated && metrics.is_generated)
+    }
+}
+
+/// Collects branch points, nesting and qualifiers while walking an item
+#[derive(Debug, Default)]
+pub struct MetricsVisitor {
+    decisions: u32,
+    depth: u32,
+    max_depth: u32,
+    is_unsafe: bool,
+    is_async: bool,
+    is_const: bool,
+}
+
+impl MetricsVisitor {
+    /// Visit a nested block one level deeper
+    fn nested(&mut self, visit: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        visit(self);
+        self.depth -= 1;
+    }
+}
+
+impl<'ast> Visit<'ast> for MetricsVisitor {
+    fn visit_expr_if(&mut self, node: &'ast syn::ExprIf) {
+        self.decisions += 1;
+        self.visit_expr(&node.cond);
+        self.nested(|visitor| visitor.visit_block(&node.then_branch));
+
+        // `else if` chains stay at the same depth
+        if let Some((_, else_branch)) = &node.else_branch {
+            match &**else_branch {
+                Expr::If(else_if) => self.visit_expr_if(else_if),
+                other => self.nested(|visitor| visitor.visit_expr(other)),
+            }
+        }
+    }
+
+    fn visit_expr_while(&mut self, node: &'ast syn::ExprWhile) {
+        self.decisions += 1;
+        self.nested(|visitor| visit::visit_expr_while(visitor, node));
+    }
+
+    fn visit_expr_for_loop(&mut self, node: &'ast syn::ExprForLoop) {
+        self.decisions += 1;
+        self.nested(|visitor| visit::visit_expr_for_loop(visitor, node));
+    }
+
+    fn visit_expr_loop(&mut self, node: &'ast syn::ExprLoop) {
+        self.nested(|visitor| visit::visit_expr_loop(visitor, node));
+    }
+
+    fn visit_expr_match(&mut self, node: &'ast syn::ExprMatch) {
+        self.decisions += node.arms.len().saturating_sub(1) as u32;
+        self.decisions += node.arms.iter().filter(|arm| arm.guard.is_some()).count() as u32;
+        self.nested(|visitor| visit::visit_expr_match(visitor, node));
+    }
+
+    fn visit_expr_binary(&mut self, node: &'ast syn::ExprBinary) {
+        if matches!(node.op, syn::BinOp::And(_) | syn::BinOp::Or(_)) {
+            self.decisions += 1;
+        }
+        visit::visit_expr_binary(self, node);
+    }
+
+    fn visit_expr_try(&mut self, node: &'ast syn::ExprTry) {
+        self.decisions += 1;
+        visit::visit_expr_try(self, node);
+    }
+
+    fn visit_local(&mut self, node: &'ast syn::Local) {
+        if node
+            .init
+            .as_ref()
+            .is_some_and(|init| init.diverge.is_some())
+        {
+            self.decisions += 1;
+        }
+        visit::visit_local(self, node);
+    }
+
+    fn visit_expr_closure(&mut self, node: &'ast syn::ExprClosure) {
+        self.is_async |= node.asyncness.is_some();
+        self.is_const |= node.constness.is_some();
+        self.nested(|visitor| visit::visit_expr_closure(visitor, node));
+    }
+
+    fn visit_expr_block(&mut self, node: &'ast syn::ExprBlock) {
+        self.nested(|visitor| visit::visit_expr_block(visitor, node));
+    }
+
+    fn visit_expr_unsafe(&mut self, node: &'ast syn::ExprUnsafe) {
+        self.is_unsafe = true;
+        self.nested(|visitor| visit::visit_expr_unsafe(visitor, node));
+    }
+
+    fn visit_expr_async(&mut self, node: &'ast syn::ExprAsync) {
+        self.is_async = true;
+        self.nested(|visitor| visit::visit_expr_async(visitor, node));
+    }
+
+    fn visit_expr_const(&mut self, node: &'ast syn::ExprConst) {
+        self.is_const = true;
+        self.nested(|visitor| visit::visit_expr_const(visitor, node));
+    }
+
+    fn visit_signature(&mut self, node: &'ast syn::Signature) {
+        self.is_unsafe |= node.unsafety.is_some();
+        self.is_async |= node.asyncness.is_some();
+        self.is_const |= node.constness.is_some();
+        visit::visit_signature(self, node);
+    }
+
+    fn visit_item_impl(&mut self, node: &'ast syn::ItemImpl) {
+        self.is_unsafe |= node.unsafety.is_some();
+        visit::visit_item_impl(self, node);
+    }
+
+    fn visit_item_trait(&mut self, node: &'ast syn::ItemTrait) {
+        self.is_unsafe |= node.unsafety.is_some();
+        visit::visit_item_trait(self, node);
+    }
+
+    fn visit_item_const(&mut self, node: &'ast syn::ItemConst) {
+        self.is_const = true;
+        visit::visit_item_const(self, node);
+    }
+
+    fn visit_impl_item_const(&mut self, node: &'ast syn::ImplItemConst) {
+        self.is_const = true;
+        visit::visit_impl_item_const(self, node);
+    }
+
+    fn visit_trait_item_const(&mut self, node: &'ast syn::TraitItemConst) {
+        self.is_const = true;
+        visit::visit_trait_item_const(self, node);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complexity_and_nesting() {
+        let item: syn::ItemFn = syn::parse_str(
+            r#"
+            /// Documented
+            async fn parse(input: &str) -> Result<u32, Error> {
+                let Some(first) = input.chars().next() else { return Err(Error) };
+                if first == '-' && input.len() > 1 {
+                    return Ok(0);
+                } else if first == '+' {
+                    for c in input.chars() {
+                        match c {
+                            '0'..='9' => {}
+                            _ if c.is_whitespace() => continue,
+                            _ => return Err(Error),
+                        }
+                    }
+                }
+                Ok(input.parse()?)
+            }
+            "#,
+        )
+        .unwrap();
+
+        let metrics = measure(&item);
+        // let-else, if, &&, else if, for, two extra arms, a guard and `?`
+        assert_eq!(metrics.cyclomatic_complexity, 10);
+        // if > for > match > arm block
+        assert_eq!(metrics.nesting_depth, 4);
+        assert!(metrics.is_async);
+        assert!(!metrics.is_unsafe && !metrics.is_const && !metrics.is_test);
+
+        let simple: syn::ItemFn = syn::parse_str("fn id(x: u32) -> u32 { x }").unwrap();
+        let metrics = measure(&simple);
+        assert_eq!(metrics.cyclomatic_complexity, 1);
+        assert_eq!(metrics.nesting_depth, 0);
+        // `fn`, `id`, `()`, `x`, `:`, `u32`, `->`, `u32`, `{}`, `x`
+        assert_eq!(metrics.token_count, 10);
+    }
+
+    #[test]
+    fn test_classification() {
+        let test_fn: syn::ItemFn = syn::parse_str("#[tokio::test] async fn it_works() {}").unwrap();
+        assert!(measure(&test_fn).is_test);
+
+        let tests: syn::ItemMod =
+            syn::parse_str("#[cfg(all(test, feature = \"x\"))] mod tests {}").unwrap();
+        assert!(measure(&tests).is_test);
+
+        let not_test: syn::ItemMod = syn::parse_str("#[cfg(not(test))] mod real {}").unwrap();
+        assert!(!measure(&not_test).is_test);
+
+        let bench: syn::ItemFn = syn::parse_str("#[bench] fn fast(b: &mut Bencher) {}").unwrap();
+        assert!(measure(&bench).is_bench);
+
+        let block: syn::ItemImpl = syn::parse_str(
+            "#[automatically_derived] impl Foo { const N: usize = 1; fn get(&self) -> u8 { unsafe { *self.0 } } }",
+        )
+        .unwrap();
+        let metrics = measure(&block);
+        assert!(metrics.is_generated && metrics.is_const && metrics.is_unsafe);
+        assert!(!metrics.is_async);
+
+        assert_eq!(
+            line_count(&["fn f() {", "", "    // note", "    1", "}"]),
+            3
+        );
+    }
+
+    #[test]
+    fn test_quality_filter() {
+        let metrics = ItemMetrics {
+            token_count: 40,
+            line_count: 6,
+            is_test: true,
+            ..Default::default()
+        };
+        assert!(QualityFilter::default().is_empty());
+        assert!(QualityFilter::default().allows(&metrics));
+
+        let sized = QualityFilter {
+            min_tokens: Some(10),
+            max_tokens: Some(40),
+            max_lines: Some(6),
+            ..Default::default()
+        };
+        assert!(sized.allows(&metrics));
+        assert!(!sized.allows(&ItemMetrics {
+            token_count: 41,
+            ..metrics.clone()
+        }));
+
+        let no_tests = QualityFilter {
+            exclude_tests: true,
+            ..Default::default()
+        };
+        assert!(!no_tests.is_empty());
+        assert!(!no_tests.allows(&metrics));
+    }
+}
@@ -1,15 +1,25 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
 /// Represents the type of code item extracted
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ItemKind {
+    #[default]
     Function,
     Method,
     Impl,
     Trait,
     Module,
+    Struct,
+    Enum,
+    #[serde(rename = "type_alias")]
+    TypeAlias,
+    Const,
+    Static,
+    Union,
+    Macro,
 }
 
 impl fmt::Display for ItemKind {
@@ -20,32 +30,170 @@ impl fmt::Display for ItemKind {
             ItemKind::Impl => write!(f, "impl"),
             ItemKind::Trait => write!(f, "trait"),
             ItemKind::Module => write!(f, "module"),
+            ItemKind::Struct => write!(f, "struct"),
+            ItemKind::Enum => write!(f, "enum"),
+            ItemKind::TypeAlias => write!(f, "type_alias"),
+            ItemKind::Const => write!(f, "const"),
+            ItemKind::Static => write!(f, "static"),
+            ItemKind::Union => write!(f, "union"),
+            ItemKind::Macro => write!(f, "macro"),
+        }
+    }
+}
+
+/// How an item differs between two commits in diff mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// Only present in the newer commit
+    Added,
+    /// Present in both commits with different content
+    Changed,
+    /// Only present in the older commit; the item is taken from that commit
+    Removed,
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeKind::Added => write!(f, "added"),
+            ChangeKind::Changed => write!(f, "changed"),
+            ChangeKind::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+/// Kind of Cargo target a source file is compiled into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum TargetKind {
+    Lib,
+    Bin,
+    Test,
+    Bench,
+    Example,
+    BuildScript,
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetKind::Lib => write!(f, "lib"),
+            TargetKind::Bin => write!(f, "bin"),
+            TargetKind::Test => write!(f, "test"),
+            TargetKind::Bench => write!(f, "bench"),
+            TargetKind::Example => write!(f, "example"),
+            TargetKind::BuildScript => write!(f, "build-script"),
         }
     }
 }
 
 /// Contains project context information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ProjectContext {
     pub repo_url: String,
     pub commit_hash: String,
     pub file_path: String,
+    /// Name of the crate the file is compiled into, e.g. `serde_json`
+    #[serde(default)]
+    pub crate_name: Option<String>,
+    /// Version of the package the crate belongs to
+    #[serde(default)]
+    pub crate_version: Option<String>,
+    /// Cargo target of the crate, `None` for files no target reaches
+    #[serde(default)]
+    pub target_kind: Option<TargetKind>,
+    /// Rust edition of the package, e.g. `2021`
+    #[serde(default)]
+    pub edition: Option<String>,
+    /// Module path of the file, e.g. `serde_json::de`
+    #[serde(default)]
+    pub module_path: Option<String>,
+    /// SPDX license expression of the file, e.g. `MIT OR Apache-2.0`
+    #[serde(default)]
+    pub license: Option<String>,
 }
 
 /// Contains metadata about the extracted item
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct ItemMeta {
+    /// Stable identifier of this item, see `extractor::item_id`
+    pub id: String,
+    /// Id of the enclosing impl or trait block for methods
+    pub parent_id: Option<String>,
     pub kind: ItemKind,
     pub name: String,
     pub fully_qualified_name: String,
+    /// 1-based line of the first token (including attributes and doc comments)
     pub start_line: u32,
+    /// 1-based line of the last token
     pub end_line: u32,
+    /// 0-based character column of the first token
+    pub start_column: u32,
+    /// 0-based character column just past the last token
+    pub end_column: u32,
+    /// Byte offset of the first token in the source file
+    pub start_byte: usize,
+    /// Byte offset just past the last token in the source file
+    pub end_byte: usize,
+    /// Id of the canonical item when this one is an exact duplicate
+    #[serde(default)]
+    pub duplicate_of: Option<String>,
+    /// Change relative to the base commit, only set in diff mode
+    #[serde(default)]
+    pub change: Option<ChangeKind>,
+    /// Placeholders substituted for secrets and PII, one per match, e.g.
+    /// `SECRET_AWS_KEY`
+    #[serde(default)]
+    pub redactions: Vec<String>,
+    /// Size, complexity and classification of the item
+    #[serde(default)]
+    pub metrics: ItemMetrics,
+    /// Ids of the items of the same crate this item calls, names or
+    /// implements, see `graph::GraphBuilder`
+    #[serde(default)]
+    pub references: Vec<String>,
+    /// Ids of the items of the same crate that reference this item
+    #[serde(default)]
+    pub referenced_by: Vec<String>,
+}
+
+/// Size, complexity and classification of an item.
+///
+/// Container items (impls, traits, modules) are measured over everything
+/// they contain, so an impl with an `async fn` is `is_async`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ItemMetrics {
+    /// Rust tokens in the item, doc comments excluded; multi-character
+    /// operators and each pair of delimiters count as one token
+    pub token_count: u32,
+    /// Source lines that are neither blank nor line comments
+    pub line_count: u32,
+    /// McCabe complexity: one plus the number of branch points (`if`,
+    /// loops, extra match arms and guards, `&&`, `||`, `?` and `let else`)
+    pub cyclomatic_complexity: u32,
+    /// Deepest nesting of blocks, loops, closures and branches in the item
+    pub nesting_depth: u32,
+    /// `#[test]` functions, items under `#[cfg(test)]` and integration tests
+    pub is_test: bool,
+    /// `#[bench]` functions and items of bench targets
+    pub is_bench: bool,
+    /// Declares or contains an `unsafe` fn, impl, trait or block
+    pub is_unsafe: bool,
+    /// Declares or contains an `async` fn, block or closure
+    pub is_async: bool,
+    /// A `const` item or `const fn`, or contains one
+    pub is_const: bool,
+    /// From a file with an `@generated` marker, or `#[automatically_derived]`
+    pub is_generated: bool,
 }
 
 /// Contains the normalized content and metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Content {
     pub signature: String,
+    /// Generic parameters and where clause, e.g. `<T: Clone> where T: Debug`
+    pub generics: Option<String>,
     pub body_normalized: String,
     pub semantic_hash: String,
     pub docstring: Option<String>,
@@ -53,19 +201,116 @@ pub struct Content {
 }
 
 /// Contains contextual information for RAG
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct RagContext {
     pub context_before: Option<String>,
     pub context_after: Option<String>,
 }
 
+/// Version of the dataset record format, bumped whenever fields are added,
+/// removed or change meaning
+pub const SCHEMA_VERSION: &str = "1.0.0";
+
 /// Represents a complete extracted code item
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct ExtractedItem {
+    /// Format version of the record, `SCHEMA_VERSION` when it was written;
+    /// empty for records written before versioning
+    #[serde(default)]
+    pub schema_version: String,
     pub project_context: ProjectContext,
     pub item_meta: ItemMeta,
     pub content: Content,
     pub rag_context: RagContext,
+    /// Fill-in-the-middle sample of a function, see `ExtractConfig::fim`
+    #[serde(default)]
+    pub fim: Option<FimSample>,
+}
+
+/// Order of the parts of a fill-in-the-middle prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum FimOrder {
+    /// Prefix, suffix, then the middle to complete
+    #[default]
+    Psm,
+    /// Suffix, prefix, then the middle to complete
+    Spm,
+}
+
+/// Syntax node covered by the middle of a FIM sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum FimSpanKind {
+    Statement,
+    Expression,
+    Block,
+    MatchArm,
+}
+
+impl FimSpanKind {
+    pub const ALL: [FimSpanKind; 4] = [
+        FimSpanKind::Statement,
+        FimSpanKind::Expression,
+        FimSpanKind::Block,
+        FimSpanKind::MatchArm,
+    ];
+}
+
+/// Tokens marking the parts of a FIM prompt
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FimSentinels {
+    pub prefix: String,
+    pub suffix: String,
+    pub middle: String,
+}
+
+impl Default for FimSentinels {
+    fn default() -> Self {
+        Self {
+            prefix: "<fim_prefix>".to_string(),
+            suffix: "<fim_suffix>".to_string(),
+            middle: "<fim_middle>".to_string(),
+        }
+    }
+}
+
+/// Settings of the FIM samples attached to functions during extraction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FimConfig {
+    pub order: FimOrder,
+    pub sentinels: FimSentinels,
+    /// Seed of the span choice; the same seed picks the same spans
+    pub seed: u64,
+    /// Kinds of nodes the middle may cover, each equally likely
+    pub span_kinds: Vec<FimSpanKind>,
+}
+
+impl Default for FimConfig {
+    fn default() -> Self {
+        Self {
+            order: FimOrder::Psm,
+            sentinels: FimSentinels::default(),
+            seed: 0,
+            span_kinds: FimSpanKind::ALL.to_vec(),
+        }
+    }
+}
+
+/// A function's source split around one syntax node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FimSample {
+    pub order: FimOrder,
+    pub span_kind: FimSpanKind,
+    /// Source of the item before the middle, attributes and docs included
+    pub prefix: String,
+    /// Source of the chosen node, the completion
+    pub middle: String,
+    /// Source of the item after the middle
+    pub suffix: String,
+    /// Prefix and suffix with sentinels in `order`, ending with the middle
+    /// sentinel
+    pub prompt: String,
 }
 
 /// Error types for the library
@@ -83,6 +328,10 @@ pub enum CoreError {
     InvalidInput(String),
     #[error("Strip prefix error: {0}")]
     StripPrefix(#[from] std::path::StripPrefixError),
+    #[error("Arrow error: {0}")]
+    Arrow(#[from] arrow_schema::ArrowError),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
 }
 
 /// Result type for core operations
@@ -94,6 +343,10 @@ pub struct ExtractConfig {
     pub keep_docs: bool,
     pub include_context: bool,
     pub context_lines: usize,
+    /// Replace secrets and PII with placeholders, see `scrub::scrub_item`
+    pub scrub: bool,
+    /// Attach a FIM sample to every function and method, see `fim`
+    pub fim: Option<FimConfig>,
 }
 
 impl Default for ExtractConfig {
@@ -102,6 +355,8 @@ impl Default for ExtractConfig {
             keep_docs: false,
             include_context: false,
             context_lines: 50,
+            scrub: true,
+            fim: None,
         }
     }
 }
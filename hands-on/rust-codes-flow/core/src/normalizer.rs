@@ -1,4 +1,5 @@
 use crate::models::*;
+use proc_macro2::{Group, TokenStream, TokenTree};
 use std::collections::HashMap;
 use syn::{self, visit_mut::VisitMut};
 
@@ -24,29 +25,113 @@ impl<'ast> Normalizer<'ast> {
         }
     }
 
-    pub fn normalize_file(&mut self, _file: &mut syn::File) -> String {
-        // Reset counters for each file
-        self.var_counter = 0;
-        self.type_counter = 0;
-        self.scope_stack.clear();
+    /// Anonymize identifiers in a whole file and pretty-print the result
+    pub fn normalize_file(&mut self, file: &mut syn::File) -> String {
+        self.reset();
+
+        // Root scope for names declared at the top level (item names, generics)
+        self.enter_scope();
+        self.visit_file_mut(file);
+        self.exit_scope();
+
+        prettyplease::unparse(file)
+    }
+
+    /// Anonymize identifiers in a single item and pretty-print the result
+    pub fn normalize_syn_item(&mut self, item: syn::Item) -> String {
+        let mut file = syn::File {
+            shebang: None,
+            attrs: Vec::new(),
+            items: vec![item],
+        };
+        self.normalize_file(&mut file)
+    }
+
+    /// Anonymize a method body (parsed as a free function) and pretty-print it.
+    ///
+    /// Unlike free functions, the method name is kept: it belongs to the
+    /// type's API and callers reach it through method syntax.
+    pub fn normalize_method(&mut self, mut method: syn::ItemFn) -> String {
+        self.reset();
+
+        self.enter_scope();
+        self.visit_fn_scoped(&mut method.sig, Some(&mut method.block));
+        self.exit_scope();
+
+        prettyplease::unparse(&syn::File {
+            shebang: None,
+            attrs: Vec::new(),
+            items: vec![syn::Item::Fn(method)],
+        })
+    }
 
-        // For now, return a simplified implementation that doesn't require AST modification
-        // In a full implementation, we would visit and modify the AST
-        "".to_string()
+    /// Collect `///`, `//!` and `#[doc = "..."]` attributes into a docstring.
+    ///
+    /// Outer and inner (module) docs are both collected. The indentation
+    /// shared by all lines is removed, so the single space after `///` goes
+    /// away while the relative indentation of code fences is preserved.
+    pub fn extract_docstring(&self, attrs: &[syn::Attribute]) -> Option<String> {
+        let mut lines = Vec::new();
+
+        for attr in attrs {
+            if !attr.path().is_ident("doc") {
+                continue;
+            }
+
+            if let syn::Meta::NameValue(meta) = &attr.meta
+                && let syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(text),
+                    ..
+                }) = &meta.value
+            {
+                // `split` rather than `lines`: an empty `///` is still a line
+                let value = text.value();
+                lines.extend(value.split('\n').map(|line| line.trim_end().to_string()));
+            }
+        }
+
+        // Only ASCII spaces and tabs count as indent, so `indent` is always a
+        // char boundary of the lines it is cut from
+        let indent = lines
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+            .min()?;
+
+        let lines: Vec<&str> = lines
+            .iter()
+            .map(|line| line.get(indent..).unwrap_or("").trim_end())
+            .collect();
+
+        // Drop blank lines around the text, keep the ones inside it
+        let start = lines.iter().position(|line| !line.is_empty())?;
+        let end = lines.iter().rposition(|line| !line.is_empty())?;
+
+        Some(lines[start..=end].join("\n"))
     }
 
-    pub fn extract_docstring(&self, _item: &syn::Item) -> Option<String> {
-        if !self.config.keep_docs {
-            return None;
+    /// Remove doc attributes from an item's tokens unless `keep_docs` is set
+    fn strip_docs_unless_kept(&self, tokens: TokenStream) -> TokenStream {
+        if self.config.keep_docs {
+            tokens
+        } else {
+            strip_doc_attributes(tokens)
         }
+    }
 
-        // Simple docstring extraction - in a real implementation you'd parse attributes
-        None
+    /// Flattened `use` declarations at the top level of a file
+    pub fn extract_imports(&self, file: &syn::File) -> Vec<String> {
+        collect_imports(&file.items)
+            .into_iter()
+            .map(|import| import.declaration)
+            .collect()
     }
 
-    pub fn extract_imports(&self, _file: &syn::File) -> Vec<String> {
-        // Simple import extraction - in a real implementation you'd traverse use statements
-        Vec::new()
+    /// Reset counters and scopes so the output only depends on the input
+    fn reset(&mut self) {
+        self.var_counter = 0;
+        self.type_counter = 0;
+        self.scope_stack.clear();
     }
 
     fn enter_scope(&mut self) {
@@ -61,10 +146,8 @@ impl<'ast> Normalizer<'ast> {
         let original = ident.to_string();
 
         // Check if this identifier is already in any scope
-        for scope in self.scope_stack.iter().rev() {
-            if let Some(normalized) = scope.get(&original) {
-                return syn::Ident::new(normalized, ident.span());
-            }
+        if let Some(normalized) = self.lookup(&original) {
+            return syn::Ident::new(normalized, ident.span());
         }
 
         // Generate new normalized name
@@ -88,6 +171,15 @@ impl<'ast> Normalizer<'ast> {
         syn::Ident::new(&normalized, ident.span())
     }
 
+    /// Find the normalized name of an identifier declared in current or parent scopes
+    fn lookup(&self, name: &str) -> Option<&str> {
+        self.scope_stack
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .map(String::as_str)
+    }
+
     fn is_local_variable(&self, ident: &syn::Ident) -> bool {
         let name = ident.to_string();
 
@@ -97,13 +189,68 @@ impl<'ast> Normalizer<'ast> {
         }
 
         // Check if this identifier is declared in current or parent scopes
-        for scope in self.scope_stack.iter().rev() {
-            if scope.contains_key(&name) {
-                return true;
-            }
+        self.lookup(&name).is_some()
+    }
+
+    /// Whether the identifier was declared as a generic type parameter
+    fn is_type_parameter(&self, ident: &syn::Ident) -> bool {
+        self.lookup(&ident.to_string())
+            .is_some_and(|normalized| normalized.starts_with('T'))
+    }
+
+    /// Rename identifiers inside macro input that refer to names in scope.
+    ///
+    /// Macro bodies are opaque token streams, so this is token based: field
+    /// accesses (`.name`), lifetimes (`'name`) and path tails (`::name`) are
+    /// left untouched.
+    fn rename_tokens(&self, tokens: TokenStream) -> TokenStream {
+        let mut output = Vec::new();
+        let mut previous: Option<char> = None;
+        let mut after_path_separator = false;
+
+        for token in tokens {
+            let (token, punct) = match token {
+                TokenTree::Group(group) => {
+                    let mut renamed =
+                        Group::new(group.delimiter(), self.rename_tokens(group.stream()));
+                    renamed.set_span(group.span());
+                    (TokenTree::Group(renamed), None)
+                }
+                TokenTree::Ident(ident) => {
+                    let skip = after_path_separator || matches!(previous, Some('.') | Some('\''));
+                    let renamed = match self.lookup(&ident.to_string()) {
+                        Some(normalized) if !skip => syn::Ident::new(normalized, ident.span()),
+                        _ => ident,
+                    };
+                    (TokenTree::Ident(renamed), None)
+                }
+                TokenTree::Punct(punct) => {
+                    let ch = punct.as_char();
+                    (TokenTree::Punct(punct), Some(ch))
+                }
+                literal @ TokenTree::Literal(_) => (literal, None),
+            };
+
+            after_path_separator = punct == Some(':') && previous == Some(':');
+            previous = punct;
+            output.push(token);
         }
 
-        false
+        output.into_iter().collect()
+    }
+
+    /// Visit a function signature and body inside a fresh scope
+    fn visit_fn_scoped(&mut self, sig: &mut syn::Signature, block: Option<&mut syn::Block>) {
+        self.enter_scope();
+
+        // Generics, parameters (patterns and types) and the return type
+        self.visit_signature_mut(sig);
+
+        if let Some(block) = block {
+            self.visit_block_mut(block);
+        }
+
+        self.exit_scope();
     }
 }
 
@@ -128,18 +275,44 @@ impl<'ast> VisitMut for Normalizer<'ast> {
                 syn::GenericParam::Const(_) => {}
             }
         }
+
+        // Bounds and where clauses may refer to the renamed parameters
+        syn::visit_mut::visit_generics_mut(self, generics);
     }
 
     fn visit_pat_ident_mut(&mut self, pat: &mut syn::PatIdent) {
-        // This is a variable declaration
-        let normalized = self.normalize_identifier(&pat.ident);
-        pat.ident = normalized;
+        // Uppercase bindings in patterns are unit variants or constants (`None`, `MAX`)
+        let is_binding = pat
+            .ident
+            .to_string()
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_lowercase() || c == '_');
+
+        if is_binding && pat.ident != "self" {
+            // This is a variable declaration
+            let normalized = self.normalize_identifier(&pat.ident);
+            pat.ident = normalized;
+        }
+
+        if let Some((_, subpat)) = &mut pat.subpat {
+            self.visit_pat_mut(subpat);
+        }
     }
 
     fn visit_expr_path_mut(&mut self, expr: &mut syn::ExprPath) {
-        // Check if this is a local variable usage
-        if let Some(path_seg) = expr.path.segments.first() {
-            if self.is_local_variable(&path_seg.ident) {
+        // Check if this is a local variable usage, or an associated item of a
+        // generic parameter (`T::default()`)
+        if expr.qself.is_none()
+            && let Some(path_seg) = expr.path.segments.first()
+        {
+            let rename = if expr.path.segments.len() == 1 {
+                self.is_local_variable(&path_seg.ident)
+            } else {
+                self.is_type_parameter(&path_seg.ident)
+            };
+
+            if rename {
                 let normalized = self.normalize_identifier(&path_seg.ident);
                 expr.path.segments[0].ident = normalized;
             }
@@ -148,27 +321,64 @@ impl<'ast> VisitMut for Normalizer<'ast> {
         syn::visit_mut::visit_expr_path_mut(self, expr);
     }
 
-    fn visit_item_fn_mut(&mut self, item: &mut syn::ItemFn) {
-        self.enter_scope();
+    fn visit_type_path_mut(&mut self, ty: &mut syn::TypePath) {
+        // Uses of generic type parameters (`T`, `T::Item`)
+        if ty.qself.is_none()
+            && ty.path.leading_colon.is_none()
+            && let Some(path_seg) = ty.path.segments.first()
+            && self.is_type_parameter(&path_seg.ident)
+        {
+            let normalized = self.normalize_identifier(&path_seg.ident);
+            ty.path.segments[0].ident = normalized;
+        }
+
+        syn::visit_mut::visit_type_path_mut(self, ty);
+    }
+
+    fn visit_field_value_mut(&mut self, field: &mut syn::FieldValue) {
+        syn::visit_mut::visit_field_value_mut(self, field);
+
+        // Shorthand `Point { x }` must be spelled out once `x` is renamed
+        if field.colon_token.is_none()
+            && let (syn::Member::Named(member), syn::Expr::Path(expr)) =
+                (&field.member, &field.expr)
+            && !expr.path.is_ident(member)
+        {
+            field.colon_token = Some(Default::default());
+        }
+    }
+
+    fn visit_field_pat_mut(&mut self, field: &mut syn::FieldPat) {
+        syn::visit_mut::visit_field_pat_mut(self, field);
+
+        if field.colon_token.is_none()
+            && let (syn::Member::Named(member), syn::Pat::Ident(pat)) = (&field.member, &*field.pat)
+            && pat.ident != *member
+        {
+            field.colon_token = Some(Default::default());
+        }
+    }
 
-        // Normalize function name
+    fn visit_macro_mut(&mut self, mac: &mut syn::Macro) {
+        mac.tokens = self.rename_tokens(std::mem::take(&mut mac.tokens));
+    }
+
+    fn visit_item_fn_mut(&mut self, item: &mut syn::ItemFn) {
+        // Normalize function name in the enclosing scope so that calls to it resolve
         let normalized_name = self.normalize_identifier(&item.sig.ident);
         item.sig.ident = normalized_name;
 
-        // Normalize function parameters
-        for input in &mut item.sig.inputs {
-            match input {
-                syn::FnArg::Receiver(_) => {}
-                syn::FnArg::Typed(pat_type) => {
-                    self.visit_pat_mut(&mut pat_type.pat);
-                }
-            }
-        }
+        self.visit_fn_scoped(&mut item.sig, Some(&mut item.block));
+    }
 
-        // Visit function body
-        self.visit_block_mut(&mut item.block);
+    fn visit_impl_item_fn_mut(&mut self, item: &mut syn::ImplItemFn) {
+        // Method names are part of the type's API and are called through
+        // method syntax, so only the signature and body are normalized
+        self.visit_fn_scoped(&mut item.sig, Some(&mut item.block));
+    }
 
-        self.exit_scope();
+    fn visit_trait_item_fn_mut(&mut self, item: &mut syn::TraitItemFn) {
+        self.visit_fn_scoped(&mut item.sig, item.default.as_mut());
     }
 
     fn visit_block_mut(&mut self, block: &mut syn::Block) {
@@ -219,8 +429,11 @@ impl<'ast> VisitMut for Normalizer<'ast> {
         // Visit impl block
         self.visit_generics_mut(&mut item.generics);
 
-        // Handle different impl types - simplified approach
-        // Just visit the self type regardless of trait impl or not
+        // Generic arguments of the implemented trait (`impl<T> From<T> for ..`)
+        if let Some((_, path, _)) = &mut item.trait_ {
+            self.visit_path_mut(path);
+        }
+
         self.visit_type_mut(&mut item.self_ty);
 
         for item in &mut item.items {
@@ -231,19 +444,185 @@ impl<'ast> VisitMut for Normalizer<'ast> {
     }
 }
 
+/// A single name brought into scope by a `use` declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// Fully spelled declaration, e.g. `use std::io::BufRead as Buf`
+    pub declaration: String,
+    /// Name bound in scope; `None` for globs and `as _` imports
+    pub binding: Option<String>,
+}
+
+/// Flattened imports of all `use` items in a list of items
+pub fn collect_imports(items: &[syn::Item]) -> Vec<Import> {
+    let mut imports = Vec::new();
+
+    for item in items {
+        if let syn::Item::Use(item_use) = item {
+            let prefix = if item_use.leading_colon.is_some() {
+                vec![String::new()]
+            } else {
+                Vec::new()
+            };
+            flatten_use_tree(&item_use.tree, prefix, &mut imports);
+        }
+    }
+
+    imports
+}
+
+/// Flatten a use tree into one import per bound name.
+///
+/// `use std::io::{self, Read as R, prelude::*}` becomes `use std::io`,
+/// `use std::io::Read as R` and `use std::io::prelude::*`.
+pub fn flatten_use_tree(tree: &syn::UseTree, mut prefix: Vec<String>, out: &mut Vec<Import>) {
+    match tree {
+        syn::UseTree::Path(path) => {
+            prefix.push(path.ident.to_string());
+            flatten_use_tree(&path.tree, prefix, out);
+        }
+        syn::UseTree::Name(name) if name.ident == "self" => {
+            // `use a::b::{self}` imports `a::b` itself
+            if let Some(binding) = prefix.last().cloned() {
+                out.push(Import {
+                    declaration: format!("use {}", prefix.join("::")),
+                    binding: Some(binding),
+                });
+            }
+        }
+        syn::UseTree::Name(name) => {
+            prefix.push(name.ident.to_string());
+            out.push(Import {
+                declaration: format!("use {}", prefix.join("::")),
+                binding: Some(name.ident.to_string()),
+            });
+        }
+        syn::UseTree::Rename(rename) => {
+            let original = if rename.ident == "self" {
+                prefix.join("::")
+            } else {
+                prefix.push(rename.ident.to_string());
+                prefix.join("::")
+            };
+            let binding = (rename.rename != "_").then(|| rename.rename.to_string());
+            out.push(Import {
+                declaration: format!("use {} as {}", original, rename.rename),
+                binding,
+            });
+        }
+        syn::UseTree::Glob(_) => {
+            prefix.push("*".to_string());
+            out.push(Import {
+                declaration: format!("use {}", prefix.join("::")),
+                binding: None,
+            });
+        }
+        syn::UseTree::Group(group) => {
+            for tree in &group.items {
+                flatten_use_tree(tree, prefix.clone(), out);
+            }
+        }
+    }
+}
+
+/// Remove `#[doc = "..."]` and `#![doc = "..."]` attributes (the desugared
+/// form of doc comments) at any depth of a token stream.
+///
+/// Other `doc` attributes such as `#[doc(hidden)]` are kept.
+pub fn strip_doc_attributes(tokens: TokenStream) -> TokenStream {
+    let mut output: Vec<TokenTree> = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        match token {
+            TokenTree::Punct(pound) if pound.as_char() == '#' => {
+                let mut attribute = vec![TokenTree::Punct(pound)];
+                if let Some(TokenTree::Punct(bang)) = tokens.peek()
+                    && bang.as_char() == '!'
+                {
+                    attribute.extend(tokens.next());
+                }
+
+                match tokens.peek() {
+                    Some(TokenTree::Group(group)) if is_doc_comment(group) => {
+                        tokens.next();
+                    }
+                    _ => output.extend(attribute),
+                }
+            }
+            TokenTree::Group(group) => {
+                let mut stripped =
+                    Group::new(group.delimiter(), strip_doc_attributes(group.stream()));
+                stripped.set_span(group.span());
+                output.push(TokenTree::Group(stripped));
+            }
+            other => output.push(other),
+        }
+    }
+
+    output.into_iter().collect()
+}
+
+/// Whether an attribute body is `[doc = ...]`
+fn is_doc_comment(group: &Group) -> bool {
+    let mut inner = group.stream().into_iter();
+    group.delimiter() == proc_macro2::Delimiter::Bracket
+        && matches!(inner.next(), Some(TokenTree::Ident(ident)) if ident == "doc")
+        && matches!(inner.next(), Some(TokenTree::Punct(punct)) if punct.as_char() == '=')
+}
+
+/// Attributes attached to an item, including inner attributes of modules
+/// and function bodies
+fn item_attrs(item: &syn::Item) -> &[syn::Attribute] {
+    match item {
+        syn::Item::Const(item) => &item.attrs,
+        syn::Item::Enum(item) => &item.attrs,
+        syn::Item::ExternCrate(item) => &item.attrs,
+        syn::Item::Fn(item) => &item.attrs,
+        syn::Item::ForeignMod(item) => &item.attrs,
+        syn::Item::Impl(item) => &item.attrs,
+        syn::Item::Macro(item) => &item.attrs,
+        syn::Item::Mod(item) => &item.attrs,
+        syn::Item::Static(item) => &item.attrs,
+        syn::Item::Struct(item) => &item.attrs,
+        syn::Item::Trait(item) => &item.attrs,
+        syn::Item::TraitAlias(item) => &item.attrs,
+        syn::Item::Type(item) => &item.attrs,
+        syn::Item::Union(item) => &item.attrs,
+        syn::Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
 /// Normalize a complete ExtractedItem
+///
+/// The item body is re-parsed, its identifiers are anonymized by a fresh
+/// `Normalizer` and the result is pretty-printed, so items that only differ
+/// in local names or formatting end up with the same `body_normalized`.
+/// Doc comments are collected into `docstring`, and only stay in the body
+/// when `keep_docs` is set.
 pub fn normalize_item(item: &mut ExtractedItem, config: &ExtractConfig) -> CoreResult<()> {
-    let _normalizer = Normalizer::new(config);
-
-    // For now, just keep the original content
-    // In a full implementation, we would:
-    // 1. Parse the body back into AST
-    // 2. Normalize the AST
-    // 3. Convert back to string
+    let mut normalizer = Normalizer::new(config);
+    let tokens: TokenStream = syn::parse_str(&item.content.body_normalized)?;
+
+    let (docstring, body_normalized) = match item.item_meta.kind {
+        // Impl and trait methods parse as free functions
+        ItemKind::Method => {
+            let parsed: syn::ItemFn = syn::parse2(tokens.clone())?;
+            let docstring = normalizer.extract_docstring(&parsed.attrs);
+            let parsed = syn::parse2(normalizer.strip_docs_unless_kept(tokens))?;
+            (docstring, normalizer.normalize_method(parsed))
+        }
+        _ => {
+            let parsed: syn::Item = syn::parse2(tokens.clone())?;
+            let docstring = normalizer.extract_docstring(item_attrs(&parsed));
+            let parsed = syn::parse2(normalizer.strip_docs_unless_kept(tokens))?;
+            (docstring, normalizer.normalize_syn_item(parsed))
+        }
+    };
 
-    // Update the item with basic info
-    item.content.docstring = None;
-    item.content.imports = Vec::new();
+    item.content.docstring = docstring;
+    item.content.body_normalized = body_normalized;
 
     Ok(())
 }
@@ -255,3 +634,157 @@ pub fn normalize_items(items: &mut [ExtractedItem], config: &ExtractConfig) -> C
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::extract_items_from_source;
+    use crate::hashing::hash_and_update_items;
+
+    fn normalized(source: &str) -> Vec<ExtractedItem> {
+        normalized_with(source, &ExtractConfig::default())
+    }
+
+    fn normalized_with(source: &str, config: &ExtractConfig) -> Vec<ExtractedItem> {
+        let mut items = extract_items_from_source(
+            source,
+            "lib.rs".to_string(),
+            "test".to_string(),
+            "test".to_string(),
+            config,
+        )
+        .unwrap();
+        normalize_items(&mut items, config).unwrap();
+        hash_and_update_items(&mut items).unwrap();
+        items
+    }
+
+    #[test]
+    fn test_identifiers_are_anonymized() {
+        let items = normalized(
+            "fn largest<T: PartialOrd + Copy>(list: &[T]) -> T { \
+                let mut best = list[0]; \
+                for &item in list { if item > best { best = item; } } \
+                println!(\"{}\", best); \
+                best \
+            }",
+        );
+
+        let body = &items[0].content.body_normalized;
+        assert!(body.starts_with("fn var_0<T0: PartialOrd + Copy>(var_1: &[T0]) -> T0 {"));
+        assert!(body.contains("let mut var_2 = var_1[0];"));
+        assert!(body.contains("println!(\"{}\", var_2);"));
+        assert!(!body.contains("best"));
+    }
+
+    #[test]
+    fn test_local_names_do_not_affect_hash() {
+        let items = normalized(
+            "fn area(width: u32, height: u32) -> u32 { let result = width * height; result }
+             fn size(w: u32, h: u32) -> u32 {
+                 let r = w * h;
+                 r
+             }",
+        );
+
+        assert_eq!(
+            items[0].content.body_normalized,
+            items[1].content.body_normalized
+        );
+        assert_eq!(
+            items[0].content.semantic_hash,
+            items[1].content.semantic_hash
+        );
+    }
+
+    #[test]
+    fn test_shorthand_fields_and_variants() {
+        let items = normalized(
+            "fn f(p: Option<Point>) -> Point { \
+                match p { Some(Point { x, y }) => Point { x, y }, None => Point { x: 0, y: 0 } } \
+            }",
+        );
+
+        let body = &items[0].content.body_normalized;
+        assert!(
+            body.contains("Some(Point { x: var_2, y: var_3 }) => Point { x: var_2, y: var_3 }")
+        );
+        assert!(body.contains("None =>"));
+    }
+
+    #[test]
+    fn test_docstrings_are_collected() {
+        let source = r#"
+/// Adds two numbers.
+///
+/// ```
+/// let x = add(1, 2);
+///     assert_eq!(x, 3);
+/// ```
+fn add(a: i32, b: i32) -> i32 { a + b }
+
+mod utils {
+    //! Helper functions.
+    //!   Indented line.
+
+    /// Nested item docs
+    #[doc(hidden)]
+    pub fn helper() {}
+}
+"#;
+
+        let items = normalized(source);
+        assert_eq!(
+            items[0].content.docstring.as_deref(),
+            Some("Adds two numbers.\n\n```\nlet x = add(1, 2);\n    assert_eq!(x, 3);\n```")
+        );
+        assert_eq!(
+            items[1].content.docstring.as_deref(),
+            Some("Helper functions.\n  Indented line.")
+        );
+        assert_eq!(
+            items[2].content.docstring.as_deref(),
+            Some("Nested item docs")
+        );
+
+        // A line starting with other whitespace is kept, not cut mid-char
+        let spaced = normalized("/// \u{a0}Spaced\n///  Indented\nfn f() {}");
+        assert_eq!(
+            spaced[0].content.docstring.as_deref(),
+            Some("\u{a0}Spaced\n Indented")
+        );
+
+        // Doc comments are stripped from bodies by default, `#[doc(hidden)]` stays
+        assert!(
+            !items[0]
+                .content
+                .body_normalized
+                .contains("Adds two numbers")
+        );
+        assert!(
+            !items[1]
+                .content
+                .body_normalized
+                .contains("Nested item docs")
+        );
+        assert!(items[1].content.body_normalized.contains("#[doc(hidden)]"));
+
+        let config = ExtractConfig {
+            keep_docs: true,
+            ..ExtractConfig::default()
+        };
+        let items = normalized_with(source, &config);
+        assert!(
+            items[0]
+                .content
+                .body_normalized
+                .starts_with("/// Adds two numbers.")
+        );
+        assert!(
+            items[1]
+                .content
+                .body_normalized
+                .contains("//! Helper functions.")
+        );
+    }
+}
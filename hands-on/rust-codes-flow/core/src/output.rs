@@ -0,0 +1,298 @@
+use crate::columnar::{ArrowIpcWriter, ColumnarOptions, ParquetWriter};
+use crate::models::*;
+use crate::shards::{ShardSize, ShardedJsonlWriter};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// File format of the dataset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Parquet with nested struct columns
+    Parquet,
+    /// Arrow IPC file format
+    ArrowIpc,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+            OutputFormat::Parquet => write!(f, "parquet"),
+            OutputFormat::ArrowIpc => write!(f, "arrow-ipc"),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Conventional file extension, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Jsonl => "jsonl",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::ArrowIpc => "arrow",
+        }
+    }
+}
+
+/// Compression applied by the dataset writer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression of a file, from its `.gz` or `.zst` extension
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// How and where the dataset is written
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    pub compression: Compression,
+    /// Rows per record batch and Parquet row group
+    pub row_group_size: Option<usize>,
+    /// Split JSONL output into shards of this size
+    pub shard_size: Option<ShardSize>,
+}
+
+/// Create the sink for `options.format` writing to `path`
+pub fn create_sink(path: &Path, options: &OutputOptions) -> CoreResult<Box<dyn ItemSink>> {
+    let mut columnar = ColumnarOptions {
+        compression: options.compression,
+        ..Default::default()
+    };
+    if let Some(row_group_size) = options.row_group_size {
+        columnar.batch_size = row_group_size;
+    }
+
+    if options.shard_size.is_some() && options.format != OutputFormat::Jsonl {
+        return Err(CoreError::InvalidInput(format!(
+            "sharding is not supported for {} output",
+            options.format
+        )));
+    }
+
+    match options.format {
+        OutputFormat::Jsonl => match (options.compression, options.shard_size) {
+            (Compression::None, None) => Ok(Box::new(JsonlWriter::create(path)?)),
+            (compression, shard_size) => Ok(Box::new(ShardedJsonlWriter::create(
+                path,
+                compression,
+                shard_size,
+            )?)),
+        },
+        OutputFormat::Parquet => Ok(Box::new(ParquetWriter::create(path, columnar)?)),
+        OutputFormat::ArrowIpc => Ok(Box::new(ArrowIpcWriter::create(path, columnar)?)),
+    }
+}
+
+/// Destination for extracted items
+///
+/// Items are written one at a time as the pipeline produces them, so sinks
+/// never need the whole dataset in memory.
+pub trait ItemSink {
+    /// Write a single item
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()>;
+
+    /// Flush buffered data once all items have been written
+    fn finish(&mut self) -> CoreResult<()>;
+}
+
+impl<S: ItemSink + ?Sized> ItemSink for Box<S> {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        (**self).write_item(item)
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        (**self).finish()
+    }
+}
+
+/// Writes one JSON object per line
+pub struct JsonlWriter<W: Write> {
+    writer: BufWriter<W>,
+}
+
+impl JsonlWriter<File> {
+    /// Create (or truncate) a JSONL file
+    pub fn create(path: &Path) -> CoreResult<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+        }
+    }
+
+    /// The underlying writer; lines still buffered have not reached it
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Flush buffered lines and return the underlying writer
+    pub fn into_inner(self) -> CoreResult<W> {
+        self.writer
+            .into_inner()
+            .map_err(|e| CoreError::Io(e.into_error()))
+    }
+}
+
+impl<W: Write> ItemSink for JsonlWriter<W> {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        serde_json::to_writer(&mut self.writer, item)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads items back from JSONL, one line at a time
+pub struct JsonlReader<R: BufRead> {
+    lines: std::io::Lines<R>,
+}
+
+impl JsonlReader<Box<dyn BufRead>> {
+    /// Open a JSONL file, decompressing it when its name ends in `.gz` or
+    /// `.zst`
+    pub fn open(path: &Path) -> CoreResult<Self> {
+        let file = File::open(path)?;
+        let reader: Box<dyn BufRead> = match Compression::of_path(path) {
+            Compression::None => Box::new(BufReader::new(file)),
+            Compression::Gzip => Box::new(BufReader::new(flate2::read::MultiGzDecoder::new(file))),
+            Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(file)?)),
+        };
+        Ok(Self::new(reader))
+    }
+}
+
+impl<R: BufRead> JsonlReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonlReader<R> {
+    type Item = CoreResult<ExtractedItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if !line.trim().is_empty() {
+                return Some(serde_json::from_str(&line).map_err(Into::into));
+            }
+        }
+    }
+}
+
+/// Anonymous temporary JSONL file holding items between two passes
+///
+/// Used when a decision about an item needs to see the whole dataset first,
+/// without keeping the dataset in memory. The file is removed when dropped.
+pub struct Spool {
+    writer: JsonlWriter<File>,
+    items: usize,
+}
+
+impl Spool {
+    pub fn new() -> CoreResult<Self> {
+        Ok(Self {
+            writer: JsonlWriter::new(tempfile::tempfile()?),
+            items: 0,
+        })
+    }
+
+    /// Number of items written so far
+    pub fn len(&self) -> usize {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    /// Read the spooled items back in the order they were written
+    pub fn into_reader(self) -> CoreResult<JsonlReader<BufReader<File>>> {
+        let mut file = self.writer.into_inner()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(JsonlReader::new(BufReader::new(file)))
+    }
+}
+
+impl ItemSink for Spool {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        self.items += 1;
+        self.writer.write_item(item)
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool_round_trip() {
+        let mut spool = Spool::new().unwrap();
+        for name in ["a", "b", "c"] {
+            let item = ExtractedItem {
+                item_meta: ItemMeta {
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            spool.write_item(&item).unwrap();
+        }
+        assert_eq!(spool.len(), 3);
+
+        let names: Vec<String> = spool
+            .into_reader()
+            .unwrap()
+            .map(|item| item.unwrap().item_meta.name)
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+}
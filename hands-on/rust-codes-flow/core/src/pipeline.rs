@@ -0,0 +1,265 @@
+use crate::cache::{ExtractionCache, content_hash};
+use crate::extractor::{dataset_path, extract_items_with_context};
+use crate::hashing::hash_and_update_items;
+use crate::license::spdx_header;
+use crate::models::*;
+use crate::normalizer::normalize_items;
+use crate::scrub::scrub_items;
+use crate::workspace::WorkspaceIndex;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Streaming extraction pipeline for the files of one repository
+///
+/// Files are extracted, normalized and hashed in parallel on the current
+/// rayon pool, one chunk at a time. Each file's items are handed to the
+/// caller in input order as soon as its chunk is done, so memory is bounded
+/// by the chunk size rather than by the size of the repository.
+pub struct Pipeline<'a> {
+    config: &'a ExtractConfig,
+    repo_url: String,
+    commit_hash: String,
+    chunk_size: usize,
+    workspace: Option<&'a WorkspaceIndex>,
+    license: Option<String>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(config: &'a ExtractConfig, repo_url: String, commit_hash: String) -> Self {
+        Self {
+            config,
+            repo_url,
+            commit_hash,
+            chunk_size: rayon::current_num_threads() * 4,
+            workspace: None,
+            license: None,
+        }
+    }
+
+    /// Attach crate information and module paths from a workspace index
+    pub fn with_workspace(mut self, workspace: &'a WorkspaceIndex) -> Self {
+        self.workspace = Some(workspace);
+        self
+    }
+
+    /// License of files without a package license or an SPDX header,
+    /// usually the one of the repository
+    pub fn with_license(mut self, license: Option<String>) -> Self {
+        self.license = license;
+        self
+    }
+
+    /// Number of files processed in parallel before their items are emitted
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Extract, normalize and hash the items of a single file
+    pub fn process_file(&self, file_path: &Path) -> CoreResult<Vec<ExtractedItem>> {
+        let source = std::fs::read_to_string(file_path)?;
+        self.process_source(file_path, &source)
+    }
+
+    /// Extract, normalize and hash the items of a file's contents, e.g. as
+    /// read from another commit
+    pub fn process_source(&self, file_path: &Path, source: &str) -> CoreResult<Vec<ExtractedItem>> {
+        let mut items = extract_items_with_context(
+            source,
+            self.source_context(file_path, source),
+            self.config,
+        )?;
+        normalize_items(&mut items, self.config)?;
+        if self.config.scrub {
+            scrub_items(&mut items);
+        }
+        hash_and_update_items(&mut items)?;
+        Ok(items)
+    }
+
+    /// Process `files` and call `on_file` with each file's result, in order.
+    ///
+    /// A failing file is reported through `on_file` and does not stop the
+    /// run; an error returned by `on_file` (e.g. a failed write) does.
+    pub fn run<F>(&self, files: &[PathBuf], on_file: F) -> CoreResult<()>
+    where
+        F: FnMut(&Path, CoreResult<Vec<ExtractedItem>>) -> CoreResult<()>,
+    {
+        self.run_with_cache(files, None, on_file)
+    }
+
+    /// Like `run`, but files whose contents are found in `cache` are not
+    /// parsed again, and the items of every file are stored for the next run
+    pub fn run_with_cache<F>(
+        &self,
+        files: &[PathBuf],
+        mut cache: Option<&mut ExtractionCache>,
+        mut on_file: F,
+    ) -> CoreResult<()>
+    where
+        F: FnMut(&Path, CoreResult<Vec<ExtractedItem>>) -> CoreResult<()>,
+    {
+        for chunk in files.chunks(self.chunk_size) {
+            let sources: Vec<CoreResult<(String, String)>> = chunk
+                .par_iter()
+                .map(|file_path| {
+                    let source = std::fs::read_to_string(file_path)?;
+                    let hash = content_hash(&source);
+                    Ok((source, hash))
+                })
+                .collect();
+
+            // Cache lookups read a single file and stay on this thread
+            let contexts: Vec<ProjectContext> = chunk
+                .iter()
+                .zip(&sources)
+                .map(|(file_path, source)| match source {
+                    Ok((source, _)) => self.source_context(file_path, source),
+                    Err(_) => self.project_context(file_path),
+                })
+                .collect();
+            let mut cached = Vec::with_capacity(chunk.len());
+            for (context, source) in contexts.iter().zip(&sources) {
+                let hit = match (cache.as_deref_mut(), source) {
+                    (Some(cache), Ok((_, hash))) => cache.lookup(hash, context)?,
+                    _ => None,
+                };
+                cached.push(hit);
+            }
+
+            let hashes: Vec<Option<String>> = sources
+                .iter()
+                .map(|source| source.as_ref().ok().map(|(_, hash)| hash.clone()))
+                .collect();
+
+            let results: Vec<CoreResult<Vec<ExtractedItem>>> = chunk
+                .par_iter()
+                .zip(sources)
+                .zip(cached)
+                .map(|((file_path, source), cached)| match cached {
+                    Some(items) => Ok(items),
+                    None => self.process_source(file_path, &source?.0),
+                })
+                .collect();
+
+            for (((file_path, result), hash), context) in
+                chunk.iter().zip(results).zip(hashes).zip(&contexts)
+            {
+                if let (Some(cache), Ok(items), Some(hash)) = (cache.as_deref_mut(), &result, hash)
+                {
+                    cache.store(&hash, context, items)?;
+                }
+                on_file(file_path, result)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Context shared by all items of a file
+    pub fn project_context(&self, file_path: &Path) -> ProjectContext {
+        let mut context = ProjectContext {
+            repo_url: self.repo_url.clone(),
+            commit_hash: self.commit_hash.clone(),
+            file_path: dataset_path(file_path),
+            license: self.license.clone(),
+            ..Default::default()
+        };
+        if let Some(module) = self
+            .workspace
+            .and_then(|workspace| workspace.lookup(file_path))
+        {
+            module.apply(&mut context);
+        }
+        context
+    }
+
+    /// Context of a file with known contents: an SPDX header overrides the
+    /// package and repository license
+    pub fn source_context(&self, file_path: &Path, source: &str) -> ProjectContext {
+        let mut context = self.project_context(file_path);
+        if let Some(license) = spdx_header(source) {
+            context.license = Some(license);
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_preserves_file_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut files = Vec::new();
+        for index in 0..5 {
+            let path = dir.path().join(format!("file_{index}.rs"));
+            std::fs::write(&path, format!("fn f{index}() {{}}\nfn g{index}() {{}}")).unwrap();
+            files.push(path);
+        }
+        let broken = dir.path().join("broken.rs");
+        std::fs::write(&broken, "fn (").unwrap();
+        files.insert(2, broken);
+
+        let config = ExtractConfig::default();
+        let pipeline =
+            Pipeline::new(&config, "test".to_string(), "test".to_string()).with_chunk_size(2);
+
+        let mut names = Vec::new();
+        let mut failures = 0;
+        pipeline
+            .run(&files, |_, result| {
+                match result {
+                    Ok(items) => names.extend(items.into_iter().map(|item| item.item_meta.name)),
+                    Err(_) => failures += 1,
+                }
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(failures, 1);
+        assert_eq!(
+            names,
+            ["f0", "g0", "f1", "g1", "f2", "g2", "f3", "g3", "f4", "g4"]
+        );
+    }
+
+    #[test]
+    fn test_cached_run_skips_unchanged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = ["a.rs", "b.rs"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        std::fs::write(&files[0], "fn a() {}").unwrap();
+        std::fs::write(&files[1], "fn b() {}").unwrap();
+
+        let config = ExtractConfig::default();
+        let pipeline = Pipeline::new(&config, "test".to_string(), "test".to_string());
+        let cache_dir = dir.path().join("cache");
+        let run = |pipeline: &Pipeline| {
+            let mut cache = ExtractionCache::open(&cache_dir, "test", &config).unwrap();
+            let mut items = Vec::new();
+            pipeline
+                .run_with_cache(&files, Some(&mut cache), |_, result| {
+                    items.extend(result?);
+                    Ok(())
+                })
+                .unwrap();
+            let counts = (cache.hits(), cache.misses());
+            cache.finish().unwrap();
+            (counts, items)
+        };
+
+        let (counts, first) = run(&pipeline);
+        assert_eq!(counts, (0, 2));
+
+        std::fs::write(&files[1], "fn b() -> u8 { 1 }").unwrap();
+        let (counts, second) = run(&pipeline);
+        assert_eq!(counts, (1, 1));
+        assert_eq!(first[0].item_meta.id, second[0].item_meta.id);
+        assert_eq!(first[0].content, second[0].content);
+        assert_ne!(first[1].content, second[1].content);
+    }
+}
@@ -0,0 +1,91 @@
+use crate::models::*;
+use serde_json::{Value, json};
+
+/// JSON Schema (draft 2020-12) of a dataset record, one `ExtractedItem`,
+/// generated from the model types and their serde attributes.
+///
+/// `schema_version` is required and pinned to `SCHEMA_VERSION`, so records
+/// of another format version fail validation.
+pub fn json_schema() -> Value {
+    let mut schema = schemars::schema_for!(ExtractedItem).to_value();
+
+    // A URN rather than a URL: there is no server to resolve the schema from
+    schema["$id"] = json!(format!("urn:rustcodesflow:extracted-item:{SCHEMA_VERSION}"));
+    schema["properties"]["schema_version"]["const"] = json!(SCHEMA_VERSION);
+    if let Some(required) = schema["required"].as_array_mut()
+        && !required.contains(&json!("schema_version"))
+    {
+        required.push(json!("schema_version"));
+    }
+
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractor::extract_items_from_source;
+
+    /// Keys of `value` missing from the `properties` of `schema`, following
+    /// `$ref`s into `$defs` and nullable `anyOf`s
+    fn missing_keys(
+        root: &Value,
+        schema: &Value,
+        value: &Value,
+        path: &str,
+        out: &mut Vec<String>,
+    ) {
+        let schema = match schema["$ref"].as_str() {
+            Some(reference) => &root["$defs"][reference.trim_start_matches("#/$defs/")],
+            None => schema,
+        };
+        if let Some(variants) = schema["anyOf"].as_array() {
+            for variant in variants {
+                if variant["type"] != "null" {
+                    missing_keys(root, variant, value, path, out);
+                }
+            }
+            return;
+        }
+        if let Value::Object(fields) = value {
+            for (key, field) in fields {
+                let property = &schema["properties"][key];
+                if property.is_null() {
+                    out.push(format!("{path}.{key}"));
+                } else {
+                    missing_keys(root, property, field, &format!("{path}.{key}"), out);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema_covers_every_field() {
+        let config = ExtractConfig {
+            fim: Some(FimConfig::default()),
+            ..Default::default()
+        };
+        let items = extract_items_from_source(
+            "fn f(x: u32) -> u32 {\n    x + 1\n}\n",
+            "lib.rs".to_string(),
+            "repo".to_string(),
+            "commit".to_string(),
+            &config,
+        )
+        .unwrap();
+        let record = serde_json::to_value(&items[0]).unwrap();
+
+        let schema = json_schema();
+        let mut missing = Vec::new();
+        missing_keys(&schema, &schema, &record, "", &mut missing);
+        assert!(missing.is_empty(), "not in the schema: {missing:?}");
+
+        assert_eq!(record["schema_version"], SCHEMA_VERSION);
+        assert_eq!(
+            schema["properties"]["schema_version"]["const"],
+            SCHEMA_VERSION
+        );
+        let kinds = schema["$defs"]["ItemKind"].to_string();
+        assert!(kinds.contains("\"type_alias\""), "{kinds}");
+    }
+}
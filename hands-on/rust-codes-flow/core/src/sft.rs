@@ -0,0 +1,497 @@
+use crate::models::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::LazyLock;
+use syn::spanned::Spanned;
+
+/// Kind of prompt/completion pair built from dataset items
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SftTemplate {
+    /// A function's docstring as the prompt, the function without its doc
+    /// comments as the completion
+    DocToCode,
+    /// A function's signature as the prompt, its body block as the
+    /// completion
+    SignatureToBody,
+    /// The item's own `fim` sample, for datasets extracted with `--fim`.
+    /// Items without one are skipped: the dataset keeps only their
+    /// anonymized code, which would not match the raw code around them.
+    Fim,
+    /// A test as the prompt, the function it exercises as the completion
+    TestToImpl,
+}
+
+impl SftTemplate {
+    pub const ALL: [SftTemplate; 4] = [
+        SftTemplate::DocToCode,
+        SftTemplate::SignatureToBody,
+        SftTemplate::Fim,
+        SftTemplate::TestToImpl,
+    ];
+}
+
+impl fmt::Display for SftTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SftTemplate::DocToCode => write!(f, "doc-to-code"),
+            SftTemplate::SignatureToBody => write!(f, "signature-to-body"),
+            SftTemplate::Fim => write!(f, "fim"),
+            SftTemplate::TestToImpl => write!(f, "test-to-impl"),
+        }
+    }
+}
+
+/// Layout of an exported sample
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SftFormat {
+    /// `{"prompt": ..., "completion": ...}`
+    #[default]
+    PromptCompletion,
+    /// `{"messages": [{"role": "user", ...}, {"role": "assistant", ...}]}`
+    Chat,
+    /// `{"instruction": ..., "input": ..., "output": ...}`
+    Alpaca,
+}
+
+impl fmt::Display for SftFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SftFormat::PromptCompletion => write!(f, "prompt-completion"),
+            SftFormat::Chat => write!(f, "chat"),
+            SftFormat::Alpaca => write!(f, "alpaca"),
+        }
+    }
+}
+
+/// Settings of the fine-tuning export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SftConfig {
+    pub templates: Vec<SftTemplate>,
+}
+
+impl Default for SftConfig {
+    fn default() -> Self {
+        Self {
+            templates: SftTemplate::ALL.to_vec(),
+        }
+    }
+}
+
+/// A prompt/completion pair
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SftSample {
+    pub template: SftTemplate,
+    /// Id of the item the completion is taken from
+    pub item_id: String,
+    /// Id of the item the prompt is taken from, when it is another item
+    /// (the test of a test-to-impl pair)
+    pub prompt_item_id: Option<String>,
+    /// What to do; empty for FIM, whose input is the whole prompt
+    pub instruction: String,
+    pub input: String,
+    pub output: String,
+}
+
+impl SftSample {
+    /// Instruction and input as a single prompt
+    pub fn prompt(&self) -> String {
+        match (self.instruction.is_empty(), self.input.is_empty()) {
+            (true, _) => self.input.clone(),
+            (false, true) => self.instruction.clone(),
+            (false, false) => format!("{}\n\n{}", self.instruction, self.input),
+        }
+    }
+
+    /// JSON record of the sample in the given layout, tagged with its
+    /// template and item ids
+    pub fn to_json(&self, format: SftFormat) -> Value {
+        let mut record = match format {
+            SftFormat::PromptCompletion => json!({
+                "prompt": self.prompt(),
+                "completion": self.output,
+            }),
+            SftFormat::Chat => json!({
+                "messages": [
+                    { "role": "user", "content": self.prompt() },
+                    { "role": "assistant", "content": self.output },
+                ],
+            }),
+            SftFormat::Alpaca => json!({
+                "instruction": self.instruction,
+                "input": self.input,
+                "output": self.output,
+            }),
+        };
+        record["template"] = json!(self.template);
+        record["item_id"] = json!(self.item_id);
+        if let Some(prompt_item_id) = &self.prompt_item_id {
+            record["prompt_item_id"] = json!(prompt_item_id);
+        }
+        record
+    }
+}
+
+/// Crate an item belongs to: tests are only paired within one crate
+type CrateKey = (String, Option<String>);
+
+/// A function that tests may be paired with
+struct Implementation {
+    id: String,
+    file_path: String,
+}
+
+/// Turns dataset items into fine-tuning samples.
+///
+/// Test-to-impl pairs need to know every function first: pass each item to
+/// `observe`, then each item again to `samples`. Tests are kept in memory
+/// in between, other items only by name. The other templates work on a
+/// single pass over `samples`.
+pub struct SftExporter {
+    config: SftConfig,
+    implementations: HashMap<CrateKey, HashMap<String, Vec<Implementation>>>,
+    tests: Vec<ExtractedItem>,
+    /// Tests by the id of the implementation they were paired with, built
+    /// on the first call to `samples`
+    pairs: Option<HashMap<String, Vec<usize>>>,
+}
+
+/// Callee names in normalized code, e.g. `parse` in `parser::parse::<T>(`
+static CALL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"([A-Za-z_][A-Za-z0-9_]*)\s*(?:::\s*<[^()]*>\s*)?\(").expect("valid call regex")
+});
+
+impl SftExporter {
+    pub fn new(config: SftConfig) -> Self {
+        Self {
+            config,
+            implementations: HashMap::new(),
+            tests: Vec::new(),
+            pairs: None,
+        }
+    }
+
+    fn uses(&self, template: SftTemplate) -> bool {
+        self.config.templates.contains(&template)
+    }
+
+    /// First pass: remember tests and the functions they may exercise
+    pub fn observe(&mut self, item: &ExtractedItem) {
+        if !self.uses(SftTemplate::TestToImpl) || !is_function(item) {
+            return;
+        }
+
+        if item.item_meta.metrics.is_test {
+            self.tests.push(item.clone());
+        } else {
+            self.implementations
+                .entry(crate_key(item))
+                .or_default()
+                .entry(item.item_meta.name.clone())
+                .or_default()
+                .push(Implementation {
+                    id: item.item_meta.id.clone(),
+                    file_path: item.project_context.file_path.clone(),
+                });
+        }
+    }
+
+    /// Samples built from an item, in template order
+    ///
+    /// `signature-to-body` parses functions with `syn`; callers exporting a
+    /// whole dataset should call
+    /// `proc_macro2::extra::invalidate_current_thread_spans` between items.
+    pub fn samples(&mut self, item: &ExtractedItem) -> Vec<SftSample> {
+        let mut samples = Vec::new();
+        for template in self.config.templates.clone() {
+            match template {
+                SftTemplate::DocToCode => samples.extend(doc_to_code(item)),
+                SftTemplate::SignatureToBody => samples.extend(signature_to_body(item)),
+                SftTemplate::Fim => samples.extend(fim(item)),
+                SftTemplate::TestToImpl => samples.extend(self.test_to_impl(item)),
+            }
+        }
+        samples
+    }
+
+    /// Pairs of `item` as the implementation with each test exercising it
+    fn test_to_impl(&mut self, item: &ExtractedItem) -> Vec<SftSample> {
+        if self.pairs.is_none() {
+            self.pairs = Some(self.pair_tests());
+        }
+        let Some(tests) = self
+            .pairs
+            .as_ref()
+            .and_then(|pairs| pairs.get(&item.item_meta.id))
+        else {
+            return Vec::new();
+        };
+
+        tests
+            .iter()
+            .map(|&index| {
+                let test = &self.tests[index];
+                SftSample {
+                    template: SftTemplate::TestToImpl,
+                    item_id: item.item_meta.id.clone(),
+                    prompt_item_id: Some(test.item_meta.id.clone()),
+                    instruction: format!(
+                        "Implement the Rust {} exercised by this test.",
+                        kind_noun(item)
+                    ),
+                    input: test.content.body_normalized.trim().to_string(),
+                    output: item.content.body_normalized.trim().to_string(),
+                }
+            })
+            .collect()
+    }
+
+    /// Pair each test with the function it is named after among those it
+    /// calls, e.g. `test_parse_header` with `parse_header`.
+    ///
+    /// The longest such name wins. When several functions share it, the
+    /// one in the test's file is chosen; tests whose function is still
+    /// ambiguous are left out.
+    fn pair_tests(&self) -> HashMap<String, Vec<usize>> {
+        let mut pairs: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for (index, test) in self.tests.iter().enumerate() {
+            let Some(functions) = self.implementations.get(&crate_key(test)) else {
+                continue;
+            };
+            let test_name = test.item_meta.name.as_str();
+
+            let Some(candidates) = CALL
+                .captures_iter(&test.content.body_normalized)
+                .map(|capture| capture.get(1).map_or("", |name| name.as_str()))
+                .filter(|name| test_name.contains(name))
+                .filter_map(|name| functions.get(name).map(|found| (name.len(), found)))
+                .max_by_key(|(length, _)| *length)
+                .map(|(_, found)| found)
+            else {
+                continue;
+            };
+
+            let same_file: Vec<&Implementation> = candidates
+                .iter()
+                .filter(|candidate| candidate.file_path == test.project_context.file_path)
+                .collect();
+            let chosen = match same_file.as_slice() {
+                [only] => *only,
+                [] if candidates.len() == 1 => &candidates[0],
+                _ => continue,
+            };
+            pairs.entry(chosen.id.clone()).or_default().push(index);
+        }
+
+        pairs
+    }
+}
+
+fn crate_key(item: &ExtractedItem) -> CrateKey {
+    (
+        item.project_context.repo_url.clone(),
+        item.project_context.crate_name.clone(),
+    )
+}
+
+fn is_function(item: &ExtractedItem) -> bool {
+    matches!(item.item_meta.kind, ItemKind::Function | ItemKind::Method)
+}
+
+fn kind_noun(item: &ExtractedItem) -> &'static str {
+    match item.item_meta.kind {
+        ItemKind::Method => "method",
+        _ => "function",
+    }
+}
+
+/// Normalized code of an item without its leading doc comments
+fn without_docs(code: &str) -> String {
+    code.lines()
+        .skip_while(|line| {
+            let line = line.trim_start();
+            line.starts_with("///") || line.starts_with("//!") || line.starts_with("#[doc")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Split a function's code into everything up to its body block, and the
+/// block itself. `None` for code that is not a function with a non-empty
+/// body.
+fn split_function(code: &str) -> Option<(String, String)> {
+    let function: syn::ItemFn = syn::parse_str(code).ok()?;
+    if function.block.stmts.is_empty() {
+        return None;
+    }
+    let start = function.block.span().byte_range().start;
+    let (head, block) = code.split_at_checked(start)?;
+    Some((head.trim_end().to_string(), block.trim_end().to_string()))
+}
+
+fn doc_to_code(item: &ExtractedItem) -> Option<SftSample> {
+    let docstring = item.content.docstring.as_deref()?.trim();
+    if !is_function(item) || docstring.is_empty() {
+        return None;
+    }
+
+    Some(SftSample {
+        template: SftTemplate::DocToCode,
+        item_id: item.item_meta.id.clone(),
+        prompt_item_id: None,
+        instruction: format!(
+            "Write a Rust {} matching this documentation.",
+            kind_noun(item)
+        ),
+        input: docstring.to_string(),
+        output: without_docs(&item.content.body_normalized)
+            .trim()
+            .to_string(),
+    })
+}
+
+fn signature_to_body(item: &ExtractedItem) -> Option<SftSample> {
+    if !is_function(item) {
+        return None;
+    }
+    let (head, block) = split_function(&item.content.body_normalized)?;
+
+    Some(SftSample {
+        template: SftTemplate::SignatureToBody,
+        item_id: item.item_meta.id.clone(),
+        prompt_item_id: None,
+        instruction: format!("Write the body of this Rust {}.", kind_noun(item)),
+        input: head,
+        output: block,
+    })
+}
+
+fn fim(item: &ExtractedItem) -> Option<SftSample> {
+    let sample = item.fim.as_ref()?;
+    Some(SftSample {
+        template: SftTemplate::Fim,
+        item_id: item.item_meta.id.clone(),
+        prompt_item_id: None,
+        instruction: String::new(),
+        input: sample.prompt.clone(),
+        output: sample.middle.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(id: &str, name: &str, code: &str) -> ExtractedItem {
+        ExtractedItem {
+            project_context: ProjectContext {
+                repo_url: "repo".to_string(),
+                file_path: "src/lib.rs".to_string(),
+                ..Default::default()
+            },
+            item_meta: ItemMeta {
+                id: id.to_string(),
+                kind: ItemKind::Function,
+                name: name.to_string(),
+                ..Default::default()
+            },
+            content: Content {
+                body_normalized: code.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_templates() {
+        let mut item = function(
+            "a",
+            "double",
+            "/// Double a number\nfn var_0(var_1: u32) -> u32 {\n    var_1 * 2\n}\n",
+        );
+        item.content.docstring = Some("Double a number".to_string());
+        item.rag_context.context_before = Some("use std::fmt;\n".to_string());
+        item.rag_context.context_after = Some("\nfn other() {}".to_string());
+
+        let mut exporter = SftExporter::new(SftConfig {
+            templates: vec![
+                SftTemplate::DocToCode,
+                SftTemplate::SignatureToBody,
+                SftTemplate::Fim,
+            ],
+        });
+        // Without a `fim` sample of its own, the item gets no FIM sample:
+        // its anonymized code does not fit the raw context around it
+        assert_eq!(exporter.samples(&item).len(), 2);
+
+        item.fim = Some(FimSample {
+            order: FimOrder::Psm,
+            span_kind: FimSpanKind::Expression,
+            prefix: "fn double(n: u32) -> u32 {\n    ".to_string(),
+            middle: "n * 2".to_string(),
+            suffix: "\n}".to_string(),
+            prompt: "<fim_prefix>fn double(n: u32) -> u32 {\n    <fim_suffix>\n}<fim_middle>"
+                .to_string(),
+        });
+        let samples = exporter.samples(&item);
+        assert_eq!(samples.len(), 3);
+
+        assert_eq!(samples[0].input, "Double a number");
+        assert_eq!(
+            samples[0].output,
+            "fn var_0(var_1: u32) -> u32 {\n    var_1 * 2\n}"
+        );
+
+        assert_eq!(
+            samples[1].input,
+            "/// Double a number\nfn var_0(var_1: u32) -> u32"
+        );
+        assert_eq!(samples[1].output, "{\n    var_1 * 2\n}");
+
+        assert_eq!(samples[2].prompt(), item.fim.as_ref().unwrap().prompt);
+        assert_eq!(samples[2].output, "n * 2");
+
+        let record = samples[0].to_json(SftFormat::Chat);
+        assert_eq!(record["messages"][1]["content"], samples[0].output);
+        assert_eq!(record["template"], "doc-to-code");
+    }
+
+    #[test]
+    fn test_tests_are_paired_with_the_function_they_exercise() {
+        let parse = function("parse", "parse_header", "fn var_0() {}");
+        let mut other = function("other", "parse_header", "fn var_0() {}");
+        other.project_context.file_path = "src/other.rs".to_string();
+        let helper = function("helper", "helper", "fn var_0() {}");
+
+        let mut test = function(
+            "test",
+            "test_parse_header",
+            "fn var_0() {\n    assert!(parse_header(helper()));\n}",
+        );
+        test.item_meta.metrics.is_test = true;
+        let mut unrelated = function("unrelated", "it_works", "fn var_0() { helper(); }");
+        unrelated.item_meta.metrics.is_test = true;
+
+        let items = [parse, other, helper, test, unrelated];
+        let mut exporter = SftExporter::new(SftConfig {
+            templates: vec![SftTemplate::TestToImpl],
+        });
+        for item in &items {
+            exporter.observe(item);
+        }
+
+        let samples: Vec<SftSample> = items
+            .iter()
+            .flat_map(|item| exporter.samples(item))
+            .collect();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].item_id, "parse");
+        assert_eq!(samples[0].prompt_item_id.as_deref(), Some("test"));
+    }
+}
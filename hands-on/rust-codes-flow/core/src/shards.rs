@@ -0,0 +1,529 @@
+use crate::models::*;
+use crate::output::{Compression, ItemSink, JsonlReader, JsonlWriter};
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// When a JSONL shard is closed and the next one started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardSize {
+    /// Number of items per shard
+    Items(usize),
+    /// Approximate size of a shard on disk, after compression
+    Bytes(u64),
+}
+
+impl FromStr for ShardSize {
+    type Err = String;
+
+    /// Plain numbers count items; a unit (`B`, `KB`, `MB`, `GB`, `KiB`,
+    /// `MiB`, `GiB`) makes it a size in bytes, e.g. `100000` or `256MB`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let split = value
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid shard size `{value}`"))?;
+
+        let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+            "" => {
+                return match number {
+                    0 => Err("shard size must be positive".to_string()),
+                    items => usize::try_from(items)
+                        .map(ShardSize::Items)
+                        .map_err(|_| format!("shard size `{value}` is too large")),
+                };
+            }
+            "b" => 1,
+            "kb" | "k" => 1_000,
+            "mb" | "m" => 1_000_000,
+            "gb" | "g" => 1_000_000_000,
+            "kib" => 1 << 10,
+            "mib" => 1 << 20,
+            "gib" => 1 << 30,
+            other => return Err(format!("unknown shard size unit `{other}`")),
+        };
+        match number.checked_mul(multiplier) {
+            Some(0) => Err("shard size must be positive".to_string()),
+            Some(bytes) => Ok(ShardSize::Bytes(bytes)),
+            None => Err(format!("shard size `{value}` is too large")),
+        }
+    }
+}
+
+/// One finished shard, as listed in the manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardInfo {
+    /// File name, relative to the manifest
+    pub path: String,
+    pub items: usize,
+    pub bytes: u64,
+    /// BLAKE3 of the shard file as written, compression included
+    pub blake3: String,
+}
+
+/// Description of a sharded dataset, written next to the shards
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardManifest {
+    pub format: String,
+    pub compression: Compression,
+    pub total_items: usize,
+    pub shards: Vec<ShardInfo>,
+}
+
+/// Passes bytes through to a file while counting and hashing them
+struct HashingWriter {
+    file: File,
+    hasher: blake3::Hasher,
+    bytes: u64,
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Optional compression in front of a writer
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::None => Encoder::Plain(writer),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+            }
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, 0)?),
+        })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match self {
+            Encoder::Plain(writer) => writer,
+            Encoder::Gzip(encoder) => encoder.get_ref(),
+            Encoder::Zstd(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// Write the end of the compressed stream and return the writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(writer) => Ok(writer),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+struct OpenShard {
+    writer: JsonlWriter<Encoder<HashingWriter>>,
+    path: PathBuf,
+    items: usize,
+}
+
+/// Writes compressed and/or sharded JSONL
+///
+/// Without a shard size, a single file is written at the output path.
+/// With one, shards are named `<name>-00000-of-000NN.jsonl[.gz|.zst]` next
+/// to the output path, where `<name>` is its file name without extensions,
+/// and `<name>.manifest.json` lists them. Shards are written under a
+/// temporary name and renamed on `finish`, once their total is known.
+pub struct ShardedJsonlWriter {
+    output: PathBuf,
+    compression: Compression,
+    shard_size: Option<ShardSize>,
+    current: Option<OpenShard>,
+    shards: Vec<ShardInfo>,
+    finished: bool,
+}
+
+impl ShardedJsonlWriter {
+    pub fn create(
+        output: &Path,
+        compression: Compression,
+        shard_size: Option<ShardSize>,
+    ) -> CoreResult<Self> {
+        Ok(Self {
+            output: output.to_path_buf(),
+            compression,
+            shard_size,
+            current: None,
+            shards: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// Shards written so far
+    pub fn shards(&self) -> &[ShardInfo] {
+        &self.shards
+    }
+
+    /// Path of the manifest written for a sharded `output`
+    pub fn manifest_path(output: &Path) -> PathBuf {
+        output.with_file_name(format!("{}.manifest.json", shard_name(output)))
+    }
+
+    fn name(&self) -> String {
+        shard_name(&self.output)
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.compression {
+            Compression::None => "jsonl",
+            Compression::Gzip => "jsonl.gz",
+            Compression::Zstd => "jsonl.zst",
+        }
+    }
+
+    fn sibling(&self, file_name: String) -> PathBuf {
+        self.output.with_file_name(file_name)
+    }
+
+    fn open_shard(&self) -> CoreResult<OpenShard> {
+        let path = match self.shard_size {
+            None => self.output.clone(),
+            Some(_) => self.sibling(format!(
+                "{}-{:05}.{}.partial",
+                self.name(),
+                self.shards.len(),
+                self.extension()
+            )),
+        };
+        let file = HashingWriter {
+            file: File::create(&path)?,
+            hasher: blake3::Hasher::new(),
+            bytes: 0,
+        };
+        Ok(OpenShard {
+            writer: JsonlWriter::new(Encoder::new(file, self.compression)?),
+            path,
+            items: 0,
+        })
+    }
+
+    fn close_shard(&mut self) -> CoreResult<()> {
+        let Some(shard) = self.current.take() else {
+            return Ok(());
+        };
+        let file = shard.writer.into_inner()?.finish()?;
+        self.shards.push(ShardInfo {
+            path: shard.path.to_string_lossy().into_owned(),
+            items: shard.items,
+            bytes: file.bytes,
+            blake3: format!("blake3:{}", file.hasher.finalize().to_hex()),
+        });
+        Ok(())
+    }
+
+    /// Give shards their final names and write the manifest
+    fn write_manifest(&mut self) -> CoreResult<()> {
+        let name = self.name();
+        let extension = self.extension();
+        let total = self.shards.len();
+        for (index, shard) in self.shards.iter_mut().enumerate() {
+            let file_name = format!("{name}-{index:05}-of-{total:05}.{extension}");
+            std::fs::rename(&shard.path, self.output.with_file_name(&file_name))?;
+            shard.path = file_name;
+        }
+        self.remove_stale_shards()?;
+
+        let manifest = ShardManifest {
+            format: "jsonl".to_string(),
+            compression: self.compression,
+            total_items: self.shards.iter().map(|shard| shard.items).sum(),
+            shards: self.shards.clone(),
+        };
+        let mut file = BufWriter::new(File::create(Self::manifest_path(&self.output))?);
+        serde_json::to_writer_pretty(&mut file, &manifest)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Remove the shards of an earlier run, with another shard count or
+    /// compression, so that a glob over `<name>-*` only finds this one's
+    fn remove_stale_shards(&self) -> CoreResult<()> {
+        let dir = match self.output.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = self.name();
+        for entry in std::fs::read_dir(dir)? {
+            let file_name = entry?.file_name().to_string_lossy().into_owned();
+            if is_shard_of(&file_name, &name)
+                && !self.shards.iter().any(|shard| shard.path == file_name)
+            {
+                std::fs::remove_file(dir.join(&file_name))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `file_name` is named like a shard of `name`,
+/// `<name>-00000-of-00000.jsonl[.gz|.zst]`
+fn is_shard_of(file_name: &str, name: &str) -> bool {
+    let Some(rest) = file_name
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('-'))
+    else {
+        return false;
+    };
+    let Some((numbers, extension)) = rest.split_once('.') else {
+        return false;
+    };
+    let is_index = |part: &str| part.len() == 5 && part.bytes().all(|b| b.is_ascii_digit());
+    matches!(numbers.split_once("-of-"), Some((index, total)) if is_index(index) && is_index(total))
+        && matches!(extension, "jsonl" | "jsonl.gz" | "jsonl.zst")
+}
+
+/// Reads the items of datasets written by the JSONL sinks
+///
+/// Each input is a JSONL file, compressed or not (see `JsonlReader::open`),
+/// or a shard manifest (`*.manifest.json`) whose shards are read in order.
+/// Inputs are read one after the other and opened only when reached, so
+/// only one file is open at a time.
+pub struct DatasetReader {
+    files: std::vec::IntoIter<PathBuf>,
+    current: Option<JsonlReader<Box<dyn BufRead>>>,
+}
+
+impl DatasetReader {
+    /// Expand the manifests among `inputs` and check that every file exists
+    pub fn open(inputs: &[PathBuf]) -> CoreResult<Self> {
+        let mut files = Vec::new();
+        for input in inputs {
+            let is_manifest = input
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().ends_with(".manifest.json"));
+            if is_manifest {
+                let manifest: ShardManifest =
+                    serde_json::from_reader(BufReader::new(File::open(input)?))?;
+                files.extend(
+                    manifest
+                        .shards
+                        .iter()
+                        .map(|shard| input.with_file_name(&shard.path)),
+                );
+            } else {
+                files.push(input.clone());
+            }
+        }
+        for file in &files {
+            std::fs::metadata(file)
+                .map_err(|e| CoreError::InvalidInput(format!("{}: {}", file.display(), e)))?;
+        }
+
+        Ok(Self {
+            files: files.into_iter(),
+            current: None,
+        })
+    }
+}
+
+impl Iterator for DatasetReader {
+    type Item = CoreResult<ExtractedItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(reader) = &mut self.current {
+                match reader.next() {
+                    // A file that cannot be read (or decompressed) further
+                    // is given up on; a malformed line is only skipped
+                    Some(Err(CoreError::Io(e))) => {
+                        self.current = None;
+                        return Some(Err(CoreError::Io(e)));
+                    }
+                    Some(item) => return Some(item),
+                    None => self.current = None,
+                }
+            }
+            let file = self.files.next()?;
+            match JsonlReader::open(&file) {
+                Ok(reader) => self.current = Some(reader),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// File name of `output` without `.jsonl` and compression extensions
+fn shard_name(output: &Path) -> String {
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "dataset".to_string());
+    let mut name = file_name.as_str();
+    for extension in [".gz", ".zst", ".jsonl"] {
+        name = name.strip_suffix(extension).unwrap_or(name);
+    }
+    name.to_string()
+}
+
+impl ItemSink for ShardedJsonlWriter {
+    fn write_item(&mut self, item: &ExtractedItem) -> CoreResult<()> {
+        if self.current.is_none() {
+            self.current = Some(self.open_shard()?);
+        }
+        let shard = self.current.as_mut().expect("shard opened above");
+        shard.writer.write_item(item)?;
+        shard.items += 1;
+
+        let full = match self.shard_size {
+            None => false,
+            Some(ShardSize::Items(items)) => shard.items >= items,
+            Some(ShardSize::Bytes(bytes)) => shard.writer.get_ref().get_ref().bytes >= bytes,
+        };
+        if full {
+            self.close_shard()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> CoreResult<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        // An empty dataset still gets its (single, empty) output file
+        if self.current.is_none() && self.shard_size.is_none() {
+            self.current = Some(self.open_shard()?);
+        }
+        self.close_shard()?;
+
+        if self.shard_size.is_some() {
+            self.write_manifest()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::JsonlReader;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_shard_size_parsing() {
+        assert_eq!("1000".parse(), Ok(ShardSize::Items(1000)));
+        assert_eq!("256MB".parse(), Ok(ShardSize::Bytes(256_000_000)));
+        assert_eq!("1 GiB".parse(), Ok(ShardSize::Bytes(1 << 30)));
+        assert!("0".parse::<ShardSize>().is_err());
+        assert!("12 parsecs".parse::<ShardSize>().is_err());
+        assert_eq!(
+            "20000000000gb".parse::<ShardSize>(),
+            Err("shard size `20000000000gb` is too large".to_string())
+        );
+    }
+
+    #[test]
+    fn test_shards_and_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("data.jsonl");
+        let mut writer =
+            ShardedJsonlWriter::create(&output, Compression::Zstd, Some(ShardSize::Items(2)))
+                .unwrap();
+        for index in 0..5 {
+            let item = ExtractedItem {
+                item_meta: ItemMeta {
+                    id: index.to_string(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            writer.write_item(&item).unwrap();
+        }
+        for stale in [
+            "data-00003-of-00004.jsonl.zst",
+            "data-00000-of-00001.jsonl.gz",
+            "data-notes.jsonl",
+        ] {
+            std::fs::write(dir.path().join(stale), "").unwrap();
+        }
+        writer.finish().unwrap();
+        assert!(!dir.path().join("data-00003-of-00004.jsonl.zst").exists());
+        assert!(!dir.path().join("data-00000-of-00001.jsonl.gz").exists());
+        assert!(dir.path().join("data-notes.jsonl").exists());
+
+        let manifest: ShardManifest =
+            serde_json::from_reader(File::open(dir.path().join("data.manifest.json")).unwrap())
+                .unwrap();
+        assert_eq!(manifest.total_items, 5);
+        let names: Vec<&str> = manifest.shards.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "data-00000-of-00003.jsonl.zst",
+                "data-00001-of-00003.jsonl.zst",
+                "data-00002-of-00003.jsonl.zst",
+            ]
+        );
+
+        let mut ids = Vec::new();
+        for shard in &manifest.shards {
+            let bytes = std::fs::read(dir.path().join(&shard.path)).unwrap();
+            assert_eq!(bytes.len() as u64, shard.bytes);
+            assert_eq!(
+                shard.blake3,
+                format!("blake3:{}", blake3::hash(&bytes).to_hex())
+            );
+
+            let decoder = zstd::Decoder::new(bytes.as_slice()).unwrap();
+            for item in JsonlReader::new(BufReader::new(decoder)) {
+                ids.push(item.unwrap().item_meta.id);
+            }
+        }
+        assert_eq!(ids, ["0", "1", "2", "3", "4"]);
+
+        // Manifests and compressed files read back like plain JSONL
+        let single = dir.path().join("more.jsonl.gz");
+        let mut writer = ShardedJsonlWriter::create(&single, Compression::Gzip, None).unwrap();
+        writer.write_item(&ExtractedItem::default()).unwrap();
+        writer.finish().unwrap();
+        let inputs = [dir.path().join("data.manifest.json"), single];
+        let read: Vec<String> = DatasetReader::open(&inputs)
+            .unwrap()
+            .map(|item| item.unwrap().item_meta.id)
+            .collect();
+        assert_eq!(read, ["0", "1", "2", "3", "4", ""]);
+        assert!(DatasetReader::open(&[dir.path().join("missing.jsonl")]).is_err());
+    }
+}
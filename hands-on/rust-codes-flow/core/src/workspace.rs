@@ -0,0 +1,595 @@
+use crate::discovery::{normalize, walk};
+use crate::license::{NOASSERTION, identify_license_text, normalize_license};
+use crate::models::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use syn::Item;
+use toml::{Table, Value};
+
+/// Crate and module a source file belongs to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileModule {
+    pub crate_name: String,
+    pub crate_version: Option<String>,
+    /// `None` when no target's module tree reaches the file
+    pub target_kind: Option<TargetKind>,
+    pub edition: String,
+    /// Module path starting with the crate name, e.g. `["serde_json", "de"]`
+    pub module_path: Vec<String>,
+    /// License of the package, if its manifest declares one
+    pub license: Option<String>,
+}
+
+impl FileModule {
+    /// Fill the crate fields of an item context
+    pub fn apply(&self, context: &mut ProjectContext) {
+        context.crate_name = Some(self.crate_name.clone());
+        context.crate_version = self.crate_version.clone();
+        context.target_kind = self.target_kind;
+        context.edition = Some(self.edition.clone());
+        context.module_path = Some(self.module_path.join("::"));
+        if self.license.is_some() {
+            context.license = self.license.clone();
+        }
+    }
+}
+
+/// A package found from a `Cargo.toml` with a `[package]` table
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    pub version: Option<String>,
+    pub edition: String,
+    /// SPDX expression from `license`, or identified from `license-file`
+    pub license: Option<String>,
+    /// Directory holding the manifest
+    pub root: PathBuf,
+    pub targets: Vec<Target>,
+}
+
+/// A compilation target of a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// Crate name, i.e. the target name with `-` replaced by `_`
+    pub name: String,
+    pub kind: TargetKind,
+    /// Crate root source file
+    pub root: PathBuf,
+}
+
+/// Maps the source files of a repository to their crate and module
+///
+/// Built from every `Cargo.toml` under the root: targets are resolved the
+/// way Cargo does (explicit `[lib]`, `[[bin]]`, ... tables and the
+/// `src/main.rs`, `src/bin/`, `tests/`, `benches/`, `examples/`
+/// conventions), then each target's module tree is followed through
+/// `mod` declarations, honoring `#[path]` attributes. Manifests that cannot
+/// be read or parsed (some repositories ship broken ones as test fixtures)
+/// are skipped and listed in `skipped_manifests`.
+#[derive(Debug, Default)]
+pub struct WorkspaceIndex {
+    packages: Vec<Package>,
+    files: HashMap<PathBuf, FileModule>,
+    skipped: Vec<(PathBuf, String)>,
+}
+
+impl WorkspaceIndex {
+    pub fn discover(root: &Path) -> CoreResult<Self> {
+        let mut manifests = Vec::new();
+        for entry in walk(root) {
+            let entry = entry.map_err(|e| CoreError::InvalidInput(e.to_string()))?;
+            if entry.file_name() == "Cargo.toml" && entry.file_type().is_some_and(|t| t.is_file()) {
+                manifests.push(normalize(entry.path()));
+            }
+        }
+        manifests.sort();
+
+        let mut index = WorkspaceIndex::default();
+        let mut parsed = HashMap::new();
+        for manifest in &manifests {
+            let table = std::fs::read_to_string(manifest)
+                .map_err(|e| e.to_string())
+                .and_then(|source| toml::from_str::<Table>(&source).map_err(|e| e.to_string()));
+            match table {
+                Ok(table) => {
+                    parsed.insert(manifest.clone(), table);
+                }
+                Err(e) => index.skipped.push((manifest.clone(), e)),
+            }
+        }
+
+        for manifest in &manifests {
+            if let Some(package) = read_package(manifest, &parsed) {
+                index.packages.push(package);
+            }
+        }
+
+        for package in &index.packages {
+            let mut visited = HashSet::new();
+            for target in &package.targets {
+                let module = FileModule {
+                    crate_name: target.name.clone(),
+                    crate_version: package.version.clone(),
+                    target_kind: Some(target.kind),
+                    edition: package.edition.clone(),
+                    module_path: vec![target.name.clone()],
+                    license: package.license.clone(),
+                };
+                walk_module(&target.root, true, module, &mut index.files, &mut visited);
+            }
+        }
+        // Module trees are parsed for their declarations only; no span is
+        // kept, so the thread's span source map need not hold the sources
+        proc_macro2::extra::invalidate_current_thread_spans();
+
+        Ok(index)
+    }
+
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    /// Manifests that could not be read or parsed, with the reason
+    pub fn skipped_manifests(&self) -> &[(PathBuf, String)] {
+        &self.skipped
+    }
+
+    /// Crate and module of a file
+    ///
+    /// Files that no target reaches (e.g. behind a `cfg`'d macro) but that
+    /// live under a package's `src/` get a module path derived from their
+    /// location and no target kind.
+    pub fn lookup(&self, file: &Path) -> Option<FileModule> {
+        let file = normalize(file);
+        if let Some(module) = self.files.get(&file) {
+            return Some(module.clone());
+        }
+
+        let package = self
+            .packages
+            .iter()
+            .filter(|package| file.starts_with(&package.root))
+            .max_by_key(|package| package.root.components().count())?;
+        let crate_name = package
+            .targets
+            .iter()
+            .find(|target| target.kind == TargetKind::Lib)
+            .map(|target| target.name.clone())
+            .unwrap_or_else(|| crate_name(&package.name));
+
+        let mut module_path = vec![crate_name.clone()];
+        if let Ok(relative) = file.strip_prefix(package.root.join("src")) {
+            let relative = relative.with_extension("");
+            let segments: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            let last = segments.len().saturating_sub(1);
+            for (position, segment) in segments.into_iter().enumerate() {
+                let is_root = position == 0 && (segment == "lib" || segment == "main");
+                if !(position == last && (segment == "mod" || is_root)) {
+                    module_path.push(segment);
+                }
+            }
+        }
+
+        Some(FileModule {
+            crate_name,
+            crate_version: package.version.clone(),
+            target_kind: None,
+            edition: package.edition.clone(),
+            module_path,
+            license: package.license.clone(),
+        })
+    }
+}
+
+fn crate_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Read `[package]` and the targets of one manifest
+fn read_package(manifest: &Path, parsed: &HashMap<PathBuf, Table>) -> Option<Package> {
+    let table = parsed.get(manifest)?;
+    let package = table.get("package")?.as_table()?;
+    let root = manifest.parent().unwrap_or(Path::new("")).to_path_buf();
+    let name = package.get("name")?.as_str()?.to_string();
+
+    // `key.workspace = true` takes the value from `[workspace.package]` of
+    // the closest enclosing workspace manifest
+    let workspace_package = root
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .filter_map(|path| parsed.get(&normalize(&path)))
+        .find_map(|table| table.get("workspace"))
+        .and_then(|workspace| workspace.get("package"))
+        .and_then(Value::as_table);
+    let field = |key: &str| -> Option<String> {
+        match package.get(key)? {
+            Value::String(value) => Some(value.clone()),
+            Value::Table(inherit) if inherit.get("workspace")?.as_bool()? => {
+                workspace_package?.get(key)?.as_str().map(str::to_string)
+            }
+            _ => None,
+        }
+    };
+
+    let version = field("version");
+    let edition = field("edition").unwrap_or_else(|| "2015".to_string());
+    // Unrecognized `license` strings are kept as written
+    let license = match field("license") {
+        Some(license) => Some(normalize_license(&license).unwrap_or(license)),
+        None => field("license-file").map(|path| {
+            std::fs::read_to_string(root.join(path))
+                .ok()
+                .and_then(|text| identify_license_text(&text))
+                .unwrap_or(NOASSERTION)
+                .to_string()
+        }),
+    };
+    let targets = read_targets(&root, &name, table);
+
+    Some(Package {
+        name,
+        version,
+        edition,
+        license,
+        root,
+        targets,
+    })
+}
+
+/// Targets in the order files are attributed to them: lib, bins, build
+/// script, then tests, benches and examples
+fn read_targets(root: &Path, package_name: &str, table: &Table) -> Vec<Target> {
+    let mut targets = Vec::new();
+    let package = table.get("package").and_then(Value::as_table);
+    let auto = |key: &str| {
+        package
+            .and_then(|package| package.get(key))
+            .and_then(Value::as_bool)
+            .unwrap_or(true)
+    };
+
+    let lib = table.get("lib").and_then(Value::as_table);
+    let lib_path = lib
+        .and_then(|lib| lib.get("path"))
+        .and_then(Value::as_str)
+        .map(|path| root.join(path))
+        .unwrap_or_else(|| root.join("src/lib.rs"));
+    if lib.is_some() || lib_path.is_file() {
+        let name = lib
+            .and_then(|lib| lib.get("name"))
+            .and_then(Value::as_str)
+            .unwrap_or(package_name);
+        targets.push(Target {
+            name: crate_name(name),
+            kind: TargetKind::Lib,
+            root: normalize(&lib_path),
+        });
+    }
+
+    let sections = [
+        ("bin", "autobins", TargetKind::Bin, "src/bin"),
+        ("test", "autotests", TargetKind::Test, "tests"),
+        ("bench", "autobenches", TargetKind::Bench, "benches"),
+        ("example", "autoexamples", TargetKind::Example, "examples"),
+    ];
+    for (section, auto_key, kind, dir) in sections {
+        let mut section_targets = Vec::new();
+
+        for entry in table
+            .get(section)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_table)
+        {
+            let Some(name) = entry.get("name").and_then(Value::as_str) else {
+                continue;
+            };
+            let path = match entry.get("path").and_then(Value::as_str) {
+                Some(path) => root.join(path),
+                None if kind == TargetKind::Bin && name == package_name => root.join("src/main.rs"),
+                None => {
+                    let file = root.join(dir).join(format!("{name}.rs"));
+                    if file.is_file() {
+                        file
+                    } else {
+                        root.join(dir).join(name).join("main.rs")
+                    }
+                }
+            };
+            section_targets.push(Target {
+                name: crate_name(name),
+                kind,
+                root: normalize(&path),
+            });
+        }
+
+        if auto(auto_key) {
+            let mut discovered = Vec::new();
+            if kind == TargetKind::Bin && root.join("src/main.rs").is_file() {
+                discovered.push((package_name.to_string(), root.join("src/main.rs")));
+            }
+            discovered.extend(conventional_targets(&root.join(dir)));
+            for (name, path) in discovered {
+                let path = normalize(&path);
+                if !section_targets.iter().any(|target| target.root == path) {
+                    section_targets.push(Target {
+                        name: crate_name(&name),
+                        kind,
+                        root: path,
+                    });
+                }
+            }
+        }
+
+        targets.extend(section_targets);
+        if kind == TargetKind::Bin {
+            let build = match package.and_then(|package| package.get("build")) {
+                Some(Value::String(path)) => Some(root.join(path)),
+                Some(Value::Boolean(false)) => None,
+                _ => Some(root.join("build.rs")).filter(|path| path.is_file()),
+            };
+            if let Some(build) = build {
+                targets.push(Target {
+                    name: "build_script_build".to_string(),
+                    kind: TargetKind::BuildScript,
+                    root: normalize(&build),
+                });
+            }
+        }
+    }
+
+    targets
+}
+
+/// `dir/*.rs` and `dir/*/main.rs`, named after the file or directory
+fn conventional_targets(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut targets: Vec<(String, PathBuf)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "rs") {
+                Some((name, path))
+            } else if path.join("main.rs").is_file() {
+                Some((name, path.join("main.rs")))
+            } else {
+                None
+            }
+        })
+        .collect();
+    targets.sort();
+    targets
+}
+
+/// Record `file` as `module` and follow its `mod` declarations
+///
+/// `owns_directory` is true for crate roots, `mod.rs` files and files
+/// loaded through `#[path]`: their submodules live next to them rather
+/// than in a directory named after the module.
+fn walk_module(
+    file: &Path,
+    owns_directory: bool,
+    module: FileModule,
+    files: &mut HashMap<PathBuf, FileModule>,
+    visited: &mut HashSet<PathBuf>,
+) {
+    let file = normalize(file);
+    if !visited.insert(file.clone()) {
+        return;
+    }
+    let Ok(source) = std::fs::read_to_string(&file) else {
+        return;
+    };
+    let Ok(parsed) = syn::parse_file(&source) else {
+        return;
+    };
+
+    files.entry(file.clone()).or_insert_with(|| module.clone());
+
+    let file_dir = file.parent().unwrap_or(Path::new("")).to_path_buf();
+    let module_dir = if owns_directory {
+        file_dir.clone()
+    } else {
+        file_dir.join(file.file_stem().unwrap_or_default())
+    };
+
+    let mut declarations = Vec::new();
+    collect_declarations(
+        &parsed.items,
+        &file_dir,
+        &module_dir,
+        true,
+        &[],
+        &mut declarations,
+    );
+
+    for (path, owns_directory, segments) in declarations {
+        let mut child = module.clone();
+        child.module_path.extend(segments);
+        walk_module(&path, owns_directory, child, files, visited);
+    }
+}
+
+/// Resolve the files of `mod name;` declarations, descending into inline
+/// modules, as `(file, owns_directory, module segments)`
+fn collect_declarations(
+    items: &[Item],
+    file_dir: &Path,
+    module_dir: &Path,
+    top_level: bool,
+    inline: &[String],
+    out: &mut Vec<(PathBuf, bool, Vec<String>)>,
+) {
+    for item in items {
+        let Item::Mod(item_mod) = item else {
+            continue;
+        };
+        let name = item_mod.ident.to_string();
+        let name = name.strip_prefix("r#").unwrap_or(&name).to_string();
+        let path_attr = path_attribute(&item_mod.attrs);
+        let mut segments = inline.to_vec();
+        segments.push(name.clone());
+
+        match &item_mod.content {
+            Some((_, inner)) => {
+                let inner_dir = match &path_attr {
+                    Some(path) => module_dir.join(path),
+                    None => module_dir.join(&name),
+                };
+                collect_declarations(inner, file_dir, &inner_dir, false, &segments, out);
+            }
+            None => {
+                let resolved = match path_attr {
+                    // Outside inline modules `#[path]` is relative to the
+                    // directory of the declaring file
+                    Some(path) if top_level => Some((file_dir.join(path), true)),
+                    Some(path) => Some((module_dir.join(path), true)),
+                    None => {
+                        let flat = module_dir.join(format!("{name}.rs"));
+                        let nested = module_dir.join(&name).join("mod.rs");
+                        if flat.is_file() {
+                            Some((flat, false))
+                        } else if nested.is_file() {
+                            Some((nested, true))
+                        } else {
+                            None
+                        }
+                    }
+                };
+                if let Some((path, owns_directory)) = resolved {
+                    out.push((path, owns_directory, segments));
+                }
+            }
+        }
+    }
+}
+
+/// Value of a `#[path = "..."]` attribute
+fn path_attribute(attrs: &[syn::Attribute]) -> Option<String> {
+    attrs.iter().find_map(|attr| match &attr.meta {
+        syn::Meta::NameValue(meta) if meta.path.is_ident("path") => match &meta.value {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(path),
+                ..
+            }) => Some(path.value()),
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_workspace_targets_and_module_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        write(
+            root,
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"my-lib\"]\n[workspace.package]\nversion = \"1.2.3\"\nedition = \"2021\"\n",
+        );
+        write(
+            root,
+            "my-lib/Cargo.toml",
+            "[package]\nname = \"my-lib\"\nversion.workspace = true\nedition.workspace = true\nlicense = \"MIT/Apache-2.0\"\n",
+        );
+        write(
+            root,
+            "my-lib/src/lib.rs",
+            "mod foo;\n#[path = \"other/odd.rs\"]\nmod renamed;\nmod inline {\n    mod deep;\n}\n",
+        );
+        write(root, "my-lib/src/foo.rs", "pub mod bar;");
+        write(root, "my-lib/src/foo/bar.rs", "pub fn f() {}");
+        write(root, "my-lib/src/other/odd.rs", "mod child;");
+        write(root, "my-lib/src/other/child.rs", "");
+        write(root, "my-lib/src/inline/deep.rs", "");
+        write(root, "my-lib/src/orphan/mod.rs", "");
+        write(root, "my-lib/src/main.rs", "fn main() {}");
+        write(root, "my-lib/tests/it.rs", "");
+        write(
+            root,
+            "my-lib/tests/fixtures/broken/Cargo.toml",
+            "[package\nname = ",
+        );
+        // Nothing under `.git` is a manifest of the repository
+        write(root, ".git/modules/sub/Cargo.toml", "[package\nname = ");
+
+        let index = WorkspaceIndex::discover(root).unwrap();
+        let skipped: Vec<&Path> = index
+            .skipped_manifests()
+            .iter()
+            .map(|(manifest, _)| manifest.as_path())
+            .collect();
+        assert_eq!(
+            skipped,
+            [normalize(
+                &root.join("my-lib/tests/fixtures/broken/Cargo.toml")
+            )]
+        );
+        assert_eq!(index.packages().len(), 1);
+        let package = &index.packages()[0];
+        assert_eq!(package.version.as_deref(), Some("1.2.3"));
+        assert_eq!(package.edition, "2021");
+        assert_eq!(package.license.as_deref(), Some("MIT OR Apache-2.0"));
+        let targets: Vec<(&str, TargetKind)> = package
+            .targets
+            .iter()
+            .map(|target| (target.name.as_str(), target.kind))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                ("my_lib", TargetKind::Lib),
+                ("my_lib", TargetKind::Bin),
+                ("it", TargetKind::Test),
+            ]
+        );
+
+        let module = |path: &str| {
+            let module = index.lookup(&root.join(path)).unwrap();
+            (module.module_path.join("::"), module.target_kind)
+        };
+        let lib = Some(TargetKind::Lib);
+        assert_eq!(module("my-lib/src/lib.rs"), ("my_lib".to_string(), lib));
+        assert_eq!(
+            module("my-lib/src/foo/bar.rs"),
+            ("my_lib::foo::bar".to_string(), lib)
+        );
+        assert_eq!(
+            module("my-lib/src/other/odd.rs"),
+            ("my_lib::renamed".to_string(), lib)
+        );
+        assert_eq!(
+            module("my-lib/src/other/child.rs"),
+            ("my_lib::renamed::child".to_string(), lib)
+        );
+        assert_eq!(
+            module("my-lib/src/inline/deep.rs"),
+            ("my_lib::inline::deep".to_string(), lib)
+        );
+        assert_eq!(
+            module("my-lib/src/orphan/mod.rs"),
+            ("my_lib::orphan".to_string(), None)
+        );
+        assert_eq!(
+            module("my-lib/tests/it.rs"),
+            ("it".to_string(), Some(TargetKind::Test))
+        );
+    }
+}